@@ -4,14 +4,17 @@
 mod decoding;
 mod query_context;
 
-use common::{upos, utok};
-use digit_layout::types::U32;
+use common::{f16, upos, utok};
+use digit_layout::{
+    types::{F16, F32, U32},
+    DigitLayout,
+};
 use std::path::Path;
 use tensor::{udim, Tensor};
 
 pub use decoding::DecodingMeta;
 pub use query_context::QueryContext;
-pub use sample::SampleArgs;
+pub use sample::{log_softmax, MirostatState, SampleArgs, SampleArgsError, SamplingStrategy};
 
 /// 从文件系统加载的模型。
 pub trait Model: Sized {
@@ -33,6 +36,7 @@ pub trait Model: Sized {
 /// - 对词嵌入计算前向传播（[`forward`](CausalLM::forward)）；
 /// - 解码词嵌入张量得到概率密度（[`decode`](CausalLM::decode)）；
 /// - 采样概率密度（[`sample`](CausalLM::sample)）；
+/// - 计算对数概率（[`log_softmax`](CausalLM::log_softmax)，可选）；
 ///
 /// 这种定义根据计算的形式和特性将“一轮”推理分割为多个部分，方便灵活地实现调度。
 /// 为了在推理的不同阶段之间传递巨大的张量，需要 [`Storage`](CausalLM::Storage) 类型来约定中间变量的存储方式。
@@ -79,6 +83,39 @@ pub trait CausalLM: Model {
         args: impl IntoIterator<Item = SampleMeta>,
         logits: Tensor<Self::Storage>,
     ) -> Vec<utok>;
+    /// 计算 logits 每一行的对数概率，拷贝到主机（`num_decoding_tokens x vocab_size`）。
+    ///
+    /// 用于束搜索等需要比较多个候选词的解码方式。不支持的模型返回 `None`，此时束搜索退化为普通采样。
+    /// 能访问主机上的 logits 的实现可以直接对每一行调用 [`log_softmax`](crate::log_softmax)。
+    #[inline]
+    fn log_softmax(&self, _logits: Tensor<Self::Storage>) -> Option<Vec<Vec<f32>>> {
+        None
+    }
+
+    /// 以空白缓存对 `tokens` 执行词嵌入和 Transformer 计算，返回最后一层的隐藏状态（`num_tokens x hidden_size`），不经过输出头。
+    ///
+    /// 用于提取词嵌入，[`decode`](CausalLM::decode) 这个隐藏状态即可得到完整推理的 logits。
+    fn forward_without_head(&self, tokens: &[utok]) -> Tensor<Self::Storage> {
+        let mut cache = self.new_cache();
+        let token_embedded = self.token_embed(tokens.iter().copied());
+        let queries = [QueryContext {
+            cache: Some(&mut cache),
+            range: 0..tokens.len() as upos,
+        }];
+        self.forward(queries, token_embedded)
+    }
+
+    /// 模型所在设备的空闲和总内存字节数，无法获取时返回 `None`。
+    #[inline]
+    fn memory_info(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// 按 `meta` 加载模型之前，目标设备的空闲和总内存字节数，无法获取时返回 `None`。
+    #[inline]
+    fn device_memory_info(_meta: &Self::Meta) -> Option<(usize, usize)> {
+        None
+    }
 }
 
 /// 解码的要求。
@@ -87,21 +124,66 @@ pub struct SampleMeta {
     pub num_decode: usize,
     /// 采样参数。
     pub args: SampleArgs,
+    /// 上下文中已有的词，用于重复惩罚，不惩罚时可以为空。
+    pub history: Vec<utok>,
+    /// Mirostat 采样的状态，不使用 Mirostat 采样时可以为默认值。
+    pub mirostat: MirostatState,
 }
 
 /// 生成位置张量。
+///
+/// 每个 token 对应一个绝对位置，RoPE 算子以相邻元素 `(x[2i], x[2i+1])` 成对旋转。
+/// 总长度由 [`pos_batch`] 计算，不再需要 `_nt_hint`。
 #[inline]
 pub fn pos<'a, S: 'a>(
     queries: impl IntoIterator<Item = &'a QueryContext<'a, S>>,
-    nt_hint: udim,
+    _nt_hint: udim,
 ) -> Tensor<Vec<upos>> {
-    let mut ans = Vec::with_capacity(nt_hint as usize);
+    let (mut lengths, mut offsets) = (Vec::new(), Vec::new());
     for query in queries {
-        ans.extend(query.range.clone());
+        lengths.push(query.range.len() as upos);
+        offsets.push(query.range.start);
+    }
+    pos_batch(&lengths, &offsets)
+}
+
+/// 按每个序列的长度和起始位置生成位置张量。
+///
+/// 第 `i` 个序列的位置为 `offsets[i]..offsets[i] + lengths[i]`，所有序列的位置首尾相接，与 [`pos`] 的布局相同。
+pub fn pos_batch(lengths: &[upos], offsets: &[upos]) -> Tensor<Vec<upos>> {
+    assert_eq!(lengths.len(), offsets.len());
+    let mut ans = Vec::with_capacity(lengths.iter().sum::<upos>() as _);
+    for (&len, &offset) in std::iter::zip(lengths, offsets) {
+        ans.extend(offset..offset + len);
     }
     Tensor::new(U32, &[ans.len() as _], ans)
 }
 
+/// 生成正弦位置编码张量（`seq_len x d_model`）。
+///
+/// 偶数维为 `sin(pos / 10000^(2i/d_model))`，奇数维为对应的 `cos`。支持 F32 和 F16。
+pub fn sinusoidal_pos(seq_len: udim, d_model: udim, dt: DigitLayout) -> Tensor<Vec<u8>> {
+    assert_eq!(d_model % 2, 0);
+    // 以 exp(-2i * ln(10000) / d_model) 计算频率，避免直接求幂
+    let scale = -10000f32.ln() / d_model as f32;
+    let values = (0..seq_len).flat_map(|pos| {
+        (0..d_model).map(move |j| {
+            let angle = pos as f32 * ((j & !1) as f32 * scale).exp();
+            if j % 2 == 0 {
+                angle.sin()
+            } else {
+                angle.cos()
+            }
+        })
+    });
+    let shape = [seq_len, d_model];
+    match dt {
+        F32 => Tensor::from_slice(F32, &shape, &values.collect::<Vec<_>>()),
+        F16 => Tensor::from_slice(F16, &shape, &values.map(f16::from_f32).collect::<Vec<_>>()),
+        _ => panic!("unsupported dtype {dt}"),
+    }
+}
+
 /// 测试模型实现。
 pub fn test_impl<M>(meta: M::Meta, prompt: &[utok])
 where
@@ -143,6 +225,8 @@ where
         let args = [SampleMeta {
             num_decode: 1,
             args: SampleArgs::default(),
+            history: vec![],
+            mirostat: Default::default(),
         }];
         let tokens = CausalLM::sample(&model, args, logits);
 
@@ -151,3 +235,45 @@ where
         prompt = tokens;
     }
 }
+
+#[test]
+fn test_pos_batch() {
+    let batch = pos_batch(&[3, 2], &[5, 10]);
+    assert_eq!(batch.shape(), &[5]);
+    assert_eq!(batch.take_physical(), [5, 6, 7, 10, 11]);
+
+    let queries = [
+        QueryContext::<()> {
+            cache: None,
+            range: 5..8,
+        },
+        QueryContext {
+            cache: None,
+            range: 10..12,
+        },
+    ];
+    assert_eq!(pos(&queries, 5).take_physical(), [5, 6, 7, 10, 11]);
+}
+
+#[test]
+fn test_sinusoidal_pos() {
+    // numpy: pe[:, 0::2] = sin(pos * div), pe[:, 1::2] = cos(pos * div),
+    //        div = exp(arange(0, 4, 2) * -(log(10000) / 4))
+    const EXPECTED: [[f32; 4]; 3] = [
+        [0., 1., 0., 1.],
+        [0.841471, 0.540302, 0.0099998, 0.99995],
+        [0.909297, -0.416147, 0.0199987, 0.9998],
+    ];
+
+    let pe = sinusoidal_pos(3, 4, F32);
+    assert_eq!(pe.shape(), &[3, 4]);
+    for (a, b) in std::iter::zip(pe.to_vec::<f32>(), EXPECTED.concat()) {
+        assert!((a - b).abs() < 1e-5, "{a} != {b}");
+    }
+
+    let pe = sinusoidal_pos(3, 4, F16);
+    assert_eq!(pe.data_layout(), F16);
+    for (a, b) in std::iter::zip(pe.to_vec::<f16>(), EXPECTED.concat()) {
+        assert!((a.to_f32() - b).abs() < 1e-3, "{a} != {b}");
+    }
+}
@@ -0,0 +1,169 @@
+//! 从 `config.json` 识别模型结构。
+
+use crate::FileLoadError::{self, Io, Json};
+use std::{fs::File, path::Path};
+
+/// 模型结构。
+#[allow(missing_docs)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Architecture {
+    Llama2,
+    Llama3,
+    Mistral,
+    Phi2,
+    Phi3,
+    Gemma,
+    Qwen2,
+    Falcon,
+    ChatGLM,
+    Mixtral,
+    Starcoder,
+    MiniCPM,
+    InternLM2,
+    /// 未知的结构，保存 `architectures` 的首个元素。
+    Unknown(String),
+}
+
+/// Llama 3 的词表大小远大于 Llama 2，用于区分同名的两种结构。
+const LLAMA3_MIN_VOCAB: usize = 128000;
+
+impl Architecture {
+    /// 根据 `config.json` 中 `architectures` 的首个元素和词表大小识别模型结构。
+    pub fn new(name: Option<&str>, vocab_size: usize) -> Self {
+        match name.unwrap_or_default() {
+            "LlamaForCausalLM" if vocab_size >= LLAMA3_MIN_VOCAB => Self::Llama3,
+            "LlamaForCausalLM" => Self::Llama2,
+            "MistralForCausalLM" => Self::Mistral,
+            "PhiForCausalLM" => Self::Phi2,
+            "Phi3ForCausalLM" => Self::Phi3,
+            "GemmaForCausalLM" => Self::Gemma,
+            "Qwen2ForCausalLM" => Self::Qwen2,
+            "FalconForCausalLM" | "RWForCausalLM" => Self::Falcon,
+            "ChatGLMModel" | "ChatGLMForConditionalGeneration" => Self::ChatGLM,
+            "MixtralForCausalLM" => Self::Mixtral,
+            "GPTBigCodeForCausalLM" | "Starcoder2ForCausalLM" => Self::Starcoder,
+            "MiniCPMForCausalLM" => Self::MiniCPM,
+            "InternLM2ForCausalLM" => Self::InternLM2,
+            name => Self::Unknown(name.into()),
+        }
+    }
+
+    /// 模型结构在 `config.json` 的 `architectures` 中的名字。
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Llama2 | Self::Llama3 => "LlamaForCausalLM",
+            Self::Mistral => "MistralForCausalLM",
+            Self::Phi2 => "PhiForCausalLM",
+            Self::Phi3 => "Phi3ForCausalLM",
+            Self::Gemma => "GemmaForCausalLM",
+            Self::Qwen2 => "Qwen2ForCausalLM",
+            Self::Falcon => "FalconForCausalLM",
+            Self::ChatGLM => "ChatGLMModel",
+            Self::Mixtral => "MixtralForCausalLM",
+            Self::Starcoder => "GPTBigCodeForCausalLM",
+            Self::MiniCPM => "MiniCPMForCausalLM",
+            Self::InternLM2 => "InternLM2ForCausalLM",
+            Self::Unknown(name) => name,
+        }
+    }
+
+    /// HuggingFace 格式的权重中 q、k 的 RoPE 维度是否按相邻元素 `(x[2i], x[2i+1])` 成对排列。
+    ///
+    /// 大多数结构按 `(x[i], x[i + dh/2])` 成对排列（`rotate_half`），ChatGLM 以相邻元素成对旋转。
+    #[inline]
+    pub fn rope_interleaved(&self) -> bool {
+        matches!(self, Self::ChatGLM)
+    }
+
+    /// 从模型目录中的 `config.json` 识别模型结构。
+    pub fn from_model_dir(model_dir: impl AsRef<Path>) -> Result<Self, FileLoadError> {
+        let config = File::open(model_dir.as_ref().join("config.json")).map_err(Io)?;
+        let config: ArchitectureJson = serde_json::from_reader(config).map_err(Json)?;
+        Ok(config.detect())
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ArchitectureJson {
+    #[serde(default)]
+    architectures: Vec<String>,
+    #[serde(default)]
+    vocab_size: usize,
+}
+
+impl ArchitectureJson {
+    #[inline]
+    fn detect(&self) -> Architecture {
+        Architecture::new(
+            self.architectures.first().map(String::as_str),
+            self.vocab_size,
+        )
+    }
+}
+
+#[test]
+fn test_detect() {
+    use Architecture::*;
+
+    let cases = [
+        (
+            r#"{"architectures":["LlamaForCausalLM"],"vocab_size":32000}"#,
+            Llama2,
+        ),
+        (
+            r#"{"architectures":["LlamaForCausalLM"],"vocab_size":128256}"#,
+            Llama3,
+        ),
+        (
+            r#"{"architectures":["MistralForCausalLM"],"vocab_size":32000}"#,
+            Mistral,
+        ),
+        (
+            r#"{"architectures":["PhiForCausalLM"],"vocab_size":51200}"#,
+            Phi2,
+        ),
+        (
+            r#"{"architectures":["Phi3ForCausalLM"],"vocab_size":32064}"#,
+            Phi3,
+        ),
+        (
+            r#"{"architectures":["GemmaForCausalLM"],"vocab_size":256000}"#,
+            Gemma,
+        ),
+        (
+            r#"{"architectures":["Qwen2ForCausalLM"],"vocab_size":151936}"#,
+            Qwen2,
+        ),
+        (
+            r#"{"architectures":["RWForCausalLM"],"vocab_size":65024}"#,
+            Falcon,
+        ),
+        (r#"{"architectures":["ChatGLMModel"]}"#, ChatGLM),
+        (
+            r#"{"architectures":["MixtralForCausalLM"],"vocab_size":32000}"#,
+            Mixtral,
+        ),
+        (
+            r#"{"architectures":["GPTBigCodeForCausalLM"],"vocab_size":49152}"#,
+            Starcoder,
+        ),
+        (
+            r#"{"architectures":["MiniCPMForCausalLM"],"vocab_size":122753}"#,
+            MiniCPM,
+        ),
+        (
+            r#"{"architectures":["MambaForCausalLM"]}"#,
+            Unknown("MambaForCausalLM".into()),
+        ),
+        (r#"{"vocab_size":32000}"#, Unknown(String::new())),
+    ];
+    for (json, expected) in cases {
+        let config: ArchitectureJson = serde_json::from_str(json).unwrap();
+        let arch = config.detect();
+        assert_eq!(arch, expected, "{json}");
+        assert_eq!(
+            Architecture::new(Some(arch.name()), config.vocab_size),
+            arch
+        );
+    }
+}
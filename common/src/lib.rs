@@ -10,11 +10,13 @@ pub type utok = u32;
 #[allow(non_camel_case_types)]
 pub type upos = u32;
 
+mod architecture;
 mod between_f32;
 mod blob;
 pub mod safe_tensors;
 pub mod test_model;
 
+pub use architecture::Architecture;
 pub use between_f32::BetweenF32;
 pub use blob::Blob;
 pub use half::{bf16, f16};
@@ -5,7 +5,7 @@ use memmap2::Mmap;
 use std::{
     collections::{hash_map, HashMap},
     fs::File,
-    io::{Error as IoError, ErrorKind::NotFound},
+    io::{Error as IoError, ErrorKind::NotFound, Read},
     mem::size_of_val,
     ops::Deref,
     path::Path,
@@ -34,6 +34,45 @@ pub struct SafeTensor<'a> {
     pub format: &'a str,
 }
 
+/// 对文件映射访问模式的建议，对应 `madvise(2)` 的同名选项。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MemoryAdvice {
+    /// 没有特别的建议。
+    Normal,
+    /// 将按顺序访问，可以积极地预读。
+    Sequential,
+    /// 将随机访问，预读没有意义。
+    Random,
+    /// 很快将要访问，可以提前异步读入。
+    WillNeed,
+}
+
+#[cfg(unix)]
+impl From<MemoryAdvice> for memmap2::Advice {
+    #[inline]
+    fn from(value: MemoryAdvice) -> Self {
+        match value {
+            MemoryAdvice::Normal => Self::Normal,
+            MemoryAdvice::Sequential => Self::Sequential,
+            MemoryAdvice::Random => Self::Random,
+            MemoryAdvice::WillNeed => Self::WillNeed,
+        }
+    }
+}
+
+/// 模型目录中 safetensors 文件的组织方式。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ModelDirLayout {
+    /// 单个 `model.safetensors` 文件。
+    SingleFile,
+    /// `model.safetensors.index.json` 索引的多个分片文件。
+    Sharded,
+    /// 每层一个 `layer_<n>.safetensors` 文件，共享的张量在 `shared.safetensors` 中。
+    PerLayer,
+    /// 同时存在多种组织方式，[SafeTensors::load_from_dir] 按上述顺序选择。
+    Mixed,
+}
+
 /// [SafeTensors] 的张量迭代器。
 pub struct Iter<'a> {
     obj: &'a SafeTensors,
@@ -53,6 +92,10 @@ impl SafeTensors {
         if index_file.is_file() {
             return Self::index_file(index_file);
         }
+        // 最后尝试加载逐层文件
+        if path.as_ref().join("shared.safetensors").is_file() {
+            return Self::per_layer_files(path);
+        }
         // 都没有找到
         Err(Io(IoError::new(
             NotFound,
@@ -60,10 +103,34 @@ impl SafeTensors {
         )))
     }
 
+    /// 检测模型目录中 safetensors 文件的组织方式，没有找到任何 safetensors 文件时返回 `None`。
+    pub fn detect_layout(path: impl AsRef<Path>) -> Option<ModelDirLayout> {
+        let path = path.as_ref();
+        let found = [
+            (
+                path.join("model.safetensors").is_file(),
+                ModelDirLayout::SingleFile,
+            ),
+            (
+                path.join("model.safetensors.index.json").is_file(),
+                ModelDirLayout::Sharded,
+            ),
+            (
+                path.join("shared.safetensors").is_file(),
+                ModelDirLayout::PerLayer,
+            ),
+        ];
+        let mut found = found.into_iter().filter(|(found, _)| *found);
+        match (found.next(), found.next()) {
+            (None, _) => None,
+            (Some((_, layout)), None) => Some(layout),
+            (Some(_), Some(_)) => Some(ModelDirLayout::Mixed),
+        }
+    }
+
     /// 加载单个 `.safetensors` 文件。
     pub fn single_file(path: impl AsRef<Path>) -> Result<Self, FileLoadError> {
-        let file = File::open(path).map_err(Io)?;
-        let file = unsafe { Mmap::map(&file) }.map_err(Io)?;
+        let file = map(path)?;
         let header = load_header(&file)?;
         Ok(Self {
             tensors: header
@@ -103,8 +170,7 @@ impl SafeTensors {
                 // 张量在新文件中
                 Entry::Vacant(e) => {
                     // 打开文件
-                    let file = File::open(dir.join(e.key())).map_err(Io)?;
-                    let file = unsafe { Mmap::map(&file) }.map_err(Io)?;
+                    let file = map(dir.join(e.key()))?;
                     let header = load_header(&file)?;
                     // 迭代文件中的张量
                     let i = files.len();
@@ -124,6 +190,58 @@ impl SafeTensors {
         Ok(Self { tensors, files })
     }
 
+    /// 加载逐层存储的模型目录。
+    ///
+    /// 目录中 `shared.safetensors` 存放词表、输出归一化等共享的张量，`layer_<n>.safetensors` 存放第 `n` 层的张量。
+    /// 张量仍以完整的名字访问，不同文件中不能有同名的张量。
+    pub fn per_layer_files(path: impl AsRef<Path>) -> Result<Self, FileLoadError> {
+        let path = path.as_ref();
+        // 按层号排序逐层文件
+        let mut layers = Vec::new();
+        for entry in path.read_dir().map_err(Io)? {
+            let entry = entry.map_err(Io)?;
+            let name = entry.file_name();
+            let layer = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("layer_"))
+                .and_then(|name| name.strip_suffix(".safetensors"))
+                .and_then(|n| n.parse::<usize>().ok());
+            if let Some(layer) = layer {
+                layers.push((layer, entry.path()));
+            }
+        }
+        layers.sort_unstable_by_key(|(layer, _)| *layer);
+        // 依次加载共享文件和逐层文件
+        let mut tensors = HashMap::new();
+        let mut files = Vec::new();
+        let paths = std::iter::once(path.join("shared.safetensors"))
+            .chain(layers.into_iter().map(|(_, path)| path));
+        for (i, path) in paths.enumerate() {
+            let file = map(path)?;
+            let header = load_header(&file)?;
+            for (name, info) in header.tensors {
+                assert!(tensors.insert(name, (i, info)).is_none());
+            }
+            files.push((file, header.metadata.format));
+        }
+        Ok(Self { tensors, files })
+    }
+
+    /// 只读取 `.safetensors` 文件的头部，按名字顺序列出其中所有张量的名字、数据类型和形状。
+    ///
+    /// 不映射文件，返回时文件已关闭。
+    pub fn tensor_names(
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<(String, Dtype, Vec<usize>)>, FileLoadError> {
+        let mut file = File::open(path).map_err(Io)?;
+        let mut header_len = [0u8; 8];
+        file.read_exact(&mut header_len).map_err(Io)?;
+        let mut header = vec![0u8; u64::from_le_bytes(header_len) as _];
+        file.read_exact(&mut header).map_err(Io)?;
+        let header: SafeTensorsHeader = serde_json::from_slice(&header).map_err(Json)?;
+        Ok(header.list_tensors())
+    }
+
     /// 共享自身。
     #[inline]
     pub fn share(self) -> Pin<Arc<Self>> {
@@ -167,6 +285,17 @@ impl SafeTensors {
         self.tensors.len()
     }
 
+    /// 向操作系统建议所有文件映射的访问模式，在非 unix 平台上什么也不做。
+    pub fn advise(&self, advice: MemoryAdvice) -> std::io::Result<()> {
+        #[cfg(unix)]
+        for (file, _) in &self.files {
+            file.advise(advice.into())?;
+        }
+        #[cfg(not(unix))]
+        let _ = advice;
+        Ok(())
+    }
+
     /// 获取张量迭代器。
     #[inline]
     pub fn iter(&self) -> Iter {
@@ -248,6 +377,21 @@ impl SharedTensor {
     pub fn data(&self) -> &[u8] {
         self.data
     }
+
+    /// 向操作系统建议张量数据所在映射区域的访问模式，在非 unix 平台上什么也不做。
+    ///
+    /// 例如在拷贝下一层权重之前建议 [`MemoryAdvice::WillNeed`]，使其提前读入。
+    pub fn advise(&self, advice: MemoryAdvice) -> std::io::Result<()> {
+        #[cfg(unix)]
+        {
+            let file = &self.safetensors.files[self.value.0].0;
+            let offset = self.data.as_ptr() as usize - file.as_ptr() as usize;
+            file.advise_range(advice.into(), offset, self.data.len())?;
+        }
+        #[cfg(not(unix))]
+        let _ = advice;
+        Ok(())
+    }
 }
 
 #[allow(missing_docs)]
@@ -272,6 +416,19 @@ pub struct SafeTensorsHeader {
     pub metadata: SafeTensorsHeaderMetadata,
 }
 
+impl SafeTensorsHeader {
+    /// 按名字顺序列出头部中所有张量的名字、数据类型和形状。
+    pub fn list_tensors(&self) -> Vec<(String, Dtype, Vec<usize>)> {
+        let mut ans = self
+            .tensors
+            .iter()
+            .map(|(name, info)| (name.clone(), info.dtype, info.shape.clone()))
+            .collect::<Vec<_>>();
+        ans.sort_unstable_by(|(a, ..), (b, ..)| a.cmp(b));
+        ans
+    }
+}
+
 #[inline]
 fn default_metadata() -> SafeTensorsHeaderMetadata {
     SafeTensorsHeaderMetadata {
@@ -285,6 +442,16 @@ pub struct SafeTensorsHeaderMetadata {
     pub format: String,
 }
 
+/// 映射文件，权重通常将被完整地读取一遍，因此建议顺序预读。
+fn map(path: impl AsRef<Path>) -> Result<Mmap, FileLoadError> {
+    let file = File::open(path).map_err(Io)?;
+    let file = unsafe { Mmap::map(&file) }.map_err(Io)?;
+    // 建议只影响性能，失败时忽略
+    #[cfg(unix)]
+    let _ = file.advise(memmap2::Advice::Sequential);
+    Ok(file)
+}
+
 fn load_header(file: &Mmap) -> Result<SafeTensorsHeader, FileLoadError> {
     let header_len = unsafe { *file.as_ptr().cast::<u64>() };
     let header = &file[size_of_val(&header_len)..][..header_len as _];
@@ -307,3 +474,122 @@ fn test() {
         safetensors.files_count(),
     );
 }
+
+#[test]
+fn test_advise() {
+    let header = br#"{"a":{"dtype":"F32","shape":[2],"data_offsets":[0,8]}}"#;
+    let mut file = (header.len() as u64).to_le_bytes().to_vec();
+    file.extend_from_slice(header);
+    file.extend_from_slice(&[0; 8]);
+    let path = std::env::temp_dir().join("transformer-rs-advise.safetensors");
+    std::fs::write(&path, file).unwrap();
+    let safetensors = SafeTensors::single_file(&path).unwrap().share();
+    std::fs::remove_file(&path).unwrap();
+
+    for advice in [
+        MemoryAdvice::Normal,
+        MemoryAdvice::Sequential,
+        MemoryAdvice::Random,
+        MemoryAdvice::WillNeed,
+    ] {
+        safetensors.advise(advice).unwrap();
+    }
+    let tensor = safetensors.share_tensor("a").unwrap();
+    assert_eq!(tensor.data(), &[0; 8]);
+    tensor.advise(MemoryAdvice::WillNeed).unwrap();
+}
+
+#[test]
+fn test_tensor_names() {
+    let header = br#"{"b":{"dtype":"F16","shape":[2,3],"data_offsets":[0,12]},"a":{"dtype":"F32","shape":[2],"data_offsets":[12,20]}}"#;
+    let mut file = (header.len() as u64).to_le_bytes().to_vec();
+    file.extend_from_slice(header);
+    file.extend_from_slice(&[0; 20]);
+    let path = std::env::temp_dir().join("transformer-rs-tensor-names.safetensors");
+    std::fs::write(&path, file).unwrap();
+    let names = SafeTensors::tensor_names(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        names,
+        [
+            ("a".into(), Dtype::F32, vec![2]),
+            ("b".into(), Dtype::F16, vec![2, 3]),
+        ]
+    );
+
+    let Some(model_dir) = crate::test_model::find() else {
+        return;
+    };
+    let path = model_dir.join("model.safetensors");
+    if !path.is_file() {
+        return;
+    }
+    let names = SafeTensors::tensor_names(&path).unwrap();
+    let safetensors = SafeTensors::single_file(&path).unwrap();
+    assert_eq!(names.len(), safetensors.tensors_count());
+    assert!(names.iter().all(|(name, ..)| safetensors.contains(name)));
+}
+
+#[test]
+fn test_per_layer_files() {
+    let write = |path: &Path, names: &[String]| {
+        let header = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let (begin, end) = (i * 8, i * 8 + 8);
+                format!(r#""{name}":{{"dtype":"F32","shape":[2],"data_offsets":[{begin},{end}]}}"#)
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let header = format!("{{{header}}}");
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend_from_slice(header.as_bytes());
+        file.extend_from_slice(&vec![0u8; names.len() * 8]);
+        std::fs::write(path, file).unwrap();
+    };
+
+    let dir = std::env::temp_dir().join("transformer-rs-per-layer");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    assert_eq!(SafeTensors::detect_layout(&dir), None);
+
+    write(
+        &dir.join("shared.safetensors"),
+        &[
+            "model.embed_tokens.weight".into(),
+            "model.norm.weight".into(),
+            "lm_head.weight".into(),
+        ],
+    );
+    for l in 0..3 {
+        write(
+            &dir.join(format!("layer_{l:02}.safetensors")),
+            &[
+                format!("model.layers.{l}.input_layernorm.weight"),
+                format!("model.layers.{l}.post_attention_layernorm.weight"),
+            ],
+        );
+    }
+    assert_eq!(
+        SafeTensors::detect_layout(&dir),
+        Some(ModelDirLayout::PerLayer)
+    );
+
+    let safetensors = SafeTensors::load_from_dir(&dir).unwrap();
+    assert_eq!(safetensors.files_count(), 4);
+    assert_eq!(safetensors.tensors_count(), 9);
+    assert!(safetensors.contains("lm_head.weight"));
+    let layers = (0..)
+        .take_while(|l| safetensors.contains(&format!("model.layers.{l}.input_layernorm.weight")))
+        .count();
+    assert_eq!(layers, 3);
+
+    write(&dir.join("model.safetensors"), &[]);
+    assert_eq!(
+        SafeTensors::detect_layout(&dir),
+        Some(ModelDirLayout::Mixed)
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
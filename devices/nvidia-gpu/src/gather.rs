@@ -1,8 +1,14 @@
 ﻿use common::utok;
 use operators::cuda::{DevByte, Stream};
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    ops::{Deref, DerefMut},
+};
 use tensor::Tensor;
 
+/// 从主存中的词表收集 `tokens` 对应的行。
+///
+/// 每个词只从主存拷贝一次，重复的词从其首次出现的位置在显存内拷贝。
 pub fn gather<T, U, I>(x: &mut Tensor<T>, table: &Tensor<U>, tokens: I, stream: &Stream)
 where
     T: DerefMut<Target = [DevByte]>,
@@ -20,9 +26,16 @@ where
 
     let x = &mut **x.physical_mut();
     let table = table.as_slice();
+    let mut first = HashMap::new();
     for (i, t) in tokens.into_iter().enumerate() {
-        let dst = &mut x[d * i..][..d];
-        let src = &table[d * t as usize..][..d];
-        stream.memcpy_h2d(dst, src);
+        let (gathered, x) = x.split_at_mut(d * i);
+        let dst = &mut x[..d];
+        match first.entry(t) {
+            Entry::Occupied(j) => stream.memcpy_d2d(dst, &gathered[d * j.get()..][..d]),
+            Entry::Vacant(j) => {
+                j.insert(i);
+                stream.memcpy_h2d(dst, &table[d * t as usize..][..d]);
+            }
+        }
     }
 }
@@ -20,7 +20,7 @@ use std::{
 
 pub use common_devices::{Kernels, KernelsA, KernelsB};
 pub use operators::{cuda, nvidia_gpu::Handle as Gpu};
-pub use sample::{sample_cpu, sample_nv};
+pub use sample::{log_softmax_cpu, sample_cpu, sample_nv};
 pub use tensor::{reslice, reslice_mut, slice, split, udim, LocalSplitable, Tensor};
 
 pub struct NvidiaKernels(HashMap<i32, Internal>);
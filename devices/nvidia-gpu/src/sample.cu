@@ -91,8 +91,15 @@ static __global__ void random_sample_kernel(
     half const *__restrict__ data,
     unsigned int const *__restrict__ indices,
     unsigned int *__restrict__ index_,
-    float random, float topp, int topk, int voc) {
-    half p = random * min(topp * (float) data[voc - 1], (float) data[topk - 1]);
+    float random, float topp, int topk, int min_keep, int voc) {
+    // 软阈值在硬阈值筛选后的分布上计算，保留到累积概率首次达到 topp 的词为止
+    float pp = topp * (float) data[topk - 1];
+    int n = 0;
+    while (n < topk - 1 && (float) data[n] < pp) {
+        ++n;
+    }
+    n = min(max(n + 1, min_keep), topk);
+    half p = random * (float) data[n - 1];
     for (int i = 0;; ++i) {
         if (data[i] >= p) {
             *index_ = indices[i];
@@ -105,12 +112,12 @@ extern "C" cudaError random_sample_half(
     half const *data,
     unsigned int const *indices,
     unsigned int *index,
-    float random, float topp, int topk, int voc,
+    float random, float topp, int topk, int min_keep, int voc,
     cudaStream_t stream) {
     unsigned int *index_ = nullptr;
     cudaMallocAsync(&index_, sizeof(unsigned int), stream);
 
-    random_sample_kernel<<<1, 1, 0, stream>>>(data, indices, index_, random, topp, topk, voc);
+    random_sample_kernel<<<1, 1, 0, stream>>>(data, indices, index_, random, topp, topk, min_keep, voc);
 
     cudaMemcpy(index, index_, sizeof(unsigned int), cudaMemcpyDeviceToHost);
     cudaFree(index_);
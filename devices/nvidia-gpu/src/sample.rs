@@ -1,16 +1,17 @@
 ﻿use common::{f16, utok, Blob};
 use operators::cuda::{bindings::CUstream, memcpy_d2h, AsRaw, DevByte, DevMem, Stream};
-use sample::SampleArgs;
+use sample::{MirostatState, SampleArgs, SamplingStrategy};
 use std::{
     collections::HashMap,
     ffi::{c_int, c_void},
+    mem::size_of,
     ptr::{null, null_mut},
     sync::{Mutex, OnceLock},
 };
 use tensor::{reslice, reslice_mut};
 
 pub fn sample_cpu(
-    args: impl IntoIterator<Item = (usize, SampleArgs)>,
+    args: impl IntoIterator<Item = (usize, (SampleArgs, Vec<utok>, MirostatState))>,
     logits: &[DevByte],
     voc: usize,
     _stream: &Stream,
@@ -20,10 +21,20 @@ pub fn sample_cpu(
 
     let logits: &[f16] = reslice(&host);
     args.into_iter()
-        .map(|(i, arg)| arg.random(&logits[voc * i..][..voc]))
+        .map(|(i, (arg, history, state))| {
+            arg.random_with_state(&logits[voc * i..][..voc], &history, &state)
+        })
         .collect()
 }
 
+pub fn log_softmax_cpu(logits: &[DevByte], voc: usize) -> Vec<Vec<f32>> {
+    let mut host = Blob::new(logits.len());
+    memcpy_d2h(&mut host, logits);
+
+    let logits: &[f16] = reslice(&host);
+    logits.chunks_exact(voc).map(sample::log_softmax).collect()
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
 #[repr(C)]
 struct CubKeyValuePair<K, V> {
@@ -101,6 +112,7 @@ extern "C" {
         random: f32,
         topp: f32,
         topk: c_int,
+        min_keep: c_int,
         voc: c_int,
         stream: CUstream,
     ) -> c_int;
@@ -180,7 +192,7 @@ fn prealloc_inclusive_sum<'ctx>(stream: &Stream<'ctx>, len: usize) -> DevMem<'ct
 }
 
 pub fn sample_nv(
-    args: impl IntoIterator<Item = (usize, SampleArgs)>,
+    args: impl IntoIterator<Item = (usize, (SampleArgs, Vec<utok>, MirostatState))>,
     logits: &[DevByte],
     voc: usize,
     stream: &Stream,
@@ -201,13 +213,22 @@ pub fn sample_nv(
 
     let mut temp_sum = prealloc_inclusive_sum(stream, voc);
 
+    let rows = logits;
     let logits = logits.as_ptr().cast::<f16>();
     let ans = args
         .into_iter()
-        .map(|(i, args)| {
+        .map(|(i, (args, history, state))| {
             let logits = unsafe { logits.add(i * voc) };
 
-            if args.is_argmax() {
+            let host_only = (args.strategy != SamplingStrategy::TopKTopP || args.min_p > 0.)
+                && !args.is_argmax();
+            if args.modifies_logits(&history) || host_only {
+                // 重复惩罚和 logit 偏置需要修改 logits，设备上只实现了 top-k/top-p 采样，拷贝到主机上采样
+                let mut host = vec![f16::ZERO; voc];
+                let len = voc * size_of::<f16>();
+                memcpy_d2h(&mut host, &rows[i * len..][..len]);
+                args.random_with_state(&host, &history, &state)
+            } else if args.is_argmax() {
                 assert_eq!(0, unsafe {
                     argmax_half(
                         temp_argmax.as_mut_ptr().cast(),
@@ -257,9 +278,10 @@ pub fn sample_nv(
                         sort_out.as_ptr().cast(),
                         indices_out.as_ptr().cast(),
                         &mut index,
-                        rand::random::<f32>(),
+                        args.uniform(),
                         args.top_p,
                         topk,
+                        args.min_tokens_to_keep.clamp(1, voc) as _,
                         voc as _,
                         stream.as_raw(),
                     )
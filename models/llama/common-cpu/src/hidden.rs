@@ -0,0 +1,109 @@
+use crate::Transformer;
+use causal_lm::{CausalLM, DecodingMeta, QueryContext, SampleArgs, SampleMeta};
+use common::{utok, Blob};
+use common_cpu::tensor::Tensor;
+use llama::ComputeStream;
+
+/// 需要捕获隐藏状态的层号，为空时与普通的推理相同。
+#[derive(Clone, Default, Debug)]
+pub struct CaptureHiddenStates(pub Vec<usize>);
+
+impl Transformer {
+    /// 推理并为每个请求采样一个词，同时捕获指定层输出的最后一个词的隐藏状态。
+    ///
+    /// `queries` 为每个请求的查询词序列及其上下文。返回每个请求采样的词，
+    /// 以及按 `capture` 的顺序排列的每层隐藏状态（`batch x 1 x hidden_size`）。
+    pub fn decode_with_hidden_states<'a>(
+        &self,
+        queries: impl IntoIterator<Item = (&'a [utok], QueryContext<'a, Blob>)>,
+        sample: &SampleArgs,
+        capture: &CaptureHiddenStates,
+    ) -> (Vec<utok>, Vec<Tensor<Vec<u8>>>) {
+        let (tokens, queries): (Vec<_>, Vec<_>) = queries.into_iter().unzip();
+        assert!(tokens.iter().all(|t| !t.is_empty()));
+        let dt = self.s.config.dt;
+        let d = self.s.config.d;
+        let batch = tokens.len();
+        // 每个请求最后一个词在所有词中的序号
+        let last = tokens
+            .iter()
+            .scan(0, |end, t| {
+                *end += t.len();
+                Some(*end - 1)
+            })
+            .collect::<Vec<_>>();
+
+        let mut hidden = vec![None; capture.0.len()];
+        let token_embedded = self.token_embed(tokens.iter().flat_map(|t| t.iter().copied()));
+        let hidden_state = self.forward_with(queries, token_embedded, |layer, x| {
+            let row = d as usize * dt.nbytes();
+            let x = x.as_slice();
+            for (i, _) in capture.0.iter().enumerate().filter(|&(_, &l)| l == layer) {
+                let mut ans = Tensor::alloc(dt, &[batch as _, 1, d], |len| vec![0u8; len]);
+                for (dst, &t) in ans.as_mut_slice().chunks_mut(row).zip(&last) {
+                    dst.copy_from_slice(&x[t * row..][..row]);
+                }
+                hidden[i] = Some(ans);
+            }
+        });
+
+        let decoding = tokens.iter().map(|t| DecodingMeta {
+            num_query: t.len(),
+            num_decode: 1,
+        });
+        let logits = self.decode(decoding, hidden_state);
+        let args = (0..batch).map(|_| SampleMeta {
+            num_decode: 1,
+            args: sample.clone(),
+            history: vec![],
+            mirostat: Default::default(),
+        });
+        let tokens = self.sample(args, logits);
+        let hidden = hidden
+            .into_iter()
+            .map(|h| h.expect("layer index out of range"))
+            .collect();
+        (tokens, hidden)
+    }
+}
+
+#[test]
+fn test_hidden_states() {
+    use causal_lm::Model;
+    use common::upos;
+
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    println!("model_dir: {}", model_dir.display());
+
+    let model = Transformer::load(model_dir, ()).unwrap();
+    let d = model.s.config.d;
+    let nlayers = model.s.config.nlayers as usize;
+
+    let prompts: [&[utok]; 2] = [&[1, 450, 7483, 310], &[1, 3681]];
+    let mut caches = prompts.map(|_| model.new_cache());
+    let run = |caches: &mut [Tensor<Blob>; 2], capture: CaptureHiddenStates| {
+        let queries = prompts.iter().zip(caches.iter_mut()).map(|(&p, cache)| {
+            let ctx = QueryContext {
+                cache: Some(cache),
+                range: 0..p.len() as upos,
+            };
+            (p, ctx)
+        });
+        model.decode_with_hidden_states(queries, &Default::default(), &capture)
+    };
+
+    let (tokens, hidden) = run(&mut caches, CaptureHiddenStates(vec![0, nlayers - 1]));
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(hidden.len(), 2);
+    for h in &hidden {
+        assert_eq!(h.shape(), &[2, 1, d]);
+    }
+    assert_ne!(hidden[0].as_slice(), hidden[1].as_slice());
+
+    // 不捕获时结果相同
+    let (tokens_, hidden) = run(&mut caches, CaptureHiddenStates::default());
+    assert_eq!(tokens, tokens_);
+    assert!(hidden.is_empty());
+}
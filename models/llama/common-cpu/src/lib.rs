@@ -1,3 +1,6 @@
+mod hidden;
+mod reward;
+
 use causal_lm::{CausalLM, DecodingMeta, Model, QueryContext, SampleMeta};
 use common::{f16, upos, utok, Blob, FileLoadError};
 use common_cpu::{
@@ -7,6 +10,9 @@ use common_cpu::{
 use llama::{ComputeConst, ComputeStream, Handle, LayerStorage, QueueOf, SliceOn, Storage, Weight};
 use std::{iter::repeat, ops::Deref, path::Path, slice::from_raw_parts};
 
+pub use hidden::CaptureHiddenStates;
+pub use reward::RewardModel;
+
 pub struct Transformer {
     s: Storage,
     kernels: CpuKernels,
@@ -18,8 +24,11 @@ impl Model for Transformer {
 
     #[inline]
     fn load(model_dir: impl AsRef<Path>, _meta: Self::Meta) -> Result<Self, Self::Error> {
+        let s = llama::Storage::load_safetensors(model_dir)?;
+        #[cfg(feature = "force_f16_cpu")]
+        let s = s.to_f16_weights();
         Ok(Self {
-            s: llama::Storage::load_safetensors(model_dir)?,
+            s,
             kernels: Default::default(),
         })
     }
@@ -112,6 +121,42 @@ impl<'a> llama::LLamaLayer for LlamaLayer<'a> {
     }
 }
 
+impl Transformer {
+    /// 选出需要解码的词嵌入，归一化后乘以输出头。
+    fn head(
+        &self,
+        decoding: impl IntoIterator<Item = DecodingMeta>,
+        hidden_state: Tensor<Blob>,
+        head: &Tensor<Weight>,
+    ) -> Tensor<Blob> {
+        let dt = self.s.config.dt;
+        let d = self.s.config.d;
+        let epsilon = self.s.config.epsilon;
+
+        let mut x = hidden_state;
+        let range = DecodingMeta::select(&mut x, decoding, |dst, src| dst.copy_from_slice(src));
+
+        if range.is_empty() {
+            return Tensor::alloc(dt, &[0, d as _], Blob::new);
+        }
+
+        let lm_layernorm = &self.s.lm_layernorm;
+        let mut x = x.slice(&[slice![range.start => range.end], slice![=>]]);
+        let mut logits = Tensor::alloc(dt, &[x.shape()[0], head.shape()[1]], Blob::new);
+
+        // 复制一个 x 以实现原地归一化
+        let x_ = x
+            .as_ref()
+            .map_physical(|u| unsafe { from_raw_parts(u.as_ptr(), u.len()) });
+        self.kernels()
+            .rms_norm(&mut x, &x_, lm_layernorm, epsilon, self.queue());
+        self.kernels()
+            .mat_mul(&mut logits, 0., &x, head, 1., self.queue());
+
+        logits
+    }
+}
+
 impl CausalLM for Transformer {
     type Storage = Blob;
 
@@ -158,37 +203,13 @@ impl CausalLM for Transformer {
         <Self as ComputeStream>::forward(self, queries, token_embedded)
     }
 
+    #[inline]
     fn decode(
         &self,
         decoding: impl IntoIterator<Item = DecodingMeta>,
         hidden_state: Tensor<Self::Storage>,
     ) -> Tensor<Self::Storage> {
-        let dt = self.s.config.dt;
-        let d = self.s.config.d;
-        let epsilon = self.s.config.epsilon;
-
-        let mut x = hidden_state;
-        let range = DecodingMeta::select(&mut x, decoding, |dst, src| dst.copy_from_slice(src));
-
-        if range.is_empty() {
-            return Tensor::alloc(dt, &[0, d as _], Blob::new);
-        }
-
-        let lm_layernorm = &self.s.lm_layernorm;
-        let lm_head = &self.s.lm_head;
-        let mut x = x.slice(&[slice![range.start => range.end], slice![=>]]);
-        let mut logits = Tensor::alloc(dt, &[x.shape()[0], lm_head.shape()[1]], Blob::new);
-
-        // 复制一个 x 以实现原地归一化
-        let x_ = x
-            .as_ref()
-            .map_physical(|u| unsafe { from_raw_parts(u.as_ptr(), u.len()) });
-        self.kernels()
-            .rms_norm(&mut x, &x_, lm_layernorm, epsilon, self.queue());
-        self.kernels()
-            .mat_mul(&mut logits, 0., &x, lm_head, 1., self.queue());
-
-        logits
+        self.head(decoding, hidden_state, &self.s.lm_head)
     }
 
     fn sample(
@@ -199,11 +220,24 @@ impl CausalLM for Transformer {
         let &[_, voc] = logits.shape() else { panic!() };
         let logits: &[f16] = reslice(logits.as_slice());
         args.into_iter()
-            .flat_map(|meta| repeat(meta.args).take(meta.num_decode))
+            .flat_map(|meta| repeat((meta.args, meta.history, meta.mirostat)).take(meta.num_decode))
             .enumerate()
-            .map(|(i, args)| args.random(&common_cpu::slice!(logits; voc; [i])))
+            .map(|(i, (args, history, state))| {
+                args.random_with_state(&common_cpu::slice!(logits; voc; [i]), &history, &state)
+            })
             .collect()
     }
+
+    fn log_softmax(&self, logits: Tensor<Self::Storage>) -> Option<Vec<Vec<f32>>> {
+        let &[_, voc] = logits.shape() else { panic!() };
+        let logits: &[f16] = reslice(logits.as_slice());
+        Some(
+            logits
+                .chunks_exact(voc as _)
+                .map(causal_lm::log_softmax)
+                .collect(),
+        )
+    }
 }
 
 #[test]
@@ -216,3 +250,37 @@ fn test_infer() {
         ],
     );
 }
+
+#[test]
+fn test_forward_without_head() {
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    println!("model_dir: {}", model_dir.display());
+
+    let model = Transformer::load(model_dir, ()).unwrap();
+    let tokens = [1, 450, 7483, 310, 3444, 338];
+    let hidden_state = model.forward_without_head(&tokens);
+    assert_eq!(
+        hidden_state.shape(),
+        &[tokens.len() as udim, model.s.config.d]
+    );
+
+    let decoding = || {
+        [DecodingMeta {
+            num_query: tokens.len(),
+            num_decode: 1,
+        }]
+    };
+    let logits = model.decode(decoding(), hidden_state);
+
+    let mut cache = model.new_cache();
+    let token_embedded = model.token_embed(tokens);
+    let queries = [QueryContext {
+        cache: Some(&mut cache),
+        range: 0..tokens.len() as upos,
+    }];
+    let hidden_state = CausalLM::forward(&model, queries, token_embedded);
+    let expected = model.decode(decoding(), hidden_state);
+    assert_eq!(logits.as_slice(), expected.as_slice());
+}
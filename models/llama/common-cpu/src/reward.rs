@@ -0,0 +1,82 @@
+use crate::Transformer;
+use causal_lm::{CausalLM, DecodingMeta, Model};
+use common::{f16, utok, FileLoadError};
+use common_cpu::tensor::{reslice, Tensor};
+use llama::Weight;
+use std::path::Path;
+
+/// 奖励模型。
+///
+/// 以标量输出头替换语言模型头，对每个序列输出一个奖励分数，不进行采样。
+pub struct RewardModel {
+    transformer: Transformer,
+    reward_head: Tensor<Weight>,
+}
+
+impl Model for RewardModel {
+    type Meta = ();
+    type Error = FileLoadError;
+
+    #[inline]
+    fn load(model_dir: impl AsRef<Path>, _meta: Self::Meta) -> Result<Self, Self::Error> {
+        let transformer = Transformer::load(&model_dir, ())?;
+        let reward_head = transformer.s.load_reward_head(model_dir)?;
+        Ok(Self::new(transformer, reward_head))
+    }
+}
+
+impl RewardModel {
+    /// 以语言模型和标量输出头（`hidden_size x 1`）构造奖励模型。
+    pub fn new(transformer: Transformer, reward_head: Tensor<Weight>) -> Self {
+        assert_eq!(reward_head.data_layout(), transformer.s.config.dt);
+        assert_eq!(reward_head.shape(), &[transformer.s.config.d, 1]);
+        Self {
+            transformer,
+            reward_head,
+        }
+    }
+
+    /// 计算词序列的奖励分数，取最后一个词的隐藏状态经过输出头得到。
+    pub fn score(&self, tokens: &[utok]) -> f32 {
+        assert!(!tokens.is_empty());
+        let model = &self.transformer;
+        let hidden_state = model.forward_without_head(tokens);
+
+        let decoding = [DecodingMeta {
+            num_query: tokens.len(),
+            num_decode: 1,
+        }];
+        let score = model.head(decoding, hidden_state, &self.reward_head);
+        reslice::<u8, f16>(score.as_slice())[0].to_f32()
+    }
+}
+
+#[test]
+fn test_score() {
+    use common::Blob;
+    use common_cpu::tensor::reslice_mut;
+
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    println!("model_dir: {}", model_dir.display());
+
+    let transformer = Transformer::load(model_dir, ()).unwrap();
+    let config = &transformer.s.config;
+    let mut head = Tensor::alloc(config.dt, &[1, config.d], Blob::new);
+    for (i, x) in reslice_mut::<u8, f16>(head.physical_mut())
+        .iter_mut()
+        .enumerate()
+    {
+        *x = f16::from_f32((i * 7919 % 101) as f32 / 1010. - 0.05);
+    }
+    let head = head.map_physical(Weight::from).transpose(&[1, 0]);
+    let model = RewardModel::new(transformer, head);
+
+    let varied = model.score(&[1, 450, 7483, 310, 3444, 338, 3681, 29889]);
+    let repetitive = model.score(&[1, 3681, 3681, 3681, 3681, 3681, 3681, 3681]);
+    println!("varied: {varied}, repetitive: {repetitive}");
+    assert!(varied.is_finite());
+    assert!(repetitive.is_finite());
+    assert_ne!(varied, repetitive);
+}
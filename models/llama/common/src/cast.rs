@@ -30,9 +30,19 @@ impl Storage {
             lm_head: cast(self.lm_head, dt),
         }
     }
+
+    /// 将 BF16 权重转换为 F16，供不支持 BF16 的后端使用。
+    #[inline]
+    pub fn to_f16_weights(self) -> Self {
+        if self.config.dt == BF16 {
+            self.cast(F16)
+        } else {
+            self
+        }
+    }
 }
 
-fn cast(src: Tensor<Weight>, dt: DigitLayout) -> Tensor<Weight> {
+pub(crate) fn cast(src: Tensor<Weight>, dt: DigitLayout) -> Tensor<Weight> {
     match (src.data_layout(), dt) {
         (F16, BF16) => typed(src, |x: &f16| bf16::from_f32(x.to_f32())),
         (F16, F32) => typed(src, |x: &f16| x.to_f32()),
@@ -61,3 +71,20 @@ fn typed<T: AsDigit + Sync, U: AsDigit + Send>(
 
     ans.map_physical(|b| b.into())
 }
+
+#[test]
+fn test_bf16_to_f16() {
+    use tensor::reslice_mut;
+
+    // 1, -2, 1/3, π, 2^17 的 BF16 表示
+    const BF16_BITS: [u16; 5] = [0x3f80, 0xc000, 0x3eab, 0x4049, 0x4800];
+    // torch.tensor(..., dtype=torch.bfloat16).to(torch.float16).view(torch.int16)
+    const F16_BITS: [u16; 5] = [0x3c00, 0xc000, 0x3558, 0x4248, 0x7c00];
+
+    let mut src = Tensor::alloc(BF16, &[5], Blob::new);
+    reslice_mut(src.physical_mut()).copy_from_slice(&BF16_BITS);
+
+    let ans = cast(src.map_physical(|b| b.into()), F16);
+    assert_eq!(ans.data_layout(), F16);
+    assert_eq!(ans.to_vec::<u16>(), F16_BITS);
+}
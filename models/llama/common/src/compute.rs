@@ -32,9 +32,22 @@ pub trait ComputeStream {
     ) -> impl Iterator<Item = impl LLamaLayer<Byte = <Self::Handle as Handle>::Byte>>;
 
     fn forward<'q>(
+        &self,
+        queries: impl IntoIterator<Item = QueryContext<'q, Self::Storage>>,
+        token_embedded: Tensor<Self::Storage>,
+    ) -> Tensor<Self::Storage>
+    where
+        Self::Storage: 'q,
+    {
+        self.forward_with(queries, token_embedded, |_, _| {})
+    }
+
+    /// 执行前向传播，每层计算完成后以层号和这一层输出的隐藏状态（`num_tokens x hidden_size`）调用 `capture`。
+    fn forward_with<'q>(
         &self,
         queries: impl IntoIterator<Item = QueryContext<'q, Self::Storage>>,
         mut token_embedded: Tensor<Self::Storage>,
+        mut capture: impl FnMut(usize, &Tensor<&mut SliceOn<Self::Handle>>),
     ) -> Tensor<Self::Storage>
     where
         Self::Storage: 'q,
@@ -163,6 +176,8 @@ pub trait ComputeStream {
             self.kernels().swiglu(&mut gate, &up, queue);
             self.kernels()
                 .mat_mul(&mut x, 1., &gate, &params.mlp_down(), 1., queue);
+
+            capture(layer, &x);
         }
         self.free_pos(pos.take_physical());
         self.free(state_buf.take_physical());
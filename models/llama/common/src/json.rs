@@ -1,11 +1,18 @@
-﻿use common::utok;
+﻿use crate::InferenceConfig;
+use common::{
+    utok, Architecture,
+    FileLoadError::{self, Io, Json},
+};
 use digit_layout::{
     types::{BF16, F16, F32},
     DigitLayout,
 };
+use std::{fs::File, io::Read, path::Path};
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub(crate) struct ConfigJson {
+    #[serde(default)]
+    pub architectures: Vec<String>,
     pub bos_token_id: utok,
     pub eos_token_id: utok,
     pub hidden_size: usize,
@@ -19,10 +26,31 @@ pub(crate) struct ConfigJson {
     pub rms_norm_eps: f32,
     #[serde(default = "default_rope_theta")]
     pub rope_theta: f32,
+    /// 权重中 q、k 的 RoPE 维度是否已按相邻元素成对排列。
+    ///
+    /// HuggingFace 的 config.json 没有这个字段，由模型结构推断；`Storage::save` 保存的模型显式写入。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rope_interleaved: Option<bool>,
     pub torch_dtype: String,
 }
 
 impl ConfigJson {
+    /// 根据 `architectures` 的首个元素识别模型结构。
+    #[inline]
+    pub fn detect_architecture(&self) -> Architecture {
+        Architecture::new(
+            self.architectures.first().map(String::as_str),
+            self.vocab_size,
+        )
+    }
+
+    /// 权重中 q、k 的 RoPE 排列方式，未显式指定时由模型结构推断。
+    #[inline]
+    pub fn rope_interleaved(&self) -> bool {
+        self.rope_interleaved
+            .unwrap_or_else(|| self.detect_architecture().rope_interleaved())
+    }
+
     pub fn data_layout(&self) -> DigitLayout {
         match self.torch_dtype.as_str() {
             "float16" => F16,
@@ -33,6 +61,44 @@ impl ConfigJson {
     }
 }
 
+impl From<&ConfigJson> for InferenceConfig {
+    fn from(config: &ConfigJson) -> Self {
+        let d = config.hidden_size as _;
+        let nh = config.num_attention_heads as _;
+        let nkvh = config.num_key_value_heads as _;
+        Self {
+            architecture: config.detect_architecture(),
+            dt: config.data_layout(),
+            voc: config.vocab_size as _,
+            nlayers: config.num_hidden_layers as _,
+            nh,
+            nkvh,
+            d,
+            dkv: d / nh * nkvh,
+            di: config.intermediate_size as _,
+            max_seq_len: config.max_position_embeddings as _,
+            bos_token: config.bos_token_id,
+            eos_token: config.eos_token_id,
+            epsilon: config.rms_norm_eps,
+            theta: config.rope_theta,
+            rope_interleaved: config.rope_interleaved(),
+        }
+    }
+}
+
+impl InferenceConfig {
+    /// 从 `config.json` 的内容解析模型配置，不加载权重。
+    pub fn from_reader(reader: impl Read) -> Result<Self, serde_json::Error> {
+        serde_json::from_reader::<_, ConfigJson>(reader).map(|config| Self::from(&config))
+    }
+
+    /// 解析模型目录中的 `config.json`，不加载权重。
+    pub fn from_model_dir(model_dir: impl AsRef<Path>) -> Result<Self, FileLoadError> {
+        let file = File::open(model_dir.as_ref().join("config.json")).map_err(Io)?;
+        Self::from_reader(file).map_err(Json)
+    }
+}
+
 pub(crate) fn data_layout_name(layout: DigitLayout) -> &'static str {
     match layout {
         F16 => "float16",
@@ -51,3 +117,46 @@ const fn default_rms_norm_eps() -> f32 {
 const fn default_rope_theta() -> f32 {
     1e4
 }
+
+#[test]
+fn test_from_reader() {
+    let json = r#"{
+        "architectures": ["LlamaForCausalLM"],
+        "bos_token_id": 1,
+        "eos_token_id": 2,
+        "hidden_size": 2048,
+        "intermediate_size": 5632,
+        "max_position_embeddings": 2048,
+        "num_attention_heads": 32,
+        "num_hidden_layers": 22,
+        "num_key_value_heads": 4,
+        "vocab_size": 32000,
+        "torch_dtype": "bfloat16"
+    }"#;
+    let config = InferenceConfig::from_reader(json.as_bytes()).unwrap();
+    assert_eq!(config.dt, BF16);
+    assert_eq!(config.voc, 32000);
+    assert_eq!(config.nlayers, 22);
+    assert_eq!((config.nh, config.nkvh), (32, 4));
+    assert_eq!((config.d, config.dkv, config.di), (2048, 256, 5632));
+    assert_eq!(config.max_seq_len, 2048);
+    assert_eq!((config.bos_token, config.eos_token), (1, 2));
+    assert_eq!(config.epsilon, 1e-5);
+    assert_eq!(config.theta, 1e4);
+    assert!(!config.rope_interleaved);
+
+    // 以相邻元素成对旋转的结构
+    let json = json.replace("LlamaForCausalLM", "ChatGLMModel");
+    let config = InferenceConfig::from_reader(json.as_bytes()).unwrap();
+    assert!(config.rope_interleaved);
+
+    assert!(InferenceConfig::from_reader(&b"{}"[..]).is_err());
+
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    let config = InferenceConfig::from_model_dir(&model_dir).unwrap();
+    let storage = crate::Storage::load_safetensors(model_dir).unwrap();
+    assert_eq!(config.nlayers, storage.config.nlayers);
+    assert_eq!(config.d, storage.config.d);
+}
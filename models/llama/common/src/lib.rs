@@ -2,9 +2,10 @@ mod cast;
 mod compute;
 mod json;
 mod load;
+mod prune;
 mod save;
 
-use common::{safe_tensors::SharedTensor, upos, utok, Blob};
+use common::{safe_tensors::SharedTensor, upos, utok, Architecture, Blob};
 use digit_layout::DigitLayout;
 use std::{ops::Deref, sync::Arc};
 use tensor::{slice, udim, Tensor};
@@ -53,6 +54,7 @@ impl<T> LayerStorage<T> {
 
 #[derive(Clone, Debug)]
 pub struct InferenceConfig {
+    pub architecture: Architecture,
     pub dt: DigitLayout,
     pub voc: udim,
     pub nlayers: udim,
@@ -66,9 +68,42 @@ pub struct InferenceConfig {
     pub eos_token: utok,
     pub epsilon: f32,
     pub theta: f32,
+    /// RoPE 以相邻元素 `(x[2i], x[2i+1])` 成对旋转（复数形式）。
+    ///
+    /// 推理时总是使用这种形式，因此为 `false` 时，加载的 q、k 权重将由
+    /// `(x[i], x[i + dh/2])` 成对的形式重排为这种形式。
+    pub rope_interleaved: bool,
 }
 
 impl InferenceConfig {
+    /// 每个 token 在所有层的 kv 缓存中占用的字节数，即 `2 x nlayers x nkvh x dh x sizeof(dt)`。
+    ///
+    /// 在加载权重前估计显存预算：
+    ///
+    /// ```no_run
+    /// let config = llama::Storage::load_safetensors("path/to/model").unwrap().config;
+    /// let per_token = config.kv_cache_bytes_per_token();
+    /// let sessions = config.max_sessions_for_memory(8 << 30, config.max_seq_len as _);
+    /// println!("{per_token} bytes/token, {sessions} sessions in 8 GiB");
+    /// ```
+    #[inline]
+    pub fn kv_cache_bytes_per_token(&self) -> usize {
+        let dh = (self.d / self.nh) as usize;
+        2 * self.nlayers as usize * self.nkvh as usize * dh * self.dt.nbytes()
+    }
+
+    /// `available_bytes` 字节的空间（扣除权重后）最多能容纳的 kv 缓存 token 数。
+    #[inline]
+    pub fn max_kv_cache_tokens(&self, available_bytes: usize) -> usize {
+        available_bytes / self.kv_cache_bytes_per_token()
+    }
+
+    /// `available_bytes` 字节的空间能容纳的会话数，每个会话的缓存长度为 `max_seq_len`。
+    #[inline]
+    pub fn max_sessions_for_memory(&self, available_bytes: usize, max_seq_len: usize) -> usize {
+        self.max_kv_cache_tokens(available_bytes) / max_seq_len
+    }
+
     pub fn new_cache<S>(&self, f: impl FnOnce(usize) -> S) -> Tensor<S> {
         Tensor::alloc(
             self.dt,
@@ -139,3 +174,39 @@ impl Deref for Weight {
         }
     }
 }
+
+#[test]
+fn test_kv_cache_bytes() {
+    use digit_layout::types::F16;
+
+    let config = InferenceConfig {
+        architecture: Architecture::Llama2,
+        dt: F16,
+        voc: 32000,
+        nlayers: 22,
+        nh: 32,
+        nkvh: 4,
+        d: 2048,
+        dkv: 256,
+        di: 5632,
+        max_seq_len: 2048,
+        bos_token: 1,
+        eos_token: 2,
+        epsilon: 1e-5,
+        theta: 1e4,
+        rope_interleaved: true,
+    };
+    assert_eq!(config.kv_cache_bytes_per_token(), 2 * 22 * 4 * 64 * 2);
+
+    let cache = config.new_cache(|_| ());
+    let per_session = cache.bytes_size();
+    assert_eq!(
+        per_session,
+        config.kv_cache_bytes_per_token() * config.max_seq_len as usize
+    );
+    assert_eq!(config.max_sessions_for_memory(per_session * 3 + 1, 2048), 3);
+
+    let per_token = config.kv_cache_bytes_per_token();
+    assert_eq!(config.max_kv_cache_tokens(per_token * 100), 100);
+    assert_eq!(config.max_kv_cache_tokens(per_token * 100 - 1), 99);
+}
@@ -1,12 +1,12 @@
-﻿use crate::{json::ConfigJson, InferenceConfig, LayerStorage, Storage, Weight};
+﻿use crate::{cast::cast, json::ConfigJson, InferenceConfig, LayerStorage, Storage, Weight};
 use common::{
     safe_tensors::{Dtype, SafeTensors},
-    Blob,
+    Architecture, Blob,
     FileLoadError::{self, Io, Json},
 };
 use digit_layout::DigitLayout;
 use std::{fs::File, path::Path, pin::Pin, sync::Arc};
-use tensor::{udim, Shape, Tensor};
+use tensor::{slice, udim, Shape, SliceDim, Tensor};
 
 impl Storage {
     pub fn load_safetensors(model_dir: impl AsRef<Path>) -> Result<Self, FileLoadError> {
@@ -23,44 +23,59 @@ impl Storage {
         let dkv = dh * nkvh;
         let di = config.intermediate_size as udim;
 
+        let architecture = config.detect_architecture();
+        let internlm2 = architecture == Architecture::InternLM2;
+        let rename = |name: &'static str| {
+            if internlm2 {
+                internlm2_name(name)
+            } else {
+                name
+            }
+        };
+
         Ok(Self {
-            config: InferenceConfig {
-                dt,
-                voc,
-                nlayers: config.num_hidden_layers as _,
-                nh,
-                nkvh,
-                d,
-                dkv,
-                di,
-                max_seq_len: config.max_position_embeddings as _,
-                bos_token: config.bos_token_id,
-                eos_token: config.eos_token_id,
-                epsilon: config.rms_norm_eps,
-                theta: config.rope_theta,
-            },
-
-            embed_tokens: tensor(&model, "model.embed_tokens.weight", dt, [voc, d]),
+            config: InferenceConfig::from(&config),
+
+            embed_tokens: tensor(&model, rename("model.embed_tokens.weight"), dt, [voc, d]),
             layers: (0..config.num_hidden_layers)
                 .map(|l| {
-                    let name = |name: &str| format!("model.layers.{l}.{name}.weight");
+                    let name =
+                        |name: &'static str| format!("model.layers.{l}.{}.weight", rename(name));
+                    // 将 q、k 重排为相邻元素成对旋转的形式
+                    let rope = |t: Tensor<Weight>, nh: udim| {
+                        let t = t.reshape(&[nh, 2, dh / 2, d]);
+                        if config.rope_interleaved() {
+                            t
+                        } else {
+                            t.transpose(&[0, 2, 1, 3])
+                        }
+                    };
+                    let skv = &[nkvh, 2, dh / 2, d];
                     LayerStorage {
                         att_layernorm: tensor(&model, &name("input_layernorm"), dt, [d]),
                         att_qkv: {
                             let qkv = name("self_attn.qkv_proj");
-                            if model.contains(&qkv) {
+                            if internlm2 {
+                                // InternLM2 的 wqkv 按 kv 头分组，每组依次为 q、k、v
+                                let g = nh / nkvh;
+                                let wqkv = tensor(&model, &qkv, dt, [d + dkv + dkv, d]);
+                                let wqkv = wqkv.reshape(&[nkvh, g + 2, dh, d]);
+                                let part = |s: SliceDim, n: udim| {
+                                    let t = wqkv.clone();
+                                    let t = t.slice(&[slice![=>], s, slice![=>], slice![=>]]);
+                                    concat0(&[t]).reshape(&[n * dh, d])
+                                };
+                                let q = rope(part(slice![=>g], nh), nh);
+                                let k = rope(part(slice![g =>=> 1], nkvh), nkvh);
+                                let v = part(slice![g + 1 =>=> 1], nkvh).reshape(skv);
+                                concat0(&[q, k, v]).reshape(&[d + dkv + dkv, d])
+                            } else if model.contains(&qkv) {
                                 tensor(&model, &qkv, dt, [d + dkv + dkv, d])
                             } else {
-                                let sq = &[nh, 2, dh / 2, d];
-                                let skv = &[nkvh, 2, dh / 2, d];
-                                let perm = &[0, 2, 1, 3];
-
-                                let q = tensor(&model, &name("self_attn.q_proj"), dt, [d, d])
-                                    .reshape(sq)
-                                    .transpose(perm);
-                                let k = tensor(&model, &name("self_attn.k_proj"), dt, [dkv, d])
-                                    .reshape(skv)
-                                    .transpose(perm);
+                                let q = tensor(&model, &name("self_attn.q_proj"), dt, [d, d]);
+                                let q = rope(q, nh);
+                                let k = tensor(&model, &name("self_attn.k_proj"), dt, [dkv, d]);
+                                let k = rope(k, nkvh);
                                 let v = tensor(&model, &name("self_attn.v_proj"), dt, [dkv, d])
                                     .reshape(skv);
                                 concat0(&[q, k, v]).reshape(&[d + dkv + dkv, d])
@@ -87,10 +102,49 @@ impl Storage {
                     }
                 })
                 .collect(),
-            lm_layernorm: tensor(&model, "model.norm.weight", dt, [d]),
-            lm_head: tensor(&model, "lm_head.weight", dt, [voc, d]).transpose(&[1, 0]),
+            lm_layernorm: tensor(&model, rename("model.norm.weight"), dt, [d]),
+            lm_head: tensor(&model, rename("lm_head.weight"), dt, [voc, d]).transpose(&[1, 0]),
         })
     }
+
+    /// 从 `reward_head.safetensors` 加载奖励模型的标量输出头（`hidden_size x 1`）。
+    ///
+    /// 输出头以 `score.weight`（`1 x hidden_size`）命名，将转换为与模型相同的数据类型。
+    pub fn load_reward_head(
+        &self,
+        model_dir: impl AsRef<Path>,
+    ) -> Result<Tensor<Weight>, FileLoadError> {
+        let path = model_dir.as_ref().join("reward_head.safetensors");
+        let model = SafeTensors::single_file(path)?.share();
+        let name = "score.weight";
+        let dt = model
+            .get(name)
+            .map(|t| convert(t.dtype))
+            .unwrap_or_else(|| panic!("missing tensor: {name}"));
+        let head = tensor(&model, name, dt, [1, self.config.d]);
+        let head = if dt == self.config.dt {
+            head
+        } else {
+            cast(head, self.config.dt)
+        };
+        Ok(head.transpose(&[1, 0]))
+    }
+}
+
+/// 将 Llama 的权重名映射为 InternLM2 的权重名。
+fn internlm2_name(name: &str) -> &str {
+    match name {
+        "model.embed_tokens.weight" => "model.tok_embeddings.weight",
+        "input_layernorm" => "attention_norm",
+        "self_attn.qkv_proj" => "attention.wqkv",
+        "self_attn.o_proj" => "attention.wo",
+        "post_attention_layernorm" => "ffn_norm",
+        "mlp.gate_proj" => "feed_forward.w1",
+        "mlp.up_proj" => "feed_forward.w3",
+        "mlp.down_proj" => "feed_forward.w2",
+        "lm_head.weight" => "output.weight",
+        name => name,
+    }
 }
 
 fn tensor<const N: usize>(
@@ -110,7 +164,7 @@ fn tensor<const N: usize>(
     Tensor::new(dt, &shape, Weight::SafeTensor(shared))
 }
 
-fn concat0(tensors: &[Tensor<Weight>]) -> Tensor<Weight> {
+pub(crate) fn concat0(tensors: &[Tensor<Weight>]) -> Tensor<Weight> {
     assert!(tensors
         .windows(2)
         .all(|t| t[0].data_layout() == t[1].data_layout()));
@@ -130,6 +184,221 @@ fn concat0(tensors: &[Tensor<Weight>]) -> Tensor<Weight> {
     ans.map_physical(|b| b.into())
 }
 
+/// 以 `(x[i], x[i + dh/2])` 成对旋转的 RoPE 参考实现。
+#[cfg(test)]
+fn rope_half(x: &[f32], pos: f32, theta: f32) -> Vec<f32> {
+    let half = x.len() / 2;
+    let mut ans = x.to_vec();
+    for i in 0..half {
+        let freq = pos / theta.powf(i as f32 * 2. / x.len() as f32);
+        let (sin, cos) = freq.sin_cos();
+        ans[i] = x[i] * cos - x[i + half] * sin;
+        ans[i + half] = x[i] * sin + x[i + half] * cos;
+    }
+    ans
+}
+
+/// 以 `(x[2i], x[2i+1])` 成对旋转的 RoPE 参考实现。
+#[cfg(test)]
+fn rope_interleaved(x: &[f32], pos: f32, theta: f32) -> Vec<f32> {
+    let mut ans = x.to_vec();
+    for i in 0..x.len() / 2 {
+        let freq = pos / theta.powf(i as f32 * 2. / x.len() as f32);
+        let (sin, cos) = freq.sin_cos();
+        ans[2 * i] = x[2 * i] * cos - x[2 * i + 1] * sin;
+        ans[2 * i + 1] = x[2 * i] * sin + x[2 * i + 1] * cos;
+    }
+    ans
+}
+
+#[test]
+fn test_rope_interleaved() {
+    const DH: usize = 8;
+    let x = (0..DH).map(|i| i as f32 + 1.).collect::<Vec<_>>();
+    let (pos, theta) = (3., 1e4);
+    // 与加载时的重排相同：[2, dh/2] -> [dh/2, 2]
+    let permute = |x: &[f32]| {
+        (0..DH)
+            .map(|i| x[i % 2 * DH / 2 + i / 2])
+            .collect::<Vec<_>>()
+    };
+
+    let half = rope_half(&x, pos, theta);
+    let interleaved = rope_interleaved(&x, pos, theta);
+    assert!(half
+        .iter()
+        .zip(&interleaved)
+        .any(|(a, b)| (a - b).abs() > 1e-3));
+
+    let permuted = rope_interleaved(&permute(&x), pos, theta);
+    for (a, b) in permute(&half).iter().zip(&permuted) {
+        assert!((a - b).abs() < 1e-5);
+    }
+}
+
+/// 在 `dir` 中写入测试用的模型，每个张量的元素的值为其所在的行号。
+#[cfg(test)]
+fn write_test_model(dir: &Path, config: &str, shapes: Vec<(&str, Vec<usize>)>) {
+    use common::safe_tensors::{SafeTensorsHeader, SafeTensorsHeaderMetadata, TensorInfo};
+    use std::{collections::HashMap, fs, io::Write};
+
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("config.json"), config).unwrap();
+
+    let mut header = SafeTensorsHeader {
+        tensors: HashMap::new(),
+        metadata: SafeTensorsHeaderMetadata {
+            format: "pt".into(),
+        },
+    };
+    let mut data = Vec::<u8>::new();
+    for (name, shape) in shapes {
+        let cols = shape[1..].iter().product::<usize>();
+        let start = data.len();
+        for i in 0..shape[0] {
+            for _ in 0..cols {
+                data.extend_from_slice(&(i as f32).to_ne_bytes());
+            }
+        }
+        let info = TensorInfo {
+            dtype: Dtype::F32,
+            shape,
+            data_offsets: (start, data.len()),
+        };
+        header.tensors.insert(name.into(), info);
+    }
+    let mut header = serde_json::to_string(&header).unwrap();
+    while header.len() % 8 != 0 {
+        header.push(' ');
+    }
+    let mut file = File::create(dir.join("model.safetensors")).unwrap();
+    file.write_all(&(header.len() as u64).to_ne_bytes())
+        .unwrap();
+    file.write_all(header.as_bytes()).unwrap();
+    file.write_all(&data).unwrap();
+}
+
+#[test]
+fn test_load_internlm2() {
+    const VOC: usize = 16;
+    const D: usize = 8;
+    const DI: usize = 4;
+    const DKV: usize = 4;
+
+    let dir = std::env::temp_dir().join("transformer-rs-internlm2");
+    write_test_model(
+        &dir,
+        r#"{
+            "architectures": ["InternLM2ForCausalLM"],
+            "bos_token_id": 1,
+            "eos_token_id": 2,
+            "hidden_size": 8,
+            "intermediate_size": 4,
+            "max_position_embeddings": 32,
+            "num_attention_heads": 4,
+            "num_hidden_layers": 1,
+            "num_key_value_heads": 2,
+            "vocab_size": 16,
+            "torch_dtype": "float32"
+        }"#,
+        vec![
+            ("model.tok_embeddings.weight", vec![VOC, D]),
+            ("model.layers.0.attention_norm.weight", vec![D]),
+            (
+                "model.layers.0.attention.wqkv.weight",
+                vec![D + DKV + DKV, D],
+            ),
+            ("model.layers.0.attention.wo.weight", vec![D, D]),
+            ("model.layers.0.ffn_norm.weight", vec![D]),
+            ("model.layers.0.feed_forward.w1.weight", vec![DI, D]),
+            ("model.layers.0.feed_forward.w3.weight", vec![DI, D]),
+            ("model.layers.0.feed_forward.w2.weight", vec![D, DI]),
+            ("model.norm.weight", vec![D]),
+            ("output.weight", vec![VOC, D]),
+        ],
+    );
+    let storage = Storage::load_safetensors(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(storage.config.architecture, Architecture::InternLM2);
+    assert_eq!(storage.embed_tokens.shape(), &[VOC as udim, D as udim]);
+    assert_eq!(storage.lm_head.shape(), &[D as udim, VOC as udim]);
+    // wqkv 按 kv 组排列为 [q0 q1 k0 v0 | q2 q3 k1 v1]，每个头 2 行
+    let qkv = storage.layers[0].att_qkv.to_vec::<f32>();
+    assert_eq!(
+        qkv[..D + DKV + DKV],
+        [0., 1., 2., 3., 8., 9., 10., 11., 4., 5., 12., 13., 6., 7., 14., 15.]
+    );
+}
+
+#[test]
+fn test_load_rope_layout() {
+    const VOC: usize = 16;
+    const D: usize = 8;
+    const DI: usize = 4;
+
+    let shapes = || {
+        vec![
+            ("model.embed_tokens.weight", vec![VOC, D]),
+            ("model.layers.0.input_layernorm.weight", vec![D]),
+            ("model.layers.0.self_attn.q_proj.weight", vec![D, D]),
+            ("model.layers.0.self_attn.k_proj.weight", vec![D, D]),
+            ("model.layers.0.self_attn.v_proj.weight", vec![D, D]),
+            ("model.layers.0.self_attn.o_proj.weight", vec![D, D]),
+            ("model.layers.0.post_attention_layernorm.weight", vec![D]),
+            ("model.layers.0.mlp.gate_proj.weight", vec![DI, D]),
+            ("model.layers.0.mlp.up_proj.weight", vec![DI, D]),
+            ("model.layers.0.mlp.down_proj.weight", vec![D, DI]),
+            ("model.norm.weight", vec![D]),
+            ("lm_head.weight", vec![VOC, D]),
+        ]
+    };
+    let config = |architecture: &str| {
+        format!(
+            r#"{{
+                "architectures": ["{architecture}"],
+                "bos_token_id": 1,
+                "eos_token_id": 2,
+                "hidden_size": 8,
+                "intermediate_size": 4,
+                "max_position_embeddings": 32,
+                "num_attention_heads": 2,
+                "num_hidden_layers": 1,
+                "num_key_value_heads": 2,
+                "vocab_size": 16,
+                "torch_dtype": "float32"
+            }}"#
+        )
+    };
+    let load = |architecture: &str| {
+        let dir = std::env::temp_dir().join(format!("transformer-rs-rope-{architecture}"));
+        write_test_model(&dir, &config(architecture), shapes());
+        let storage = Storage::load_safetensors(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        storage
+    };
+
+    // rotate_half 形式的 q、k 每个头的行由 [2, dh/2] 重排为 [dh/2, 2]，dh = 4
+    let storage = load("LlamaForCausalLM");
+    assert!(!storage.config.rope_interleaved);
+    let qkv = storage.layers[0].att_qkv.to_vec::<f32>();
+    assert_eq!(
+        qkv[..3 * D],
+        [
+            0., 2., 1., 3., 4., 6., 5., 7., // q
+            0., 2., 1., 3., 4., 6., 5., 7., // k
+            0., 1., 2., 3., 4., 5., 6., 7., // v
+        ]
+    );
+    // 以相邻元素成对排列的权重不重排
+    let storage = load("ChatGLMModel");
+    assert!(storage.config.rope_interleaved);
+    let qkv = storage.layers[0].att_qkv.to_vec::<f32>();
+    for part in qkv[..3 * D].chunks(D) {
+        assert_eq!(part, [0., 1., 2., 3., 4., 5., 6., 7.]);
+    }
+}
+
 fn convert(dtype: Dtype) -> DigitLayout {
     use digit_layout::types::*;
     match dtype {
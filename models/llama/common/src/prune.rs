@@ -0,0 +1,117 @@
+use crate::{load::concat0, InferenceConfig, Storage, Weight};
+use common::utok;
+use tensor::{slice, Tensor};
+
+impl Storage {
+    /// 裁剪词表，只保留 `keep_ids` 对应的词嵌入和输出头。
+    ///
+    /// 裁剪后的第 `i` 个词对应原词表中的 `keep_ids[i]`，`keep_ids` 必须包含 bos 和 eos。
+    pub fn prune_vocab(self, keep_ids: &[utok]) -> Self {
+        let remap = |token: utok| {
+            keep_ids
+                .iter()
+                .position(|&id| id == token)
+                .unwrap_or_else(|| panic!("token {token} must be kept")) as utok
+        };
+        let bos_token = remap(self.config.bos_token);
+        let eos_token = remap(self.config.eos_token);
+        Self {
+            config: InferenceConfig {
+                voc: keep_ids.len() as _,
+                bos_token,
+                eos_token,
+                ..self.config
+            },
+            embed_tokens: gather_rows(self.embed_tokens, keep_ids),
+            lm_head: gather_rows(self.lm_head.transpose(&[1, 0]), keep_ids).transpose(&[1, 0]),
+            ..self
+        }
+    }
+}
+
+fn gather_rows(t: Tensor<Weight>, ids: &[utok]) -> Tensor<Weight> {
+    let &[voc, _] = t.shape() else { panic!() };
+    let rows = ids
+        .iter()
+        .map(|&id| {
+            assert!(id < voc, "token {id} out of vocab {voc}");
+            t.clone().slice(&[slice![id =>=> 1], slice![=>]])
+        })
+        .collect::<Vec<_>>();
+    concat0(&rows)
+}
+
+#[test]
+fn test_prune_vocab() {
+    use common::{Architecture, Blob};
+    use digit_layout::types::F32;
+    use tensor::reslice_mut;
+
+    const VOC: usize = 1000;
+    const D: usize = 4;
+
+    let weight = |shape: &[u32], f: &dyn Fn(usize) -> f32| {
+        let mut t = Tensor::alloc(F32, shape, Blob::new);
+        for (i, x) in reslice_mut::<u8, f32>(t.physical_mut())
+            .iter_mut()
+            .enumerate()
+        {
+            *x = f(i);
+        }
+        t.map_physical(Weight::from)
+    };
+    let storage = Storage {
+        config: InferenceConfig {
+            architecture: Architecture::Llama2,
+            dt: F32,
+            voc: VOC as _,
+            nlayers: 0,
+            nh: 1,
+            nkvh: 1,
+            d: D as _,
+            dkv: D as _,
+            di: D as _,
+            max_seq_len: 16,
+            bos_token: 1,
+            eos_token: 8,
+            epsilon: 1e-5,
+            theta: 1e4,
+            rope_interleaved: true,
+        },
+        // 每行的值为词号
+        embed_tokens: weight(&[VOC as _, D as _], &|i| (i / D) as f32),
+        layers: vec![],
+        lm_layernorm: weight(&[D as _], &|_| 1.),
+        // 词号越大得分越高
+        lm_head: weight(&[VOC as _, D as _], &|i| (i / D) as f32).transpose(&[1, 0]),
+    };
+
+    let keep_ids = (0..100).map(|i| i * 7 + 1).collect::<Vec<utok>>();
+    let pruned = storage.prune_vocab(&keep_ids);
+    assert_eq!(pruned.config.voc, 100);
+    assert_eq!(pruned.config.bos_token, 0);
+    assert_eq!(pruned.config.eos_token, 1);
+    assert_eq!(pruned.embed_tokens.shape(), &[100, D as _]);
+    assert_eq!(pruned.lm_head.shape(), &[D as _, 100]);
+
+    let embed = pruned.embed_tokens.to_vec::<f32>();
+    for (row, &id) in embed.chunks(D).zip(&keep_ids) {
+        assert!(row.iter().all(|&x| x == id as f32));
+    }
+
+    // 对保留词的隐藏状态计算 logits，top-1 必然落在保留的词中
+    let lm_head = pruned.lm_head.to_vec::<f32>();
+    for hidden in embed.chunks(D) {
+        let logits = (0..100)
+            .map(|j| {
+                (0..D)
+                    .map(|k| hidden[k] * lm_head[k * 100 + j])
+                    .sum::<f32>()
+            })
+            .collect::<Vec<_>>();
+        let top1 = (0..100)
+            .max_by(|&a, &b| logits[a].total_cmp(&logits[b]))
+            .unwrap();
+        assert_eq!(keep_ids[top1], *keep_ids.last().unwrap());
+    }
+}
@@ -17,6 +17,7 @@ impl Storage {
         let dir = dir.as_ref();
         fs::create_dir_all(dir)?;
         let config = serde_json::to_string_pretty(&ConfigJson {
+            architectures: vec![self.config.architecture.name().into()],
             bos_token_id: self.config.bos_token,
             eos_token_id: self.config.eos_token,
             hidden_size: self.config.d as _,
@@ -28,6 +29,8 @@ impl Storage {
             vocab_size: self.config.voc as _,
             rms_norm_eps: self.config.epsilon,
             rope_theta: self.config.theta,
+            // 保存的 qkv 权重已经重排为相邻元素成对的形式
+            rope_interleaved: Some(true),
             torch_dtype: data_layout_name(self.config.dt).to_string(),
         })?;
         fs::write(dir.join("config.json"), config)?;
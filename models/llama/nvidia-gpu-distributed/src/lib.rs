@@ -13,7 +13,8 @@ use common_nv::{
         AsRaw, Context, ContextResource, ContextSpore, DevByte, DevMem, DevMemSpore, Device,
         HostMemSpore, Stream, StreamSpore,
     },
-    sample_nv, slice, split, udim, DropOption, Kernels, LocalSplitable, NvidiaKernels, Tensor,
+    log_softmax_cpu, sample_nv, slice, split, udim, DropOption, Kernels, LocalSplitable,
+    NvidiaKernels, Tensor,
 };
 use digit_layout::types::F16;
 use itertools::izip;
@@ -422,7 +423,9 @@ impl CausalLM for Transformer {
         contexts[0].apply(|ctx| {
             sample_nv(
                 args.into_iter()
-                    .flat_map(|meta| repeat(meta.args).take(meta.num_decode))
+                    .flat_map(|meta| {
+                        repeat((meta.args, meta.history, meta.mirostat)).take(meta.num_decode)
+                    })
                     .enumerate(),
                 mem[0].sprout_ref(ctx),
                 voc,
@@ -430,6 +433,17 @@ impl CausalLM for Transformer {
             )
         })
     }
+
+    fn log_softmax(&self, logits: Tensor<Self::Storage>) -> Option<Vec<Vec<f32>>> {
+        assert_eq!(logits.data_layout(), F16);
+        let &[_nt, voc] = logits.shape() else {
+            panic!()
+        };
+        let voc = voc as usize;
+        let Cache { contexts, mem } = logits.physical();
+
+        Some(contexts[0].apply(|ctx| log_softmax_cpu(mem[0].sprout_ref(ctx), voc)))
+    }
 }
 
 impl Transformer {
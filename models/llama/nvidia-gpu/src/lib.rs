@@ -8,8 +8,8 @@ extern crate log;
 use causal_lm::{CausalLM, DecodingMeta, Model, QueryContext, SampleMeta};
 use common::{upos, utok, Blob, FileLoadError};
 use common_nv::{
-    cuda::memcpy_d2h, sample_nv, slice, udim, DropOption, Gpu, Kernels, KernelsA, KernelsB,
-    NvidiaKernels, Tensor,
+    cuda::memcpy_d2h, log_softmax_cpu, sample_nv, slice, udim, DropOption, Gpu, Kernels, KernelsA,
+    KernelsB, NvidiaKernels, Tensor,
 };
 use cuda::{
     ContextResource, ContextSpore, DevByte, DevMem, DevMemSpore, Device, EventSpore, HostMemSpore,
@@ -129,6 +129,18 @@ impl Model for Transformer {
 }
 
 impl Transformer {
+    /// 分配 kv 缓存时保留的空闲显存比例。
+    const MEM_MARGIN: f64 = 0.1;
+
+    /// 按设备当前的空闲显存估计可分配的 kv 缓存 token 数，保留 10% 的空闲显存作为余量。
+    ///
+    /// 权重已经加载到设备，因此空闲显存已扣除权重。
+    pub fn auto_config_block_pool(&self) -> usize {
+        let (free, _) = self.resource.mem_info();
+        let available = (free as f64 * (1. - Self::MEM_MARGIN)) as usize;
+        self.config.max_kv_cache_tokens(available)
+    }
+
     #[inline]
     fn cache(&self, len: usize) -> Cache {
         Cache::new(&self.resource, len)
@@ -283,7 +295,9 @@ impl CausalLM for Transformer {
         self.resource.apply(|compute| {
             sample_nv(
                 args.into_iter()
-                    .flat_map(|meta| repeat(meta.args).take(meta.num_decode))
+                    .flat_map(|meta| {
+                        repeat((meta.args, meta.history, meta.mirostat)).take(meta.num_decode)
+                    })
                     .enumerate(),
                 logits
                     .take_physical()
@@ -295,6 +309,35 @@ impl CausalLM for Transformer {
             )
         })
     }
+
+    fn log_softmax(&self, logits: Tensor<Self::Storage>) -> Option<Vec<Vec<f32>>> {
+        assert_eq!(logits.data_layout(), F16);
+        let &[_nt, voc] = logits.shape() else {
+            panic!()
+        };
+        let voc = voc as usize;
+
+        Some(self.resource.apply(|compute| {
+            log_softmax_cpu(
+                logits
+                    .take_physical()
+                    .mem
+                    .as_ref()
+                    .sprout_ref(compute.ctx()),
+                voc,
+            )
+        }))
+    }
+
+    #[inline]
+    fn memory_info(&self) -> Option<(usize, usize)> {
+        Some(self.resource.mem_info())
+    }
+
+    #[inline]
+    fn device_memory_info(meta: &Self::Meta) -> Option<(usize, usize)> {
+        Some(resource::mem_info(&meta.device.retain_primary()))
+    }
 }
 
 impl Drop for Transformer {
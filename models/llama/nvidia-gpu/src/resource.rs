@@ -25,6 +25,23 @@ impl Resource {
         self.context
             .apply(|ctx| f(self.compute.as_ref().sprout_ref(ctx)))
     }
+
+    /// 设备的空闲和总显存字节数。
+    #[inline]
+    pub fn mem_info(&self) -> (usize, usize) {
+        mem_info(&self.context)
+    }
+}
+
+/// `context` 所在设备的空闲和总显存字节数。
+pub(super) fn mem_info(context: &Context) -> (usize, usize) {
+    use common_nv::cuda::bindings::{cuMemGetInfo_v2, CUresult};
+    context.apply(|_| {
+        let (mut free, mut total) = (0, 0);
+        let err = unsafe { cuMemGetInfo_v2(&mut free, &mut total) };
+        assert_eq!(err, CUresult::CUDA_SUCCESS);
+        (free, total)
+    })
 }
 
 impl Drop for Resource {
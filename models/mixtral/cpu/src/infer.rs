@@ -289,11 +289,24 @@ impl CausalLM for MixtralCPU {
         let &[_, voc] = logits.shape() else { panic!() };
         let logits: &[f16] = reslice(logits.as_slice());
         args.into_iter()
-            .flat_map(|meta| repeat(meta.args).take(meta.num_decode))
+            .flat_map(|meta| repeat((meta.args, meta.history, meta.mirostat)).take(meta.num_decode))
             .enumerate()
-            .map(|(i, args)| args.random(&common_cpu::slice!(logits; voc; [i])))
+            .map(|(i, (args, history, state))| {
+                args.random_with_state(&common_cpu::slice!(logits; voc; [i]), &history, &state)
+            })
             .collect()
     }
+
+    fn log_softmax(&self, logits: Tensor<Self::Storage>) -> Option<Vec<Vec<f32>>> {
+        let &[_, voc] = logits.shape() else { panic!() };
+        let logits: &[f16] = reslice(logits.as_slice());
+        Some(
+            logits
+                .chunks_exact(voc as _)
+                .map(causal_lm::log_softmax)
+                .collect(),
+        )
+    }
 }
 
 #[inline]
@@ -2,6 +2,15 @@
 
 mod sample;
 
+use common::utok;
+use std::{
+    collections::HashMap,
+    error, fmt,
+    sync::{Arc, Mutex},
+};
+
+pub use sample::{log_softmax, seed};
+
 /// 采样参数。
 #[derive(Clone, PartialEq, Debug)]
 pub struct SampleArgs {
@@ -11,6 +20,78 @@ pub struct SampleArgs {
     pub top_k: usize,
     /// 软阈值，(0, 1] 区间有效，不大于 0 使用贪心采样。
     pub top_p: f32,
+    /// 相对阈值，[0, 1] 区间，为 0 时不筛选。
+    ///
+    /// 在温度之后、硬阈值之前生效，丢弃概率小于最大概率的 `min_p` 倍的词。
+    pub min_p: f32,
+    /// 软阈值筛选后至少保留的候选词数，在硬阈值筛选之后生效。
+    pub min_tokens_to_keep: usize,
+    /// 重复惩罚，不小于 1，为 1 时不惩罚。
+    ///
+    /// 在温度之前生效，上下文中出现过的词的 logit 为正时除以这个值，为负时乘以这个值。
+    pub repetition_penalty: f32,
+    /// 随机种子，非空时采样结果可复现，否则使用线程随机数。
+    pub seed: Option<u64>,
+    /// 词的 logit 偏置，在温度缩放之后、筛选之前加到对应的 logit 上。
+    ///
+    /// 负值抑制、正值提升对应的词，[`f32::NEG_INFINITY`] 使词的概率为 0。
+    pub logit_bias: HashMap<utok, f32>,
+    /// 采样策略。
+    pub strategy: SamplingStrategy,
+    /// 束宽，大于 1 时使用束搜索解码，忽略其他采样参数。
+    ///
+    /// 束搜索的结果不流式输出，整个搜索结束后才一次发送所有生成的词。
+    pub beam_width: usize,
+    /// 束搜索至多生成的词数，达到这个长度的假设视为完整的。
+    pub beam_max_tokens: usize,
+}
+
+/// 采样策略。
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum SamplingStrategy {
+    /// 依次应用温度、硬阈值和软阈值筛选候选词。
+    #[default]
+    TopKTopP,
+    /// Mirostat 2.0 自适应采样（Basu et al., 2020），忽略硬阈值和软阈值。
+    ///
+    /// 每次采样只保留惊奇度（`-log2 p`）不超过 `mu` 的词，再根据采样到的词的惊奇度调整 `mu`，
+    /// 使生成文本的惊奇度稳定在 `tau` 附近。`mu` 保存在 [`MirostatState`] 中。
+    Mirostat {
+        /// 目标惊奇度。
+        tau: f32,
+        /// `mu` 的学习率。
+        eta: f32,
+    },
+    /// 局部典型采样（Meister et al., 2023），忽略硬阈值和软阈值，(0, 1] 区间有效。
+    ///
+    /// 按词的惊奇度与分布的熵之差的绝对值从小到大保留词，直到累积概率达到阈值。
+    TypicalP(f32),
+}
+
+/// Mirostat 采样的状态，即 `mu` 的当前估计，首次采样时初始化为 `2 tau`。
+///
+/// 状态在同一个会话的多次采样之间延续。克隆得到的状态与原状态共享，使用 [`fork`](Self::fork) 复制出独立的状态。
+#[derive(Clone, Default, Debug)]
+pub struct MirostatState(Arc<Mutex<Option<f32>>>);
+
+impl MirostatState {
+    /// `mu` 的当前估计，尚未采样时为空。
+    #[inline]
+    pub fn mu(&self) -> Option<f32> {
+        *self.0.lock().unwrap()
+    }
+
+    /// 复制出与当前状态独立的状态。
+    #[inline]
+    pub fn fork(&self) -> Self {
+        Self(Arc::new(Mutex::new(self.mu())))
+    }
+
+    /// 清空状态，下次采样时重新初始化。
+    #[inline]
+    pub fn reset(&self) {
+        *self.0.lock().unwrap() = None;
+    }
 }
 
 impl Default for SampleArgs {
@@ -21,6 +102,75 @@ impl Default for SampleArgs {
             temperature: 0.,
             top_k: usize::MAX,
             top_p: 1.,
+            min_p: 0.,
+            min_tokens_to_keep: 1,
+            repetition_penalty: 1.,
+            seed: None,
+            logit_bias: HashMap::new(),
+            strategy: SamplingStrategy::TopKTopP,
+            beam_width: 1,
+            beam_max_tokens: 256,
+        }
+    }
+}
+
+/// 采样参数错误。
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SampleArgsError {
+    /// 重复惩罚小于 1 或不是数。
+    RepetitionPenalty(f32),
+    /// 相对阈值不在 [0, 1] 区间。
+    MinP(f32),
+    /// Mirostat 的目标惊奇度不大于 0 或学习率小于 0。
+    Mirostat { tau: f32, eta: f32 },
+    /// 典型采样的阈值不在 (0, 1] 区间。
+    TypicalP(f32),
+    /// 束宽为 0。
+    BeamWidth,
+    /// 束搜索至多生成的词数为 0。
+    BeamMaxTokens,
+}
+
+impl error::Error for SampleArgsError {}
+impl fmt::Display for SampleArgsError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RepetitionPenalty(p) => write!(f, "repetition penalty {p} is less than 1"),
+            Self::MinP(p) => write!(f, "min p {p} is out of range [0, 1]"),
+            Self::Mirostat { tau, eta } => write!(f, "invalid mirostat tau {tau} or eta {eta}"),
+            Self::TypicalP(p) => write!(f, "typical p {p} is out of range (0, 1]"),
+            Self::BeamWidth => write!(f, "beam width is 0"),
+            Self::BeamMaxTokens => write!(f, "beam max tokens is 0"),
+        }
+    }
+}
+
+impl SampleArgs {
+    /// 检查采样参数是否有效。
+    pub fn validate(&self) -> Result<(), SampleArgsError> {
+        match self.strategy {
+            SamplingStrategy::TopKTopP => {}
+            SamplingStrategy::Mirostat { tau, eta } if tau > 0. && eta >= 0. => {}
+            SamplingStrategy::Mirostat { tau, eta } => {
+                return Err(SampleArgsError::Mirostat { tau, eta })
+            }
+            SamplingStrategy::TypicalP(p) if p > 0. && p <= 1. => {}
+            SamplingStrategy::TypicalP(p) => return Err(SampleArgsError::TypicalP(p)),
+        }
+        if self.beam_width == 0 {
+            return Err(SampleArgsError::BeamWidth);
+        }
+        if self.beam_max_tokens == 0 {
+            return Err(SampleArgsError::BeamMaxTokens);
+        }
+        if !(0. ..=1.).contains(&self.min_p) {
+            return Err(SampleArgsError::MinP(self.min_p));
+        }
+        if self.repetition_penalty >= 1. {
+            Ok(())
+        } else {
+            Err(SampleArgsError::RepetitionPenalty(self.repetition_penalty))
         }
     }
 }
@@ -1,13 +1,171 @@
-﻿use common::{utok, BetweenF32};
-use std::{cmp::Ordering, mem::replace};
+﻿use crate::{MirostatState, SamplingStrategy};
+use common::{utok, BetweenF32};
+use rand::{
+    rngs::{SmallRng, StdRng},
+    Rng, SeedableRng,
+};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    mem::replace,
+    sync::{
+        atomic::{AtomicU64, Ordering::Acquire, Ordering::Release},
+        Mutex,
+    },
+};
+
+/// 进程内设置的种子。
+static SEED: Mutex<u64> = Mutex::new(0);
+/// 种子的版本，每次设置种子时递增，为 0 表示未设置种子，使用线程随机数。
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// 线程内的随机数发生器及其种子的版本，种子更新后在下次采样时重新初始化。
+    static RNG: RefCell<(u64, Option<StdRng>)> = const { RefCell::new((0, None)) };
+}
+
+/// 以 `seed` 重置采样使用的随机数发生器，此后每个线程中相同顺序的采样产生相同的结果。
+///
+/// 每个线程各自持有以这个种子初始化的发生器，采样时不需要加锁。
+pub fn seed(seed: u64) {
+    let mut lock = SEED.lock().unwrap();
+    *lock = seed;
+    GENERATION.fetch_add(1, Release);
+}
+
+/// 计算 `logits` 的对数概率，即 `log softmax`。
+pub fn log_softmax<T: BetweenF32>(logits: &[T]) -> Vec<f32> {
+    let max = logits.iter().map(T::get).fold(f32::NEG_INFINITY, f32::max);
+    let sum = logits.iter().map(|x| (x.get() - max).exp()).sum::<f32>();
+    let log_sum = sum.ln();
+    logits.iter().map(|x| x.get() - max - log_sum).collect()
+}
+
+#[inline]
+fn random_f32() -> f32 {
+    let generation = GENERATION.load(Acquire);
+    if generation == 0 {
+        return rand::random();
+    }
+    RNG.with_borrow_mut(|(current, rng)| {
+        if *current != generation {
+            *current = generation;
+            *rng = Some(StdRng::seed_from_u64(*SEED.lock().unwrap()));
+        }
+        rng.as_mut().unwrap().gen()
+    })
+}
 
 impl crate::SampleArgs {
     #[inline]
     pub fn is_argmax(&self) -> bool {
-        self.temperature <= 0. || self.top_k < 2 || self.top_p <= 0.
+        match self.strategy {
+            SamplingStrategy::TopKTopP => {
+                self.temperature <= 0. || self.top_k < 2 || self.top_p <= 0.
+            }
+            SamplingStrategy::Mirostat { .. } | SamplingStrategy::TypicalP(_) => {
+                self.temperature <= 0.
+            }
+        }
+    }
+
+    /// 是否使用 Mirostat 采样。
+    #[inline]
+    pub fn is_mirostat(&self) -> bool {
+        matches!(self.strategy, SamplingStrategy::Mirostat { .. })
     }
 
-    pub fn random<T>(&self, logits: &[T]) -> utok
+    /// 采样一个词使用的 [0, 1) 均匀随机数。
+    ///
+    /// 设置了 [`seed`](crate::SampleArgs::seed) 时由种子决定，否则取自共享的随机数发生器。
+    #[inline]
+    pub fn uniform(&self) -> f32 {
+        match self.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed).gen(),
+            None => random_f32(),
+        }
+    }
+
+    /// 为生成下一个词推进随机种子，使同一个种子生成的每个词使用不同的随机数。
+    #[inline]
+    pub fn advance_seed(&mut self) {
+        if let Some(seed) = &mut self.seed {
+            *seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        }
+    }
+
+    /// 从 `logits` 中采样一个词，`history` 是已经出现在上下文中的词，用于重复惩罚。
+    ///
+    /// Mirostat 采样每次使用新的状态，需要延续状态时使用 [`random_with_state`](Self::random_with_state)。
+    #[inline]
+    pub fn random<T>(&self, logits: &[T], history: &[utok]) -> utok
+    where
+        T: BetweenF32 + PartialOrd,
+    {
+        self.random_with_state(logits, history, &MirostatState::default())
+    }
+
+    /// 同 [`random`](Self::random)，Mirostat 采样读取并更新 `state`。
+    pub fn random_with_state<T>(
+        &self,
+        logits: &[T],
+        history: &[utok],
+        state: &MirostatState,
+    ) -> utok
+    where
+        T: BetweenF32 + PartialOrd,
+    {
+        if self.modifies_logits(history) {
+            let mut logits = logits.iter().map(T::get).collect::<Vec<_>>();
+            if self.repetition_penalty != 1. {
+                self.penalize(&mut logits, history);
+            }
+            self.bias(&mut logits);
+            self.sample(&logits, state)
+        } else {
+            self.sample(logits, state)
+        }
+    }
+
+    /// 采样前是否需要修改 logits，即是否有生效的重复惩罚或 logit 偏置。
+    #[inline]
+    pub fn modifies_logits(&self, history: &[utok]) -> bool {
+        (self.repetition_penalty != 1. && !history.is_empty()) || !self.logit_bias.is_empty()
+    }
+
+    /// 施加 logit 偏置。
+    ///
+    /// 偏置作用于温度缩放后的 logit，即 `x / t + b`，因此在缩放前加上 `b * t`；贪心采样不缩放。
+    pub fn bias(&self, logits: &mut [f32]) {
+        let scale = if self.temperature > 0. {
+            self.temperature
+        } else {
+            1.
+        };
+        for (&tok, &bias) in &self.logit_bias {
+            if let Some(x) = logits.get_mut(tok as usize) {
+                *x += bias * scale;
+            }
+        }
+    }
+
+    /// 对 `history` 中出现过的词施加重复惩罚，每个词只惩罚一次。
+    pub fn penalize(&self, logits: &mut [f32], history: &[utok]) {
+        let mut penalized = vec![false; logits.len()];
+        for &tok in history {
+            let tok = tok as usize;
+            if !replace(&mut penalized[tok], true) {
+                let x = &mut logits[tok];
+                if *x > 0. {
+                    *x /= self.repetition_penalty;
+                } else {
+                    *x *= self.repetition_penalty;
+                }
+            }
+        }
+    }
+
+    fn sample<T>(&self, logits: &[T], state: &MirostatState) -> utok
     where
         T: BetweenF32 + PartialOrd,
     {
@@ -58,16 +216,441 @@ impl crate::SampleArgs {
             .map(Probability::from)
             .collect::<Vec<_>>();
         logits.sort_unstable();
-        let max = replace(&mut logits[0].val, 1.);
-        // softmax & sum
+        let max = logits[0].val;
+        // softmax
+        for p in &mut logits {
+            p.val = ((p.val - max) / self.temperature).exp();
+        }
+        // minp，最大的词概率为 1，丢弃概率小于 min_p 的词
+        if self.min_p > 0. {
+            let n = logits.partition_point(|p| p.val >= self.min_p);
+            logits.truncate(n.max(1));
+        }
+        if let SamplingStrategy::Mirostat { tau, eta } = self.strategy {
+            let mut mu = state.0.lock().unwrap();
+            let mu = mu.get_or_insert(2. * tau);
+            // 保留惊奇度不超过 mu 的词，至少保留 1 个
+            let threshold = logits.iter().map(|p| p.val).sum::<f32>() * (-*mu).exp2();
+            let n = logits.partition_point(|p| p.val >= threshold).max(1);
+            let logits = &mut logits[..n];
+            for i in 1..n {
+                logits[i].val += logits[i - 1].val;
+            }
+            let plimit = self.uniform() * logits[n - 1].val;
+            let i = logits.partition_point(|p| p.val < plimit);
+            // 以采样到的词在保留的词中的惊奇度更新 mu
+            let p = logits[i].val - if i > 0 { logits[i - 1].val } else { 0. };
+            *mu -= eta * (-(p / logits[n - 1].val).log2() - tau);
+            return logits[i].tok;
+        }
+        if let SamplingStrategy::TypicalP(typical_p) = self.strategy {
+            let probs = logits.iter().map(|p| p.val).collect::<Vec<_>>();
+            let kept = typical_set(&probs, typical_p, self.min_tokens_to_keep);
+            let sum = kept
+                .iter()
+                .scan(0., |sum, &i| {
+                    *sum += probs[i];
+                    Some(*sum)
+                })
+                .collect::<Vec<_>>();
+            let plimit = self.uniform() * sum[sum.len() - 1];
+            return logits[kept[sum.partition_point(|&p| p < plimit)]].tok;
+        }
+        // sum
         for i in 1..logits.len() {
-            logits[i].val = logits[i - 1].val + ((logits[i].val - max) / self.temperature).exp();
+            logits[i].val += logits[i - 1].val;
         }
         // topk & topp & random
-        let pk = logits[self.top_k.min(logits.len()) - 1].val;
-        let pp = logits[logits.len() - 1].val * self.top_p;
-        let plimit = rand::random::<f32>() * f32::min(pk, pp);
+        // 依次应用温度、相对阈值、硬阈值和软阈值，软阈值在硬阈值筛选后的分布上计算
+        let at = |n: usize| logits[n.clamp(1, logits.len()) - 1].val;
+        let pk = at(self.top_k);
+        // 保留到累积概率首次达到 top_p 的词为止
+        let np = logits.partition_point(|p| p.val < pk * self.top_p) + 1;
+        let n = np.max(self.min_tokens_to_keep).min(self.top_k);
+        let plimit = self.uniform() * at(n);
         // sample
         logits.iter().find(|p| p.val >= plimit).unwrap().tok
     }
 }
+
+/// 局部典型采样保留的词在 `probs` 中的序号，`probs` 是未归一化的概率。
+///
+/// 按词的惊奇度与分布的熵之差的绝对值从小到大排列，保留到累积概率首次达到 `typical_p` 为止，至少保留 `min_keep` 个。
+fn typical_set(probs: &[f32], typical_p: f32, min_keep: usize) -> Vec<usize> {
+    let sum = probs.iter().sum::<f32>();
+    // 熵即惊奇度的期望
+    let entropy = probs
+        .iter()
+        .map(|&p| p / sum)
+        .filter(|&p| p > 0.)
+        .map(|p| -p * p.ln())
+        .sum::<f32>();
+    let mut deviation = probs
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| (i, (-(p / sum).ln() - entropy).abs()))
+        .collect::<Vec<_>>();
+    deviation.sort_unstable_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    let mut acc = 0.;
+    let n = deviation
+        .iter()
+        .position(|&(i, _)| {
+            acc += probs[i] / sum;
+            acc >= typical_p
+        })
+        .map_or(deviation.len(), |n| n + 1);
+    deviation.truncate(n.max(min_keep));
+    deviation.into_iter().map(|(i, _)| i).collect()
+}
+
+#[test]
+fn test_seed() {
+    let args = crate::SampleArgs {
+        temperature: 1.,
+        top_k: usize::MAX,
+        top_p: 1.,
+        min_p: 0.,
+        min_tokens_to_keep: 1,
+        repetition_penalty: 1.,
+        seed: None,
+        logit_bias: Default::default(),
+        strategy: SamplingStrategy::TopKTopP,
+        beam_width: 1,
+        beam_max_tokens: 256,
+    };
+    let logits = [0.1f32, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8];
+    let run = || {
+        seed(42);
+        (0..32)
+            .map(|_| args.random(&logits, &[]))
+            .collect::<Vec<_>>()
+    };
+    let tokens = run();
+    assert_eq!(run(), tokens);
+    assert!(tokens.iter().any(|&t| t != tokens[0]));
+}
+
+#[test]
+fn test_min_tokens_to_keep() {
+    let logits = [10f32, 1., 0.9, 0.8, 0.7, 0.6, 0.5, 0.4];
+    let sampled = |args: &crate::SampleArgs| {
+        // 使用参数中的种子，不受其他测试设置的全局种子影响
+        let mut args = crate::SampleArgs {
+            seed: Some(7),
+            ..args.clone()
+        };
+        let mut tokens = (0..1000)
+            .map(|_| {
+                let tok = args.random(&logits, &[]);
+                args.advance_seed();
+                tok
+            })
+            .collect::<Vec<_>>();
+        tokens.sort_unstable();
+        tokens.dedup();
+        tokens
+    };
+
+    // 首个词的概率超过 top_p，只剩一个候选词
+    let mut args = crate::SampleArgs {
+        temperature: 1.,
+        top_k: usize::MAX,
+        top_p: 0.01,
+        min_p: 0.,
+        min_tokens_to_keep: 1,
+        repetition_penalty: 1.,
+        seed: None,
+        logit_bias: Default::default(),
+        strategy: SamplingStrategy::TopKTopP,
+        beam_width: 1,
+        beam_max_tokens: 256,
+    };
+    assert_eq!(sampled(&args), [0]);
+    // 至少保留 5 个候选词
+    args.temperature = 100.;
+    args.min_tokens_to_keep = 5;
+    assert_eq!(sampled(&args), [0, 1, 2, 3, 4]);
+    // 先应用 top_k
+    args.top_k = 3;
+    assert_eq!(sampled(&args), [0, 1, 2]);
+}
+
+#[test]
+fn test_top_p() {
+    let logits = [0f32; 8];
+    let sampled = |args: &crate::SampleArgs| {
+        // 使用参数中的种子，不受其他测试设置的全局种子影响
+        let mut args = crate::SampleArgs {
+            seed: Some(11),
+            ..args.clone()
+        };
+        let mut tokens = (0..1000)
+            .map(|_| {
+                let tok = args.random(&logits, &[]);
+                args.advance_seed();
+                tok
+            })
+            .collect::<Vec<_>>();
+        tokens.sort_unstable();
+        tokens.dedup();
+        tokens
+    };
+
+    // top_p 为 1 时不筛选
+    let mut args = crate::SampleArgs {
+        temperature: 1.,
+        top_k: usize::MAX,
+        top_p: 1.,
+        min_p: 0.,
+        min_tokens_to_keep: 1,
+        repetition_penalty: 1.,
+        seed: None,
+        logit_bias: Default::default(),
+        strategy: SamplingStrategy::TopKTopP,
+        beam_width: 1,
+        beam_max_tokens: 256,
+    };
+    assert_eq!(sampled(&args), [0, 1, 2, 3, 4, 5, 6, 7]);
+    // 保留累积概率达到 top_p 的词
+    args.top_p = 0.3;
+    assert_eq!(sampled(&args), [0, 1, 2]);
+    // 在 top_k 筛选后的分布上计算 top_p
+    args.top_k = 4;
+    args.top_p = 0.5;
+    assert_eq!(sampled(&args), [0, 1]);
+    // top_p 为 0 时使用贪心采样
+    args.top_p = 0.;
+    assert_eq!(sampled(&args).len(), 1);
+}
+
+#[test]
+fn test_repetition_penalty() {
+    let mut args = crate::SampleArgs::default();
+    assert!(args.validate().is_ok());
+
+    // 贪心采样时，重复的词被压低
+    let logits = [2f32, 1.9, -1., -1.2];
+    assert_eq!(args.random(&logits, &[0]), 0);
+    args.repetition_penalty = 1.2;
+    assert_eq!(args.random(&logits, &[]), 0);
+    assert_eq!(args.random(&logits, &[0, 0, 0]), 1);
+    // 负的 logit 乘以惩罚系数，每个词只惩罚一次
+    let mut penalized = logits;
+    args.penalize(&mut penalized, &[0, 2, 2]);
+    assert_eq!(penalized, [2. / 1.2, 1.9, -1.2, -1.2]);
+
+    args.repetition_penalty = 0.9;
+    assert!(args.validate().is_err());
+    args.repetition_penalty = f32::NAN;
+    assert!(args.validate().is_err());
+}
+
+#[test]
+fn test_seed_field() {
+    let logits = [0.1f32, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8];
+    let generate = |args: &crate::SampleArgs| {
+        let mut args = args.clone();
+        (0..64)
+            .map(|_| {
+                let tok = args.random(&logits, &[]);
+                args.advance_seed();
+                tok
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut args = crate::SampleArgs {
+        temperature: 1.,
+        seed: Some(42),
+        ..Default::default()
+    };
+    assert_eq!(args.clone(), args);
+    let tokens = generate(&args);
+    assert_eq!(generate(&args), tokens);
+    // 同一次生成中的词使用不同的随机数
+    assert!(tokens.iter().any(|&t| t != tokens[0]));
+    // 不同的种子得到不同的结果
+    args.seed = Some(43);
+    assert_ne!(generate(&args), tokens);
+    // 不设置种子时结果随机
+    args.seed = None;
+    assert_ne!(generate(&args), generate(&args));
+}
+
+#[test]
+fn test_logit_bias() {
+    let logits = [1f32, 0.9, 0.8, -1.];
+
+    // 贪心采样
+    let mut args = crate::SampleArgs::default();
+    assert_eq!(args.random(&logits, &[]), 0);
+    args.logit_bias.insert(3, 2.5);
+    assert_eq!(args.random(&logits, &[]), 3);
+    assert_ne!(args, crate::SampleArgs::default());
+
+    // 随机采样，偏置为负无穷的词不会被采样
+    args.temperature = 1.;
+    args.seed = Some(3);
+    args.logit_bias = [(0, f32::NEG_INFINITY), (3, f32::NEG_INFINITY)].into();
+    for _ in 0..1000 {
+        assert!(matches!(args.random(&logits, &[]), 1 | 2));
+        args.advance_seed();
+    }
+    // 偏置在温度缩放之后生效
+    args.temperature = 10.;
+    args.logit_bias = [(1, 1.)].into();
+    let mut biased = logits;
+    args.bias(&mut biased);
+    assert_eq!(biased, [1., 10.9, 0.8, -1.]);
+}
+
+#[test]
+fn test_mirostat() {
+    use crate::SampleArgsError;
+
+    let logits = [0f32, -10., -10., -10.];
+    let mut args = crate::SampleArgs {
+        temperature: 1.,
+        strategy: SamplingStrategy::Mirostat { tau: 1., eta: 0. },
+        ..Default::default()
+    };
+    assert!(!args.is_argmax());
+    assert_eq!(args.validate(), Ok(()));
+    // mu 初始化为 2 tau，惊奇度超过 mu 的词被丢弃
+    let state = MirostatState::default();
+    for _ in 0..100 {
+        assert_eq!(args.random_with_state(&logits, &[], &state), 0);
+    }
+    assert_eq!(state.mu(), Some(2.));
+    // 只保留 1 个词时惊奇度为 0，mu 按学习率增大
+    args.strategy = SamplingStrategy::Mirostat { tau: 1., eta: 0.5 };
+    let fork = state.fork();
+    args.random_with_state(&logits, &[], &state);
+    assert_eq!(state.mu(), Some(2.5));
+    assert_eq!(fork.mu(), Some(2.));
+    // 共享的状态一起更新
+    args.random_with_state(&logits, &[], &state.clone());
+    assert_eq!(state.mu(), Some(3.));
+    state.reset();
+    assert_eq!(state.mu(), None);
+    // mu 收敛到使保留的词的惊奇度接近 tau
+    let logits = (0..64).map(|i| -(i as f32) / 8.).collect::<Vec<_>>();
+    args.seed = Some(7);
+    args.strategy = SamplingStrategy::Mirostat { tau: 3., eta: 0.1 };
+    let surprise = (0..2000)
+        .map(|_| {
+            let tok = args.random_with_state(&logits, &[], &state);
+            args.advance_seed();
+            tok
+        })
+        .skip(1000)
+        .map(|tok| {
+            let sum = logits.iter().map(|x| x.exp()).sum::<f32>();
+            -(logits[tok as usize].exp() / sum).log2()
+        })
+        .sum::<f32>()
+        / 1000.;
+    assert!((2.5..6.).contains(&surprise), "{surprise}");
+
+    args.strategy = SamplingStrategy::Mirostat { tau: 0., eta: 0.1 };
+    assert_eq!(
+        args.validate(),
+        Err(SampleArgsError::Mirostat { tau: 0., eta: 0.1 })
+    );
+}
+
+#[test]
+fn test_typical_p() {
+    use crate::SampleArgsError;
+
+    let logits = [2f32, 1.5, 1., 0.5, 0., -0.5, -1., -4.];
+    let probs = logits.map(|x| (x - 2.).exp());
+    let sum = probs.iter().sum::<f32>();
+    for typical_p in [0.2, 0.5, 0.9] {
+        let kept = typical_set(&probs, typical_p, 1);
+        // 保留的词是词表的真子集，且累积概率达到阈值
+        assert!(kept.len() < logits.len());
+        assert!(kept.iter().map(|&i| probs[i] / sum).sum::<f32>() >= typical_p);
+        // 只会采样到保留的词
+        let mut args = crate::SampleArgs {
+            temperature: 1.,
+            seed: Some(1),
+            strategy: SamplingStrategy::TypicalP(typical_p),
+            ..Default::default()
+        };
+        assert_eq!(args.validate(), Ok(()));
+        for _ in 0..200 {
+            let tok = args.random(&logits, &[]);
+            assert!(kept.contains(&(tok as usize)));
+            args.advance_seed();
+        }
+    }
+    // 概率极小的词惊奇度远离熵，总是最后保留
+    assert!(!typical_set(&probs, 0.99, 1).contains(&7));
+    assert_eq!(typical_set(&probs, 0.2, 3).len(), 3);
+
+    let args = crate::SampleArgs {
+        strategy: SamplingStrategy::TypicalP(1.5),
+        ..Default::default()
+    };
+    assert_eq!(args.validate(), Err(SampleArgsError::TypicalP(1.5)));
+}
+
+#[test]
+fn test_min_p() {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut args = crate::SampleArgs {
+        temperature: 1.,
+        seed: Some(0),
+        ..Default::default()
+    };
+    for _ in 0..200 {
+        let logits = (0..32)
+            .map(|_| rng.gen_range(-4f32..4.))
+            .collect::<Vec<_>>();
+        args.temperature = rng.gen_range(0.1..2.);
+        args.min_p = rng.gen();
+        args.top_k = rng.gen_range(2..40);
+        args.top_p = rng.gen_range(0.1..=1.);
+        assert_eq!(args.validate(), Ok(()));
+        // 温度缩放后，概率低于最大概率的 min_p 倍的词不会被采样
+        let max = logits.iter().copied().fold(f32::MIN, f32::max);
+        for _ in 0..20 {
+            let tok = args.random(&logits, &[]) as usize;
+            let p = ((logits[tok] - max) / args.temperature).exp();
+            assert!(p >= args.min_p, "{p} < {}", args.min_p);
+            args.advance_seed();
+        }
+    }
+    // 相对阈值为 1 时只保留概率最大的词
+    args.min_p = 1.;
+    assert_eq!(args.random(&[0.1f32, 0.5, 0.3], &[]), 1);
+
+    args.min_p = -0.1;
+    assert_eq!(args.validate(), Err(crate::SampleArgsError::MinP(-0.1)));
+}
+
+#[test]
+fn test_log_softmax() {
+    let lp = log_softmax(&[1f32, 2., 3., f32::NEG_INFINITY]);
+    assert!((lp.iter().map(|x| x.exp()).sum::<f32>() - 1.).abs() < 1e-6);
+    assert!((lp[2] - lp[1] - 1.).abs() < 1e-6);
+    assert_eq!(lp[3], f32::NEG_INFINITY);
+    // 大的 logits 不溢出
+    assert!(log_softmax(&[1000f32, 1000.])
+        .iter()
+        .all(|x| (x + 2f32.ln()).abs() < 1e-6));
+
+    let args = crate::SampleArgs {
+        beam_width: 0,
+        ..Default::default()
+    };
+    assert_eq!(args.validate(), Err(crate::SampleArgsError::BeamWidth));
+
+    let args = crate::SampleArgs {
+        beam_max_tokens: 0,
+        ..Default::default()
+    };
+    assert_eq!(args.validate(), Err(crate::SampleArgsError::BeamMaxTokens));
+}
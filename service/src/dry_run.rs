@@ -0,0 +1,39 @@
+/// 生成前对提示词的预估结果，不执行任何推理。
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DryRunResult {
+    /// 提示词占用的 token 数。
+    pub prompt_tokens: usize,
+    /// 提示词和生成内容合计至多占用的 token 数。
+    pub estimated_max_tokens: usize,
+    /// 至多占用的 token 数与模型最大序列长度之比。
+    pub context_utilization: f64,
+    /// 提示词和生成内容能否全部放入上下文。
+    ///
+    /// 为 `false` 时，调用者应在生成前截断提示词。
+    pub fits_in_context: bool,
+}
+
+impl DryRunResult {
+    /// 根据提示词长度、最大生成长度和模型最大序列长度计算预估结果。
+    pub fn new(prompt_tokens: usize, max_new_tokens: usize, max_seq_len: usize) -> Self {
+        let estimated_max_tokens = prompt_tokens + max_new_tokens;
+        Self {
+            prompt_tokens,
+            estimated_max_tokens,
+            context_utilization: estimated_max_tokens as f64 / max_seq_len as f64,
+            fits_in_context: estimated_max_tokens <= max_seq_len,
+        }
+    }
+}
+
+#[test]
+fn test_dry_run() {
+    let ans = DryRunResult::new(100, 156, 512);
+    assert_eq!(ans.estimated_max_tokens, 256);
+    assert_eq!(ans.context_utilization, 0.5);
+    assert!(ans.fits_in_context);
+
+    let ans = DryRunResult::new(500, 13, 512);
+    assert!(ans.context_utilization > 1.);
+    assert!(!ans.fits_in_context);
+}
@@ -0,0 +1,93 @@
+use common::utok;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+/// 以提示词 token 序列为键保存生成结果的缓存，满时淘汰最久未使用的条目。
+///
+/// 条目同时保存完整的提示词，哈希碰撞不会命中错误的结果。
+pub(crate) struct ExactMatchCache {
+    capacity: usize,
+    tick: u64,
+    store: HashMap<u64, Entry>,
+}
+
+struct Entry {
+    prompt: Vec<utok>,
+    response: Vec<utok>,
+    last_used: u64,
+}
+
+impl ExactMatchCache {
+    #[inline]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            store: HashMap::with_capacity(capacity),
+        }
+    }
+
+    /// 查询 `prompt` 的生成结果。
+    pub fn get(&mut self, prompt: &[utok]) -> Option<Vec<utok>> {
+        self.tick += 1;
+        let entry = self
+            .store
+            .get_mut(&key(prompt))
+            .filter(|e| e.prompt == prompt)?;
+        entry.last_used = self.tick;
+        Some(entry.response.clone())
+    }
+
+    /// 保存 `prompt` 的生成结果。
+    pub fn insert(&mut self, prompt: &[utok], response: Vec<utok>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        let key = key(prompt);
+        if !self.store.contains_key(&key) && self.store.len() >= self.capacity {
+            let lru = self
+                .store
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(&k, _)| k)
+                .unwrap();
+            self.store.remove(&lru);
+        }
+        self.store.insert(
+            key,
+            Entry {
+                prompt: prompt.to_vec(),
+                response,
+                last_used: self.tick,
+            },
+        );
+    }
+}
+
+#[inline]
+fn key(prompt: &[utok]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    prompt.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn test_lru() {
+    let mut cache = ExactMatchCache::new(2);
+    cache.insert(&[1, 2], vec![3]);
+    cache.insert(&[4, 5], vec![6]);
+    assert_eq!(cache.get(&[1, 2]), Some(vec![3]));
+    // [4, 5] 最久未使用，被淘汰
+    cache.insert(&[7], vec![8]);
+    assert_eq!(cache.get(&[4, 5]), None);
+    assert_eq!(cache.get(&[1, 2]), Some(vec![3]));
+    assert_eq!(cache.get(&[7]), Some(vec![8]));
+    assert_eq!(cache.get(&[1]), None);
+
+    let mut cache = ExactMatchCache::new(0);
+    cache.insert(&[1], vec![2]);
+    assert_eq!(cache.get(&[1]), None);
+}
@@ -0,0 +1,58 @@
+/// 服务的健康状况，供负载均衡器探测。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Status {
+    /// 服务正常。
+    Healthy,
+    /// 服务可用，但设备空闲内存不足或正在关闭。
+    Degraded,
+    /// 推理线程已崩溃或退出，服务不可用。
+    Unhealthy,
+}
+
+/// 健康检查的结果。
+#[derive(Clone, Debug)]
+pub struct HealthStatus {
+    /// 服务的健康状况。
+    pub status: Status,
+    /// 设备的空闲内存字节数，模型不提供时为 `None`。
+    pub gpu_memory_free: Option<usize>,
+    /// 等待组批的推理任务数。
+    pub queue_depth: usize,
+    /// 存活的会话数。
+    pub active_sessions: usize,
+    /// 推理线程是否仍持有模型并可执行推理。
+    pub model_loaded: bool,
+}
+
+/// 空闲内存低于这个比例时服务降级。
+const MIN_FREE_RATIO: f64 = 0.1;
+
+impl Status {
+    pub(crate) fn judge(
+        model_loaded: bool,
+        draining: bool,
+        memory: Option<(usize, usize)>,
+    ) -> Self {
+        if !model_loaded {
+            Self::Unhealthy
+        } else if draining
+            || memory.is_some_and(|(free, total)| (free as f64) < total as f64 * MIN_FREE_RATIO)
+        {
+            Self::Degraded
+        } else {
+            Self::Healthy
+        }
+    }
+}
+
+#[test]
+fn test_judge() {
+    assert_eq!(Status::judge(true, false, None), Status::Healthy);
+    assert_eq!(Status::judge(true, false, Some((20, 100))), Status::Healthy);
+    assert_eq!(Status::judge(true, false, Some((9, 100))), Status::Degraded);
+    assert_eq!(Status::judge(true, true, None), Status::Degraded);
+    assert_eq!(
+        Status::judge(false, false, Some((90, 100))),
+        Status::Unhealthy
+    );
+}
@@ -1,21 +1,79 @@
 #![deny(warnings)]
 
+mod dry_run;
+mod exact_match;
+mod health;
+mod metrics;
+mod multi;
 mod session;
+mod state;
 mod template;
 
 use causal_lm::{CausalLM, SampleArgs};
-use session::{Dispatcher, Generator};
-use std::{fmt::Debug, path::Path, sync::Arc};
+use common::utok;
+use exact_match::ExactMatchCache;
+use log::warn;
+use session::{Dispatcher, Generator, SessionRegistry};
+use std::{
+    fmt::Debug,
+    path::Path,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 use template::Template;
-use tokenizer::{BPECommonNormalizer, Normalizer, Tokenizer, VocabTxt, BPE};
+use tokenizer::{BPECommonNormalizer, Normalizer, Tokenizer, TokenizerError, VocabTxt, BPE};
 use tokio::task::JoinHandle;
 
-pub use session::{BusySession, ChatError, Session};
+pub use dry_run::DryRunResult;
+pub use health::{HealthStatus, Status};
+pub use metrics::{InferenceMetrics, ServiceMetrics};
+pub use multi::MultiModelService;
+pub use session::{
+    BusySession, ChatError, ChatMessage, ChatRole, ChatSession, Session, SessionInfo,
+};
+pub use state::{ServiceError, ServiceState};
 
 /// 对话服务。
 pub struct Service<M: CausalLM> {
     component: Arc<ServiceComponent<M>>,
     pub default_sample: SampleArgs,
+    deterministic: Option<DeterministicConfig>,
+}
+
+/// 确定性推理的配置。
+#[derive(Clone, Copy, Debug)]
+pub struct DeterministicConfig {
+    /// 没有指定种子的采样使用的种子，相同的输入将生成相同的结果。
+    pub seed: u64,
+    /// 每轮推理只处理一个任务，使结果不受同时推理的其他任务影响，吞吐量将显著下降。
+    pub strict: bool,
+}
+
+/// 推理任务的组批策略。
+#[derive(Clone, Copy, Debug)]
+pub struct BatchingPolicy {
+    /// 一次推理中最多包含的任务数，超出的任务将留到下一轮推理。
+    ///
+    /// 束搜索任务按活跃的假设数计入，束宽不超过这个值。
+    pub max_batch_size: usize,
+    /// 一次推理中每个任务最多计算的查询 token 数，更长的查询将分块在后续推理中完成。
+    pub prefill_chunk_tokens: usize,
+    /// 一次推理中最多采样的任务数，超出的任务将留到下一轮推理。
+    pub max_decode_per_step: usize,
+}
+
+impl Default for BatchingPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            max_batch_size: 32,
+            prefill_chunk_tokens: usize::MAX,
+            max_decode_per_step: 32,
+        }
+    }
 }
 
 /// 服务中不变的组件，将在所有会话之间共享。
@@ -26,6 +84,28 @@ struct ServiceComponent<M: CausalLM> {
     tokenizer: Box<dyn Tokenizer + Send + Sync>,
     normalizer: Box<dyn Normalizer + Send + Sync>,
     template: Box<dyn Template + Send + Sync>,
+    sessions: SessionRegistry,
+    max_history_messages: AtomicUsize,
+    exact_match: Mutex<Option<ExactMatchCache>>,
+    state: AtomicU8,
+}
+
+impl<M: CausalLM> ServiceComponent<M> {
+    /// 服务正在关闭时不再接受新的会话和推理任务。
+    #[inline]
+    fn check_running(&self) -> Result<(), ServiceError> {
+        match ServiceState::from(self.state.load(Relaxed)) {
+            ServiceState::Running => Ok(()),
+            ServiceState::Draining | ServiceState::Stopped => Err(ServiceError::ShuttingDown),
+        }
+    }
+
+    /// 编码套用模板之后的文本，由模板决定是否加入句子开始符和结束符。
+    fn encode_templated(&self, text: &str) -> Vec<utok> {
+        let text = self.normalizer.encode(text);
+        self.tokenizer
+            .encode_with_options(&text, self.template.add_bos(), self.template.add_eos())
+    }
 }
 
 impl<M: CausalLM> Drop for ServiceComponent<M> {
@@ -42,82 +122,722 @@ where
     M::Storage: Send,
     M::Error: Debug,
 {
+    #[inline]
     pub fn load(model_dir: impl AsRef<Path>, meta: M::Meta) -> (Self, JoinHandle<()>) {
-        let handle = Arc::new(Dispatcher::from(M::load(&model_dir, meta).unwrap()));
+        Self::load_with_policy(model_dir, meta, Default::default())
+    }
+
+    pub fn load_with_policy(
+        model_dir: impl AsRef<Path>,
+        meta: M::Meta,
+        policy: BatchingPolicy,
+    ) -> (Self, JoinHandle<()>) {
+        assert!(policy.max_batch_size > 0);
+        assert!(policy.prefill_chunk_tokens > 0);
+        assert!(policy.max_decode_per_step > 0);
+        let model = M::load(&model_dir, meta).unwrap();
+        let handle = Arc::new(Dispatcher::new(model, policy));
+        let (tokenizer, normalizer) = tokenizer(&model_dir);
         (
             Self {
                 component: Arc::new(ServiceComponent {
                     handle: handle.clone(),
-                    tokenizer: tokenizer(&model_dir),
-                    normalizer: normalizer(&model_dir),
+                    tokenizer,
+                    normalizer,
                     template: template(model_dir),
+                    sessions: Default::default(),
+                    max_history_messages: AtomicUsize::new(usize::MAX),
+                    exact_match: Default::default(),
+                    state: AtomicU8::new(ServiceState::Running as _),
                 }),
                 default_sample: Default::default(),
+                deterministic: None,
             },
             tokio::task::spawn_blocking(move || handle.run()),
         )
     }
+
+    /// 以确定性推理的配置加载模型。
+    pub fn load_deterministic(
+        model_dir: impl AsRef<Path>,
+        meta: M::Meta,
+        policy: BatchingPolicy,
+        config: DeterministicConfig,
+    ) -> (Self, JoinHandle<()>) {
+        let policy = if config.strict {
+            BatchingPolicy {
+                max_batch_size: 1,
+                max_decode_per_step: 1,
+                ..policy
+            }
+        } else {
+            policy
+        };
+        let (mut service, handle) = Self::load_with_policy(model_dir, meta, policy);
+        service.deterministic = Some(config);
+        (service, handle)
+    }
 }
 
 impl<M: CausalLM> Service<M> {
     /// 从对话服务启动一个会话。
     #[inline]
-    pub fn launch(&self) -> Session<M> {
+    pub fn launch(&self) -> Result<Session<M>, ServiceError> {
+        self.check_running()?;
         let mut session: Session<M> = self.component.clone().into();
-        session.sample = self.default_sample.clone();
-        session
+        session.sample = self.sample(None);
+        Ok(session)
+    }
+
+    /// 从对话服务启动一个多轮对话会话，可选地以 `system` 作为系统提示词。
+    #[inline]
+    pub fn launch_chat(&self, system: Option<&str>) -> Result<ChatSession<M>, ServiceError> {
+        self.launch()
+            .map(|session| ChatSession::new(session, system))
     }
 
     /// 从对话服务启动一个文本生成器。
     #[inline]
-    pub fn generate(&self, prompt: impl AsRef<str>, sample: Option<SampleArgs>) -> Generator<M> {
-        let sample = sample.unwrap_or_else(|| self.default_sample.clone());
-        Generator::new(self.component.clone(), prompt, sample)
+    pub fn generate(
+        &self,
+        prompt: impl AsRef<str>,
+        sample: Option<SampleArgs>,
+    ) -> Result<Generator<M>, ServiceError> {
+        self.check_running()?;
+        let sample = self.sample(sample);
+        Ok(Generator::new(self.component.clone(), prompt, sample))
+    }
+
+    /// 确定推理使用的采样参数，确定性推理时为没有种子的参数补充种子。
+    fn sample(&self, sample: Option<SampleArgs>) -> SampleArgs {
+        let mut sample = sample.unwrap_or_else(|| self.default_sample.clone());
+        if let Some(config) = self.deterministic {
+            sample.seed.get_or_insert(config.seed);
+        }
+        sample
+    }
+
+    /// 预估提示词的开销，只编码提示词而不执行推理。
+    ///
+    /// 返回提示词的 token 数，以及再生成 `max_new_tokens` 个 token 后能否放入模型的上下文。
+    pub fn dry_run(&self, prompt: &str, max_new_tokens: usize) -> DryRunResult {
+        let component = &*self.component;
+        let prompt = component.template.normalize(prompt);
+        let prompt_tokens = component.encode_templated(&prompt).len();
+        let max_seq_len = component.handle.model.max_seq_len() as usize;
+        DryRunResult::new(prompt_tokens, max_new_tokens, max_seq_len)
+    }
+
+    /// 模型所在设备的空闲和总内存字节数，无法获取时返回 `None`。
+    #[inline]
+    pub fn memory_info(&self) -> Option<(usize, usize)> {
+        self.component.handle.model.memory_info()
+    }
+
+    /// 服务当前的状态。
+    #[inline]
+    pub fn state(&self) -> ServiceState {
+        self.component.state.load(Relaxed).into()
+    }
+
+    /// 平滑关闭服务。
+    ///
+    /// 服务立即停止接受新的会话，然后至多等待 `timeout` 使在途的推理任务完成，最后通知推理线程退出。
+    /// 推理线程退出后，[`Service::load`] 返回的 [`JoinHandle`] 将结束。
+    ///
+    /// 返回在途任务是否在超时前全部完成。等待期间阻塞当前线程，异步上下文中应使用 [`Service::graceful_shutdown_async`]。
+    pub fn graceful_shutdown(&self, timeout: Duration) -> bool {
+        self.component
+            .state
+            .store(ServiceState::Draining as _, Relaxed);
+        let drained = self.component.handle.wait_idle(timeout);
+        self.stop();
+        drained
+    }
+
+    /// [`Service::graceful_shutdown`] 的异步版本，需要运行时启用计时器。
+    pub async fn graceful_shutdown_async(&self, timeout: Duration) -> bool {
+        self.component
+            .state
+            .store(ServiceState::Draining as _, Relaxed);
+        let drained = tokio::time::timeout(timeout, self.component.handle.idle())
+            .await
+            .is_ok();
+        self.stop();
+        drained
+    }
+
+    #[inline]
+    fn stop(&self) {
+        self.component.handle.stop();
+        self.component
+            .state
+            .store(ServiceState::Stopped as _, Relaxed);
+    }
+
+    #[inline]
+    fn check_running(&self) -> Result<(), ServiceError> {
+        self.component.check_running()
+    }
+
+    /// 设置会话推理时保留的最大历史句子数，超出的部分将从最早的对话开始丢弃。
+    #[inline]
+    pub fn set_max_history_messages(&self, n: usize) {
+        self.component.max_history_messages.store(n, Relaxed);
+    }
+
+    /// 启用容量为 `capacity` 的精确匹配缓存。
+    ///
+    /// 启用后，以贪心采样生成的文本按提示词 token 序列缓存，相同的提示词将直接重放缓存的结果而不执行推理。
+    /// 其他采样参数的结果不确定，不会读写缓存。重复启用将清空缓存。
+    #[inline]
+    pub fn enable_exact_match_cache(&self, capacity: usize) {
+        *self.component.exact_match.lock().unwrap() = Some(ExactMatchCache::new(capacity));
+    }
+
+    /// 获取服务启动以来的推理统计信息。
+    #[inline]
+    pub fn metrics(&self) -> ServiceMetrics {
+        self.component.handle.metrics.lock().unwrap().clone()
+    }
+
+    /// 列出服务中所有存活的会话。
+    #[inline]
+    pub fn list_sessions(&self) -> Vec<SessionInfo> {
+        self.component.sessions.list()
+    }
+
+    /// 检查服务的健康状况。
+    ///
+    /// 推理线程崩溃或已停止时不可用；正在关闭或设备空闲内存低于 10% 时降级。
+    pub fn health_check(&self) -> HealthStatus {
+        let ServiceComponent {
+            handle, sessions, ..
+        } = &*self.component;
+        let state = self.state();
+        let model_loaded = !handle.panicked() && state != ServiceState::Stopped;
+        let memory = handle.model.memory_info();
+        HealthStatus {
+            status: Status::judge(model_loaded, state == ServiceState::Draining, memory),
+            gpu_memory_free: memory.map(|(free, _)| free),
+            queue_depth: handle.queue_depth(),
+            active_sessions: sessions.len(),
+            model_loaded,
+        }
     }
 }
 
-#[test]
-fn test() {
-    use colored::{Color, Colorize};
-    use std::{io::Write, iter::zip};
-    use tokio::{runtime::Builder, task::JoinSet};
+/// 在测试模型上运行测试，没有测试模型时直接跳过。
+#[cfg(test)]
+fn with_runtime(multi_thread: bool, f: impl FnOnce(&tokio::runtime::Runtime, &Path)) {
+    use tokio::runtime::Builder;
 
     let Some(model_dir) = common::test_model::find() else {
         return;
     };
     println!("model_dir: {}", model_dir.display());
 
-    let runtime = Builder::new_current_thread().build().unwrap();
+    let runtime = if multi_thread {
+        Builder::new_multi_thread().enable_time().build().unwrap()
+    } else {
+        Builder::new_current_thread().enable_time().build().unwrap()
+    };
     let _rt = runtime.enter();
+    f(&runtime, &model_dir);
+    runtime.shutdown_background();
+}
+
+/// 加载测试模型的服务并运行测试，没有测试模型时直接跳过。
+#[cfg(test)]
+fn with_service(f: impl FnOnce(&tokio::runtime::Runtime, &Service<llama_cpu::Transformer>)) {
+    with_runtime(false, |runtime, model_dir| {
+        let (service, _handle) = Service::<llama_cpu::Transformer>::load(model_dir, ());
+        f(runtime, &service);
+    });
+}
+
+#[test]
+fn test() {
+    use colored::{Color, Colorize};
+    use std::{io::Write, iter::zip};
+    use tokio::task::JoinSet;
+
+    with_service(|runtime, service| {
+        let mut set = JoinSet::new();
+        let tasks = vec![
+            ("Say \"Hi\" to me.", Color::Yellow),
+            ("Hi", Color::Red),
+            ("Where is the capital of France?", Color::Green),
+        ];
+
+        let sessions = tasks
+            .iter()
+            .map(|_| service.launch().unwrap())
+            .collect::<Vec<_>>();
 
-    let (service, _handle) = Service::<llama_cpu::Transformer>::load(model_dir, ());
+        for ((prompt, color), mut session) in zip(tasks, sessions) {
+            set.spawn(async move {
+                session.extend([prompt]);
+                let mut busy = session.chat().unwrap();
+                while let Some(s) = busy.decode().await {
+                    print!("{}", s.color(color));
+                    std::io::stdout().flush().unwrap();
+                }
+            });
+        }
 
-    let mut set = JoinSet::new();
-    let tasks = vec![
-        ("Say \"Hi\" to me.", Color::Yellow),
-        ("Hi", Color::Red),
-        ("Where is the capital of France?", Color::Green),
-    ];
+        runtime.block_on(async { while set.join_next().await.is_some() {} });
+        let metrics = service.metrics();
+        assert!(metrics.tokens_per_sec > 0.);
+        assert!(metrics.first_token_p50().is_some());
+    });
+}
+
+#[test]
+fn test_batching_policy() {
+    use std::iter::zip;
+    use tokio::task::JoinSet;
+
+    with_runtime(false, |runtime, model_dir| {
+        let prompts = ["Hi", "Where is the capital of France?", "Say \"Hi\" to me."];
+        let generate = |policy: BatchingPolicy| {
+            let (service, _handle) =
+                Service::<llama_cpu::Transformer>::load_with_policy(model_dir, (), policy);
+            let mut set = JoinSet::new();
+            for (i, prompt) in prompts.into_iter().enumerate() {
+                let mut session = service.launch().unwrap();
+                set.spawn(async move {
+                    session.extend([prompt]);
+                    let mut busy = session.chat().unwrap();
+                    let mut ans = String::new();
+                    while let Some(s) = busy.decode().await {
+                        ans.push_str(&s);
+                    }
+                    (i, ans)
+                });
+            }
+            let mut ans = vec![String::new(); prompts.len()];
+            runtime.block_on(async {
+                while let Some(res) = set.join_next().await {
+                    let (i, s) = res.unwrap();
+                    ans[i] = s;
+                }
+            });
+            ans
+        };
+
+        // 组批数少于会话数、查询分块、每轮只采样一个任务，贪心采样的结果不变且没有会话饿死
+        let expected = generate(Default::default());
+        let actual = generate(BatchingPolicy {
+            max_batch_size: prompts.len() - 1,
+            prefill_chunk_tokens: 4,
+            max_decode_per_step: 1,
+        });
+        for (expected, actual) in zip(expected, actual) {
+            assert!(!actual.is_empty());
+            assert_eq!(actual, expected);
+        }
+    });
+}
+
+#[test]
+fn test_list_sessions() {
+    with_service(|_, service| {
+        let prompts = ["Hi", "Where is the capital of France?", "Say \"Hi\" to me."];
+        let sessions = prompts
+            .iter()
+            .map(|prompt| {
+                let mut session = service.launch().unwrap();
+                session.extend([*prompt]);
+                session
+            })
+            .collect::<Vec<_>>();
 
-    let sessions = tasks.iter().map(|_| service.launch()).collect::<Vec<_>>();
+        let infos = service.list_sessions();
+        assert_eq!(infos.len(), 3);
+        for (info, session) in std::iter::zip(&infos, &sessions) {
+            assert_eq!(info.id, session.id());
+            assert!(info.token_count > 0);
+            assert_eq!(info.cached_tokens, 0);
+        }
 
-    for ((prompt, color), mut session) in zip(tasks, sessions) {
-        set.spawn(async move {
-            session.extend([prompt]);
-            let mut busy = session.chat();
+        drop(sessions);
+        assert!(service.list_sessions().is_empty());
+    });
+}
+
+#[test]
+fn test_prefill() {
+    with_service(|runtime, service| {
+        let mut session = service.launch().unwrap();
+        assert!(runtime.block_on(session.prefill("Paris")).is_err());
+
+        session.extend(["Where is the capital of France?"]);
+        runtime
+            .block_on(session.prefill("The capital of France is"))
+            .unwrap();
+        let info = service.list_sessions().pop().unwrap();
+        assert!(info.cached_tokens > info.token_count);
+
+        let dialog_pos = session.dialog_pos();
+        runtime.block_on(async {
+            let mut busy = session.chat().unwrap();
             while let Some(s) = busy.decode().await {
-                print!("{}", s.color(color));
-                std::io::stdout().flush().unwrap();
+                print!("{s}");
             }
         });
-    }
+        assert_eq!(session.dialog_pos(), dialog_pos + 1);
+    });
+}
 
-    runtime.block_on(async { while set.join_next().await.is_some() {} });
-    runtime.shutdown_background();
+#[test]
+fn test_set_system_prompt() {
+    with_service(|runtime, service| {
+        let mut session = service.launch().unwrap();
+        session.set_system_prompt("You are a helpful assistant.");
+        session.extend(["Where is the capital of France?"]);
+        runtime.block_on(async {
+            let mut busy = session.chat().unwrap();
+            for _ in 0..20 {
+                if busy.decode().await.is_none() {
+                    break;
+                }
+            }
+        });
+        assert_eq!(session.dialog_pos(), 2);
+
+        let system = "You are a pirate.";
+        session.set_system_prompt(system);
+        let system = service.component.template.apply_system(system);
+        let len = service.component.encode_templated(&system).len();
+
+        assert_eq!(session.dialog_pos(), 0);
+        let info = service.list_sessions().pop().unwrap();
+        assert_eq!(info.id, session.id());
+        assert_eq!(info.token_count, len);
+    });
+}
+
+#[test]
+fn test_chat_session() {
+    with_service(|runtime, service| {
+        let mut chat = service
+            .launch_chat(Some("You are a helpful assistant."))
+            .unwrap();
+        assert!(runtime
+            .block_on(chat.generate_assistant_response())
+            .is_err());
+        for prompt in ["Hi", "Where is the capital of France?", "Thanks."] {
+            chat.add_user_message(prompt).unwrap();
+            assert!(chat.add_user_message(prompt).is_err());
+            let answer = runtime
+                .block_on(chat.generate_assistant_response())
+                .unwrap();
+            println!("{answer}");
+        }
+
+        let history = chat.get_history();
+        assert_eq!(history.len(), 6);
+        for (i, message) in history.iter().enumerate() {
+            let role = if i % 2 == 0 {
+                ChatRole::User
+            } else {
+                ChatRole::Assistant
+            };
+            assert_eq!(message.role, role);
+        }
+        assert_eq!(chat.session().dialog_pos(), 6);
+    });
+}
+
+#[test]
+fn test_get_tokens() {
+    with_service(|runtime, service| {
+        let mut session = service.launch().unwrap();
+        assert!(session.get_tokens().is_empty());
+        session.extend(["Where is the capital of France?"]);
+        let prompt = session.get_tokens();
+        assert!(!prompt.is_empty());
+
+        runtime.block_on(async {
+            let mut busy = session.chat().unwrap();
+            for _ in 0..20 {
+                if busy.decode().await.is_none() {
+                    break;
+                }
+            }
+        });
+        let tokens = session.get_tokens();
+        assert!(tokens.starts_with(&prompt));
+        assert!(tokens.len() > prompt.len());
+        let info = service.list_sessions().pop().unwrap();
+        assert_eq!(tokens.len(), info.token_count);
+        assert!(session.get_text().contains("France"));
+    });
+}
+
+#[test]
+fn test_exact_match_cache() {
+    with_service(|runtime, service| {
+        service.enable_exact_match_cache(4);
+
+        let generate = || {
+            let mut generator = service.generate("Once upon a time,", None).unwrap();
+            runtime.block_on(async {
+                let mut text = String::new();
+                while let Some(s) = generator.decode().await {
+                    text.push_str(&s);
+                }
+                text
+            })
+        };
+        let first = generate();
+        let decoded = service.metrics().decode_tokens;
+        let second = generate();
+        assert_eq!(first, second);
+        // 第二次生成由缓存重放，没有执行推理
+        assert_eq!(service.metrics().decode_tokens, decoded);
+    });
 }
 
+#[test]
+fn test_beam_search() {
+    with_runtime(true, |runtime, model_dir| {
+        let (service, _handle) = Service::<llama_cpu::Transformer>::load(model_dir, ());
+        let chat = |max_tokens| {
+            let mut session = service.launch().unwrap();
+            session.sample.beam_width = 3;
+            session.sample.beam_max_tokens = max_tokens;
+            session.extend(["Hi"]);
+            let pieces = runtime.block_on(async {
+                let mut busy = session.chat().unwrap();
+                let mut pieces = Vec::new();
+                while let Some(s) = busy.decode().await {
+                    pieces.push(s);
+                }
+                pieces
+            });
+            // 得分最高的假设作为回答加入对话
+            assert_eq!(session.dialog_pos(), 2);
+            pieces
+        };
+        let first = chat(64);
+        assert!(!first.is_empty());
+        // 束搜索的结果是确定的
+        assert_eq!(chat(64), first);
+        // 每个词至多解码出一段文本
+        assert!(chat(2).len() <= 2);
+    });
+}
+
+#[test]
+fn test_deterministic() {
+    with_runtime(false, |runtime, model_dir| {
+        let config = DeterministicConfig {
+            seed: 42,
+            strict: true,
+        };
+        let (mut service, _handle) = Service::<llama_cpu::Transformer>::load_deterministic(
+            model_dir,
+            (),
+            Default::default(),
+            config,
+        );
+        // 随机采样，结果只由种子决定
+        service.default_sample.temperature = 0.9;
+        service.default_sample.top_k = 50;
+
+        let generate = || {
+            let mut generator = service.generate("Once upon a time,", None).unwrap();
+            runtime.block_on(async {
+                let mut text = String::new();
+                while let Some(s) = generator.decode().await {
+                    text.push_str(&s);
+                }
+                text
+            })
+        };
+        let first = generate();
+        assert!(!first.is_empty());
+        assert_eq!(generate(), first);
+    });
+}
+
+#[test]
+fn test_graceful_shutdown() {
+    with_runtime(true, |runtime, model_dir| {
+        let (service, handle) = Service::<llama_cpu::Transformer>::load(model_dir, ());
+        assert_eq!(service.state(), ServiceState::Running);
+
+        let mut session = service.launch().unwrap();
+        let mut idle = service.launch().unwrap();
+        session.extend(["Hi"]);
+        idle.extend(["Hi"]);
+        let decoding = runtime.spawn(async move {
+            let mut busy = session.chat().unwrap();
+            while busy.decode().await.is_some() {}
+        });
+        // 等待任务进入推理，确保关闭时有在途的任务
+        while service.component.handle.inflight() == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let shutdown = std::thread::scope(|s| {
+            let shutdown = s.spawn(|| service.graceful_shutdown(Duration::from_secs(60)));
+            while service.state() == ServiceState::Running {
+                std::thread::yield_now();
+            }
+            // 已有的会话在等待期间也不能启动新的推理
+            assert!(matches!(idle.chat(), Err(ServiceError::ShuttingDown)));
+            assert_eq!(
+                runtime.block_on(idle.prefill("Hello")),
+                Err(ChatError::ShuttingDown)
+            );
+            shutdown.join().unwrap()
+        });
+        assert!(shutdown);
+        assert_eq!(service.state(), ServiceState::Stopped);
+        assert!(matches!(idle.chat(), Err(ServiceError::ShuttingDown)));
+        assert!(matches!(service.launch(), Err(ServiceError::ShuttingDown)));
+        assert!(matches!(
+            service.generate("Hi", None),
+            Err(ServiceError::ShuttingDown)
+        ));
+
+        runtime.block_on(async {
+            decoding.await.unwrap();
+            handle.await.unwrap();
+        });
+    });
+}
+
+#[test]
+fn test_graceful_shutdown_async() {
+    with_service(|runtime, service| {
+        let mut session = service.launch().unwrap();
+        session.extend(["Hi"]);
+        let decoding = runtime.spawn(async move {
+            let mut busy = session.chat().unwrap();
+            while busy.decode().await.is_some() {}
+        });
+        assert!(runtime.block_on(service.graceful_shutdown_async(Duration::from_secs(60))));
+        assert_eq!(service.state(), ServiceState::Stopped);
+        assert_eq!(service.component.handle.inflight(), 0);
+        runtime.block_on(async { decoding.await.unwrap() });
+    });
+}
+
+#[test]
+fn test_multi_model() {
+    use tokio::task::JoinSet;
+
+    with_runtime(false, |runtime, model_dir| {
+        let prompt = "Once upon a time,";
+        let decode = |mut generator: Generator<llama_cpu::Transformer>| async move {
+            let mut text = String::new();
+            while let Some(s) = generator.decode().await {
+                text.push_str(&s);
+            }
+            text
+        };
+        // 单独加载一个模型得到的参考结果
+        let expected = {
+            let (service, _handle) = Service::<llama_cpu::Transformer>::load(model_dir, ());
+            runtime.block_on(decode(service.generate(prompt, None).unwrap()))
+        };
+
+        let mut service = MultiModelService::<llama_cpu::Transformer>::default();
+        // 两个模型使用不同的采样参数
+        let random = SampleArgs {
+            temperature: 0.9,
+            top_k: 50,
+            seed: Some(42),
+            ..Default::default()
+        };
+        assert_eq!(
+            service.add_model("a", model_dir, (), Default::default()),
+            Ok(())
+        );
+        assert_eq!(service.add_model("b", model_dir, (), random), Ok(()));
+        assert_eq!(
+            service.add_model("a", model_dir, (), Default::default()),
+            Err(ServiceError::ModelExists)
+        );
+        assert!(matches!(
+            service.launch("c"),
+            Err(ServiceError::NoSuchModel)
+        ));
+        // cpu 模型无法获取设备内存，不计入内存占用
+        assert_eq!(service.model_memory("a"), None);
+        assert_eq!(service.memory_usage(), 0);
+
+        let mut set = JoinSet::new();
+        for name in ["a", "b"] {
+            let generator = service.generate(name, prompt, None).unwrap();
+            set.spawn(async move { (name, decode(generator).await) });
+        }
+        runtime.block_on(async {
+            while let Some(res) = set.join_next().await {
+                let (name, text) = res.unwrap();
+                assert!(!text.is_empty());
+                // 模型 a 的结果不受同时以其他参数推理的模型 b 影响
+                if name == "a" {
+                    assert_eq!(text, expected);
+                }
+            }
+        });
+        // 两个模型分别执行推理
+        for name in ["a", "b"] {
+            assert!(service.get(name).unwrap().metrics().decode_tokens > 0);
+        }
+
+        let handle = service.remove_model("a", Duration::from_secs(60)).unwrap();
+        runtime.block_on(async { handle.await.unwrap() });
+        assert!(service.remove_model("a", Duration::ZERO).is_none());
+        assert!(service.launch("b").is_ok());
+    });
+}
+
+#[test]
+fn test_health_check() {
+    with_service(|_, service| {
+        let _session = service.launch().unwrap();
+        let health = service.health_check();
+        assert_eq!(health.status, Status::Healthy);
+        assert!(health.model_loaded);
+        assert_eq!(health.gpu_memory_free, None);
+        assert_eq!(health.queue_depth, 0);
+        assert_eq!(health.active_sessions, 1);
+
+        // 模拟推理线程 panic
+        let handle = service.component.handle.clone();
+        let panicked = std::thread::spawn(move || {
+            let _guard = handle.panic_guard();
+            panic!("inference thread panicked");
+        })
+        .join();
+        assert!(panicked.is_err());
+        let health = service.health_check();
+        assert_eq!(health.status, Status::Unhealthy);
+        assert!(!health.model_loaded);
+    });
+}
+
+/// 选择对话模板。
+///
+/// 优先按 tokenizer_config.json 中的 `chat_template` 选择，没有可识别的模板时路径含有 tinyllama 的模型使用 TinyLlama 模板，其他模型使用 CPM 模板。
 fn template(model_dir: impl AsRef<Path>) -> Box<dyn Template + Send + Sync> {
-    let path: String = model_dir.as_ref().display().to_string();
+    let model_dir = model_dir.as_ref();
+    if let Some(chat_template) = chat_template(model_dir) {
+        if chat_template.contains("<|user|>") {
+            return Box::new(template::ChatTinyLlama);
+        }
+        if chat_template.contains("<用户>") {
+            return Box::new(template::ChatCPM);
+        }
+        warn!("unknown chat_template in tokenizer_config.json, fall back to the default template");
+    }
+    let path: String = model_dir.display().to_string();
     let path = path.to_ascii_lowercase();
     if path.contains("tinyllama") {
         Box::new(template::ChatTinyLlama)
@@ -126,30 +846,87 @@ fn template(model_dir: impl AsRef<Path>) -> Box<dyn Template + Send + Sync> {
     }
 }
 
-fn normalizer(model_dir: impl AsRef<Path>) -> Box<dyn Normalizer + Send + Sync> {
-    use std::io::ErrorKind::NotFound;
-    match BPE::from_model_file(model_dir.as_ref().join("tokenizer.model")) {
-        Ok(_) => return Box::new(BPECommonNormalizer {}),
-        Err(e) if e.kind() == NotFound => {}
-        Err(e) => panic!("{e:?}"),
-    }
-    match VocabTxt::from_txt_file(model_dir.as_ref().join("vocabs.txt")) {
-        Ok(_) => return Box::new(()),
-        Err(e) if e.kind() == NotFound => {}
-        Err(e) => panic!("{e:?}"),
+/// 读取 tokenizer_config.json 中的 `chat_template`，文件或字段不存在时返回 `None`。
+fn chat_template(model_dir: &Path) -> Option<String> {
+    let config = match std::fs::read_to_string(model_dir.join("tokenizer_config.json")) {
+        Ok(config) => config,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("failed to read tokenizer_config.json: {e}");
+            return None;
+        }
+    };
+    match serde_json::from_str::<serde_json::Value>(&config) {
+        Ok(config) => config.get("chat_template")?.as_str().map(String::from),
+        Err(e) => {
+            warn!("failed to parse tokenizer_config.json: {e}");
+            None
+        }
     }
-    panic!("Tokenizer file not found");
 }
 
-fn tokenizer(model_dir: impl AsRef<Path>) -> Box<dyn Tokenizer + Send + Sync> {
+#[test]
+fn test_template() {
+    let dir = std::env::temp_dir().join("transformer-rs-chat-template");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let chat = |dir: &Path| template(dir).apply_chat("Hi").into_owned();
+
+    // 没有 tokenizer_config.json 时按路径选择
+    assert_eq!(chat(&dir), "<用户>Hi<AI>");
+    let tinyllama = dir.join("TinyLlama-1.1B");
+    std::fs::create_dir_all(&tinyllama).unwrap();
+    assert_eq!(chat(&tinyllama), "<|user|>\nHi</s><|assistant|>\n");
+
+    // tokenizer_config.json 中的模板优先于路径
+    let config = r#"{"chat_template": "{% for message in messages %}<|user|>\n{{ message['content'] }}{% endfor %}"}"#;
+    std::fs::write(dir.join("tokenizer_config.json"), config).unwrap();
+    assert_eq!(chat(&dir), "<|user|>\nHi</s><|assistant|>\n");
+    std::fs::write(
+        tinyllama.join("tokenizer_config.json"),
+        r#"{"chat_template": "<用户>{{ content }}<AI>"}"#,
+    )
+    .unwrap();
+    assert_eq!(chat(&tinyllama), "<用户>Hi<AI>");
+
+    // 无法识别的模板沿用默认选择
+    std::fs::write(
+        dir.join("tokenizer_config.json"),
+        r#"{"chat_template": "{{ content }}"}"#,
+    )
+    .unwrap();
+    assert_eq!(chat(&dir), "<用户>Hi<AI>");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// 加载模型目录中的分词器及其配套的规范化器。
+///
+/// 依次尝试 tokenizer.json、tokenizer.model 和 vocabs.txt，tokenizer.json 无法使用时退回 tokenizer.model。
+fn tokenizer(
+    model_dir: impl AsRef<Path>,
+) -> (
+    Box<dyn Tokenizer + Send + Sync>,
+    Box<dyn Normalizer + Send + Sync>,
+) {
     use std::io::ErrorKind::NotFound;
-    match BPE::from_model_file(model_dir.as_ref().join("tokenizer.model")) {
-        Ok(bpe) => return Box::new(bpe),
-        Err(e) if e.kind() == NotFound => {}
-        Err(e) => panic!("{e:?}"),
+    match BPE::from_tokenizer_json(model_dir.as_ref().join("tokenizer.json")) {
+        Ok(bpe) => return (Box::new(bpe), Box::new(BPECommonNormalizer {})),
+        Err(TokenizerError::Io(e)) if e.kind() == NotFound => {}
+        Err(e) => warn!("failed to load tokenizer.json: {e}"),
+    }
+    let model = model_dir.as_ref().join("tokenizer.model");
+    match BPE::from_sentencepiece_proto(&model) {
+        Ok(bpe) => return (Box::new(bpe), Box::new(BPECommonNormalizer {})),
+        Err(TokenizerError::Io(e)) if e.kind() == NotFound => {}
+        Err(e) => {
+            // 无法完整解析时，按词汇位于文件开头的格式直接映射
+            warn!("failed to parse tokenizer.model: {e}");
+            let bpe = BPE::from_model_file(&model).unwrap();
+            return (Box::new(bpe), Box::new(BPECommonNormalizer {}));
+        }
     }
     match VocabTxt::from_txt_file(model_dir.as_ref().join("vocabs.txt")) {
-        Ok(voc) => return Box::new(voc),
+        Ok(voc) => return (Box::new(voc), Box::new(())),
         Err(e) if e.kind() == NotFound => {}
         Err(e) => panic!("{e:?}"),
     }
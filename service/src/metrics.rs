@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+/// 一次批量推理的统计信息。
+#[derive(Clone, Default, Debug)]
+pub struct InferenceMetrics {
+    /// 预填充的 token 数。
+    pub prefill_tokens: usize,
+    /// 解码的 token 数。
+    pub decode_tokens: usize,
+    /// 预填充耗时（毫秒）。
+    pub prefill_ms: f64,
+    /// 解码耗时（毫秒）。
+    pub decode_ms: f64,
+    /// 推理吞吐量。
+    pub tokens_per_sec: f64,
+}
+
+impl InferenceMetrics {
+    /// 根据一次推理的 token 数和耗时生成统计信息。
+    ///
+    /// 批量中只要存在预填充，这一次推理的耗时就计入预填充。
+    pub fn new(prefill_tokens: usize, decode_tokens: usize, time: Duration) -> Self {
+        let ms = time.as_secs_f64() * 1e3;
+        let (prefill_ms, decode_ms) = if prefill_tokens > 0 {
+            (ms, 0.)
+        } else {
+            (0., ms)
+        };
+        Self {
+            prefill_tokens,
+            decode_tokens,
+            prefill_ms,
+            decode_ms,
+            tokens_per_sec: tokens_per_sec(prefill_tokens + decode_tokens, ms),
+        }
+    }
+}
+
+/// 服务启动以来的累计统计信息。
+#[derive(Clone, Default, Debug)]
+pub struct ServiceMetrics {
+    /// 累计预填充的 token 数。
+    pub prefill_tokens: usize,
+    /// 累计解码的 token 数。
+    pub decode_tokens: usize,
+    /// 累计预填充耗时（毫秒）。
+    pub prefill_ms: f64,
+    /// 累计解码耗时（毫秒）。
+    pub decode_ms: f64,
+    /// 平均推理吞吐量。
+    pub tokens_per_sec: f64,
+    /// 首 token 延迟分布。
+    first_token: Histogram,
+}
+
+impl ServiceMetrics {
+    /// 累加一次推理的统计信息。
+    pub fn record(&mut self, m: &InferenceMetrics) {
+        self.prefill_tokens += m.prefill_tokens;
+        self.decode_tokens += m.decode_tokens;
+        self.prefill_ms += m.prefill_ms;
+        self.decode_ms += m.decode_ms;
+        self.tokens_per_sec = tokens_per_sec(
+            self.prefill_tokens + self.decode_tokens,
+            self.prefill_ms + self.decode_ms,
+        );
+    }
+
+    /// 记录一次首 token 延迟。
+    #[inline]
+    pub fn record_first_token(&mut self, latency: Duration) {
+        self.first_token.record(latency)
+    }
+
+    /// 首 token 延迟的 `q` 分位数（毫秒），没有记录时返回 `None`。
+    #[inline]
+    pub fn first_token_ms(&self, q: f64) -> Option<f64> {
+        self.first_token.quantile(q)
+    }
+
+    #[inline]
+    pub fn first_token_p50(&self) -> Option<f64> {
+        self.first_token_ms(0.5)
+    }
+
+    #[inline]
+    pub fn first_token_p90(&self) -> Option<f64> {
+        self.first_token_ms(0.9)
+    }
+
+    #[inline]
+    pub fn first_token_p99(&self) -> Option<f64> {
+        self.first_token_ms(0.99)
+    }
+}
+
+#[inline]
+fn tokens_per_sec(tokens: usize, ms: f64) -> f64 {
+    if ms > 0. {
+        tokens as f64 / ms * 1e3
+    } else {
+        0.
+    }
+}
+
+/// 对数分桶的延迟直方图，相对误差不超过 `BASE - 1`。
+#[derive(Clone, Default, Debug)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+}
+
+/// 相邻桶边界的比值。
+const BASE: f64 = 1.02;
+
+impl Histogram {
+    fn record(&mut self, latency: Duration) {
+        let us = latency.as_secs_f64() * 1e6;
+        let i = if us > 1. {
+            (us.ln() / BASE.ln()) as usize
+        } else {
+            0
+        };
+        if self.buckets.len() <= i {
+            self.buckets.resize(i + 1, 0);
+        }
+        self.buckets[i] += 1;
+        self.count += 1;
+    }
+
+    fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0., 1.) * self.count as f64).ceil() as u64).max(1);
+        let mut acc = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            acc += n;
+            if acc >= rank {
+                // 返回桶的上界，单位毫秒
+                return Some(BASE.powi(i as i32 + 1) / 1e3);
+            }
+        }
+        unreachable!()
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let mut h = Histogram::default();
+    assert_eq!(h.quantile(0.5), None);
+    for ms in 1..=100 {
+        h.record(Duration::from_millis(ms));
+    }
+    for (q, expected) in [(0.5, 50.), (0.9, 90.), (0.99, 99.)] {
+        let actual = h.quantile(q).unwrap();
+        assert!(
+            (actual - expected).abs() / expected <= BASE - 1.,
+            "{actual} vs {expected}"
+        );
+    }
+}
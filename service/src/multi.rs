@@ -0,0 +1,152 @@
+use crate::{BatchingPolicy, Generator, Service, ServiceError, Session};
+use causal_lm::{CausalLM, SampleArgs};
+use std::{collections::HashMap, fmt::Debug, path::Path, time::Duration};
+use tokio::task::JoinHandle;
+
+/// 多模型服务，在同一进程中以模型名区分同时加载的多个对话服务。
+///
+/// 每个模型占用的设备内存分别记录，所有模型占用的总量不超过设定的限制。
+pub struct MultiModelService<M: CausalLM> {
+    services: HashMap<String, Model<M>>,
+    memory_limit: usize,
+}
+
+struct Model<M: CausalLM> {
+    service: Service<M>,
+    handle: JoinHandle<()>,
+    /// 模型占用的设备内存字节数，无法获取设备内存时为 `None`。
+    memory: Option<usize>,
+}
+
+impl<M: CausalLM> Default for MultiModelService<M> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            services: Default::default(),
+            memory_limit: usize::MAX,
+        }
+    }
+}
+
+impl<M> MultiModelService<M>
+where
+    M: CausalLM + Send + Sync + 'static,
+    M::Storage: Send,
+    M::Error: Debug,
+{
+    /// 从 `model_dir` 加载名为 `name` 的模型并启动其服务，`sample` 为这个服务的默认采样参数。
+    ///
+    /// 已存在同名模型时返回 [`ServiceError::ModelExists`]，不加载模型；
+    /// 加载后所有模型占用的设备内存超出限制时返回 [`ServiceError::OutOfMemory`]，卸载这个模型后返回。
+    /// 加载和卸载都阻塞当前线程，不能在异步上下文中调用。
+    pub fn add_model(
+        &mut self,
+        name: &str,
+        model_dir: impl AsRef<Path>,
+        meta: M::Meta,
+        sample: SampleArgs,
+    ) -> Result<(), ServiceError> {
+        self.add_model_with_policy(name, model_dir, meta, sample, Default::default())
+    }
+
+    /// 以指定的组批策略加载模型，参见 [`MultiModelService::add_model`]。
+    pub fn add_model_with_policy(
+        &mut self,
+        name: &str,
+        model_dir: impl AsRef<Path>,
+        meta: M::Meta,
+        sample: SampleArgs,
+        policy: BatchingPolicy,
+    ) -> Result<(), ServiceError> {
+        if self.services.contains_key(name) {
+            return Err(ServiceError::ModelExists);
+        }
+        // 加载前后设备空闲内存之差即这个模型占用的内存
+        let free = M::device_memory_info(&meta).map(|(free, _)| free);
+        let (mut service, handle) = Service::load_with_policy(model_dir, meta, policy);
+        let memory = free
+            .zip(service.memory_info())
+            .map(|(free, (rest, _))| free.saturating_sub(rest));
+        if memory.is_some_and(|m| self.memory_usage().saturating_add(m) > self.memory_limit) {
+            // 释放服务将通知推理线程退出，等待推理线程退出确保模型已经卸载
+            drop(service);
+            let _ = tokio::runtime::Handle::current().block_on(handle);
+            return Err(ServiceError::OutOfMemory);
+        }
+        service.default_sample = sample;
+        let model = Model {
+            service,
+            handle,
+            memory,
+        };
+        self.services.insert(name.into(), model);
+        Ok(())
+    }
+
+    /// 移除名为 `name` 的模型，在后台至多等待 `timeout` 使其在途的推理任务完成后关闭服务，不阻塞当前线程。
+    ///
+    /// 返回服务关闭且推理线程退出后结束的 [`JoinHandle`]，模型不存在时返回 `None`。
+    pub fn remove_model(&mut self, name: &str, timeout: Duration) -> Option<JoinHandle<()>> {
+        let Model {
+            service, handle, ..
+        } = self.services.remove(name)?;
+        Some(tokio::spawn(async move {
+            service.graceful_shutdown_async(timeout).await;
+            handle.await.unwrap();
+        }))
+    }
+}
+
+impl<M: CausalLM> MultiModelService<M> {
+    /// 获取名为 `name` 的模型的服务。
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&Service<M>> {
+        self.services.get(name).map(|m| &m.service)
+    }
+
+    /// 设置所有模型占用的设备内存总量的上限，只限制之后加载的模型。
+    #[inline]
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = bytes;
+    }
+
+    /// 名为 `name` 的模型占用的设备内存字节数，模型不存在或无法获取设备内存时返回 `None`。
+    #[inline]
+    pub fn model_memory(&self, name: &str) -> Option<usize> {
+        self.services.get(name).and_then(|m| m.memory)
+    }
+
+    /// 所有模型占用的设备内存字节数之和。
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.services.values().filter_map(|m| m.memory).sum()
+    }
+
+    /// 列出所有已加载的模型名。
+    #[inline]
+    pub fn models(&self) -> impl Iterator<Item = &str> {
+        self.services.keys().map(String::as_str)
+    }
+
+    /// 从名为 `model` 的模型服务启动一个会话。
+    #[inline]
+    pub fn launch(&self, model: &str) -> Result<Session<M>, ServiceError> {
+        self.service(model)?.launch()
+    }
+
+    /// 从名为 `model` 的模型服务启动一个文本生成器。
+    #[inline]
+    pub fn generate(
+        &self,
+        model: &str,
+        prompt: impl AsRef<str>,
+        sample: Option<SampleArgs>,
+    ) -> Result<Generator<M>, ServiceError> {
+        self.service(model)?.generate(prompt, sample)
+    }
+
+    #[inline]
+    fn service(&self, model: &str) -> Result<&Service<M>, ServiceError> {
+        self.get(model).ok_or(ServiceError::NoSuchModel)
+    }
+}
@@ -24,15 +24,25 @@ impl<T> Batcher<T> {
         self.condvar.notify_one();
     }
 
+    /// 按入队顺序取出至多 `max` 个元素，剩余的留待下次取出。
     #[inline]
-    pub fn deq(&self) -> Vec<T> {
-        std::mem::take(
-            &mut self
-                .condvar
-                .wait_while(self.queue.lock().unwrap(), |(q, a)| q.is_empty() && *a)
-                .unwrap()
-                .0,
-        )
+    pub fn deq(&self, max: usize) -> Vec<T> {
+        let mut lock = self
+            .condvar
+            .wait_while(self.queue.lock().unwrap(), |(q, a)| q.is_empty() && *a)
+            .unwrap();
+        let queue = &mut lock.0;
+        if queue.len() <= max {
+            std::mem::take(queue)
+        } else {
+            queue.drain(..max).collect()
+        }
+    }
+
+    /// 队列中等待的元素数。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().0.len()
     }
 
     #[inline]
@@ -44,3 +54,16 @@ impl<T> Batcher<T> {
         self.condvar.notify_all();
     }
 }
+
+#[test]
+fn test_deq() {
+    let batcher = Batcher::new();
+    for i in 0..5 {
+        batcher.enq(i);
+    }
+    assert_eq!(batcher.deq(3), [0, 1, 2]);
+    batcher.enq(5);
+    assert_eq!(batcher.deq(3), [3, 4, 5]);
+    batcher.shutdown();
+    assert!(batcher.deq(3).is_empty());
+}
@@ -0,0 +1,191 @@
+use super::cache::Cache;
+use causal_lm::{CausalLM, QueryContext};
+use common::{upos, utok};
+use std::{iter::zip, mem::take};
+use tensor::Tensor;
+
+/// 束搜索中的一个假设。
+struct Hypothesis<Storage> {
+    /// 假设自己的缓存张量，搜索开始前的根假设使用任务的缓存，没有自己的张量。
+    cache: Option<Tensor<Storage>>,
+    /// 生成的词，不包括结束符。
+    tokens: Vec<utok>,
+    /// 生成的词的对数概率之和。
+    log_prob: f32,
+    /// 生成的词数，包括结束符。
+    len: usize,
+}
+
+impl<Storage> Hypothesis<Storage> {
+    /// 以长度归一化的得分比较不同长度的假设。
+    #[inline]
+    fn score(&self) -> f32 {
+        self.log_prob / self.len.max(1) as f32
+    }
+
+    /// 复制出一个子假设，只复制缓存张量中有效的部分。
+    fn fork(&self, t: &impl CausalLM<Storage = Storage>, prefix: &Cache<Storage>) -> Self {
+        let cache = match &self.cache {
+            Some(cache) => t.duplicate_cache(cache, (prefix.cached_len() + self.tokens.len()) as _),
+            None => prefix.duplicate_tensor(t),
+        };
+        Self {
+            cache: Some(cache),
+            tokens: self.tokens.clone(),
+            log_prob: self.log_prob,
+            len: self.len,
+        }
+    }
+}
+
+/// 束搜索的状态，保存在任务中，每轮推理扩展一步。
+///
+/// 提示词的 token 序列和缓存只保存在任务的缓存中，每个假设只保存提示词之后生成的词。
+/// 一个假设扩展出多个子假设时才复制缓存张量，最后一个子假设直接取走父假设的张量。
+pub(super) struct Beam<Storage> {
+    width: usize,
+    max_tokens: usize,
+    /// 活跃的假设。
+    active: Vec<Hypothesis<Storage>>,
+    /// 完整的假设。
+    finished: Vec<Hypothesis<Storage>>,
+}
+
+impl<Storage> Beam<Storage> {
+    /// 以束宽 `width` 开始搜索，每个假设至多生成 `max_tokens` 个词。
+    ///
+    /// `prefix` 是已经缓存了提示词的任务缓存，`log_probs` 是提示词之后下一个词的对数概率。
+    pub fn start(
+        t: &impl CausalLM<Storage = Storage>,
+        prefix: &Cache<Storage>,
+        log_probs: &[f32],
+        width: usize,
+        max_tokens: usize,
+    ) -> Self {
+        let root = Hypothesis {
+            cache: None,
+            tokens: vec![],
+            log_prob: 0.,
+            len: 0,
+        };
+        let mut beam = Self {
+            width,
+            max_tokens,
+            active: vec![root],
+            finished: vec![],
+        };
+        beam.step(t, prefix, &[log_probs]);
+        beam
+    }
+
+    /// 活跃的假设数，即下一步推理占用的批大小。
+    #[inline]
+    pub fn num_active(&self) -> usize {
+        self.active.len()
+    }
+
+    /// 完整假设达到束宽或没有活跃假设时搜索结束。
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.active.is_empty() || self.finished.len() >= self.width
+    }
+
+    /// 每个活跃假设的查询，即最后生成的词。
+    pub fn queries(&self) -> impl Iterator<Item = utok> + '_ {
+        self.active.iter().map(|h| *h.tokens.last().unwrap())
+    }
+
+    /// 每个活跃假设的查询上下文，`pos` 是提示词的缓存长度。
+    pub fn as_ctx(&mut self, pos: usize) -> impl Iterator<Item = QueryContext<Storage>> {
+        self.active.iter_mut().map(move |h| {
+            let pos = (pos + h.tokens.len() - 1) as upos;
+            QueryContext {
+                cache: h.cache.as_mut(),
+                range: pos..pos + 1,
+            }
+        })
+    }
+
+    /// 以每个活跃假设的下一个词的对数概率扩展一步。
+    ///
+    /// 每个假设提出对数概率最大的 `2 x width` 个候选词，按累积对数概率保留最好的 `width` 个活跃假设，
+    /// 排在它们之前的结束符候选成为完整假设。结束符不加入假设生成的词。
+    pub fn step(
+        &mut self,
+        t: &impl CausalLM<Storage = Storage>,
+        prefix: &Cache<Storage>,
+        log_probs: &[impl AsRef<[f32]>],
+    ) {
+        let eos = t.eos_token();
+        let max = t.max_seq_len() as usize;
+        let width = self.width;
+        // 每个假设提出候选词
+        let mut candidates = Vec::new();
+        for (i, (h, lp)) in zip(&self.active, log_probs).enumerate() {
+            let lp = lp.as_ref();
+            let mut top = (0..lp.len()).collect::<Vec<_>>();
+            let n = (2 * width).min(top.len());
+            top.select_nth_unstable_by(n - 1, |&a, &b| lp[b].total_cmp(&lp[a]));
+            candidates.extend(
+                top[..n]
+                    .iter()
+                    .map(|&tok| (i, tok as utok, h.log_prob + lp[tok])),
+            );
+        }
+        candidates.sort_unstable_by(|(.., a), (.., b)| b.total_cmp(a));
+        // 剪枝
+        let mut selected = Vec::with_capacity(width);
+        let mut active = 0;
+        for candidate in candidates {
+            if active == width {
+                break;
+            }
+            active += (candidate.1 != eos) as usize;
+            selected.push(candidate);
+        }
+        // 扩展假设
+        let mut children = vec![0; self.active.len()];
+        for &(i, ..) in &selected {
+            children[i] += 1;
+        }
+        let mut parents = take(&mut self.active)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        self.active = Vec::with_capacity(width);
+        for (i, tok, log_prob) in selected {
+            children[i] -= 1;
+            let parent = &mut parents[i];
+            let mut child = if children[i] == 0 && parent.as_ref().unwrap().cache.is_some() {
+                parent.take().unwrap()
+            } else {
+                parent.as_ref().unwrap().fork(t, prefix)
+            };
+            child.log_prob = log_prob;
+            child.len += 1;
+            if tok != eos {
+                child.tokens.push(tok);
+            }
+            // 生成结束符、达到长度上限或缓存用尽的假设是完整的
+            if tok == eos
+                || child.tokens.len() >= self.max_tokens
+                || prefix.cached_len() + child.tokens.len() >= max
+            {
+                self.finished.push(child);
+            } else {
+                self.active.push(child);
+            }
+        }
+    }
+
+    /// 结束搜索，返回得分最高的假设的缓存张量和生成的词。
+    pub fn finish(self) -> (Tensor<Storage>, Vec<utok>) {
+        let best = self
+            .finished
+            .into_iter()
+            .chain(self.active)
+            .max_by(|a, b| a.score().total_cmp(&b.score()))
+            .unwrap();
+        (best.cache.unwrap(), best.tokens)
+    }
+}
@@ -28,14 +28,18 @@ impl<Storage> Cache<Storage> {
     /// 复制缓存结构。
     #[inline]
     pub fn duplicate(&self, t: &impl CausalLM<Storage = Storage>) -> Self {
-        assert_eq!(self.cached.start, 0);
         Self {
             tokens: self.tokens.clone(),
             pos: self.pos,
             cached: self.cached.clone(),
-            cache: t.duplicate_cache(&self.cache, self.cached.end as _),
+            cache: self.duplicate_tensor(t),
         }
     }
+    /// 只复制缓存张量中已缓存的部分。
+    #[inline]
+    pub fn duplicate_tensor(&self, t: &impl CausalLM<Storage = Storage>) -> Tensor<Storage> {
+        t.duplicate_cache(&self.cache, self.cached.len() as _)
+    }
     /// 回滚缓存到 `pos`，并返回剩余的有效缓存长度。
     pub fn revert(&mut self, pos: usize) -> usize {
         // 只能在闲时回滚，因此 cache 和 tokens 起始位置对齐
@@ -61,18 +65,24 @@ impl<Storage> Cache<Storage> {
     pub fn query(&self) -> &[utok] {
         &self.tokens[self.cached.end..]
     }
-    /// 生成对应的查询上下文。
+    /// 查询中至多前 `max` 个 token，作为这次计算的分块。
     #[inline]
-    pub fn as_ctx(&mut self) -> QueryContext<Storage> {
+    pub fn chunk(&self, max: usize) -> &[utok] {
+        let query = self.query();
+        &query[..query.len().min(max)]
+    }
+    /// 生成只包含查询中至多前 `max` 个 token 的查询上下文。
+    pub fn as_ctx_within(&mut self, max: usize) -> QueryContext<Storage> {
+        let len = self.chunk(max).len();
         let Cache {
             pos: _pos,
             cache,
-            tokens,
+            tokens: _tokens,
             cached,
         } = self;
         QueryContext {
             cache: Some(cache),
-            range: cached.len() as upos..(tokens.len() - cached.start) as upos,
+            range: cached.len() as upos..(cached.len() + len) as upos,
         }
     }
 
@@ -82,11 +92,43 @@ impl<Storage> Cache<Storage> {
         self.cached.end = self.tokens.len();
         self.tokens.push(token);
     }
+    /// 以束搜索得到的缓存张量替换缓存，并依次加入生成的 `tokens`。
+    pub fn accept(&mut self, cache: Tensor<Storage>, tokens: &[utok]) {
+        self.cache = cache;
+        for &token in tokens {
+            self.push(token);
+        }
+    }
+    /// 不采样，将所有查询标记为已缓存。
+    #[inline]
+    pub fn commit(&mut self) {
+        self.commit_within(usize::MAX)
+    }
+    /// 不采样，将查询中至多前 `max` 个 token 标记为已缓存。
+    #[inline]
+    pub fn commit_within(&mut self, max: usize) {
+        self.cached.end += self.chunk(max).len();
+    }
     /// 已采样的最后一个词在对话中的位置。
     #[inline]
     pub fn end(&self) -> usize {
         self.pos + self.tokens.len()
     }
+    /// token 序列在对话中的起始位置。
+    #[inline]
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+    /// 缓存窗口在 token 序列中的起始位置。
+    #[inline]
+    pub fn cached_start(&self) -> usize {
+        self.cached.start
+    }
+    /// 已缓存的 token 数量。
+    #[inline]
+    pub fn cached_len(&self) -> usize {
+        self.cached.len()
+    }
     /// 提取尾部词序列。
     #[inline]
     pub fn slice_tail(&self, pos: usize) -> &[utok] {
@@ -108,14 +150,80 @@ impl<Storage> Cache<Storage> {
         self.cached = 0..0;
     }
     /// 清理缓存中已脱离缓存窗口的部分。
+    #[inline]
     pub fn cleanup(&mut self) {
-        let to_remove = self.cached.start;
+        self.cleanup_before(usize::MAX)
+    }
+    /// 清理缓存中已脱离缓存窗口的部分，但保留对话中 `keep` 及之后的 token。
+    pub fn cleanup_before(&mut self, keep: usize) {
+        let to_remove = self.cached.start.min(keep.saturating_sub(self.pos));
         if to_remove > 0 {
             self.tokens.copy_within(to_remove.., 0);
             self.pos += to_remove;
             self.tokens.truncate(self.tokens.len() - to_remove);
-            self.cached.start = 0;
+            self.cached.start -= to_remove;
             self.cached.end -= to_remove;
         }
     }
 }
+
+#[test]
+fn test_cleanup_before() {
+    use digit_layout::types::U8;
+
+    let mut cache = Cache {
+        tokens: vec![0; 8],
+        pos: 0,
+        cached: 0..0,
+        cache: Tensor::alloc(U8, &[1], |_| ()),
+    };
+    let (min, max) = (64, 256);
+    cache.commit();
+    for i in 0..10_000 {
+        cache.push(i);
+        cache.reset_within(min, max);
+        cache.cleanup_before(usize::MAX);
+        assert!(cache.tokens.len() <= max);
+        assert_eq!(cache.end(), 8 + i as usize + 1);
+    }
+    assert_eq!(cache.slice_tail(cache.end() - 1), [9_999]);
+
+    // 保留 keep 之后的 token
+    let mut cache = Cache {
+        tokens: vec![0; 8],
+        pos: 0,
+        cached: 0..0,
+        cache: Tensor::alloc(U8, &[1], |_| ()),
+    };
+    cache.commit();
+    for i in 0..1000 {
+        cache.push(i);
+        cache.reset_within(min, max);
+        cache.cleanup_before(8);
+    }
+    assert_eq!(cache.pos(), 8);
+    assert_eq!(cache.slice_tail(8).len(), 1000);
+}
+
+#[test]
+fn test_chunk() {
+    use digit_layout::types::U8;
+
+    let mut cache = Cache {
+        tokens: vec![0, 1, 2, 3, 4, 5, 6],
+        pos: 0,
+        cached: 0..0,
+        cache: Tensor::alloc(U8, &[1], |_| ()),
+    };
+    assert_eq!(cache.chunk(3), [0, 1, 2]);
+    assert_eq!(cache.as_ctx_within(3).range, 0..3);
+    cache.commit_within(3);
+    assert_eq!(cache.query(), [3, 4, 5, 6]);
+    assert_eq!(cache.as_ctx_within(3).range, 3..6);
+    cache.commit_within(3);
+    assert_eq!(cache.chunk(3), [6]);
+    assert_eq!(cache.as_ctx_within(3).range, 6..7);
+    cache.commit_within(3);
+    assert!(cache.query().is_empty());
+    assert_eq!(cache.cached_len(), 7);
+}
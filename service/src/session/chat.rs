@@ -0,0 +1,104 @@
+use super::{ChatError, Session};
+use causal_lm::CausalLM;
+
+/// 多轮对话中消息的角色。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ChatRole {
+    /// 用户输入。
+    User,
+    /// 模型的回答。
+    Assistant,
+}
+
+/// 多轮对话中的一条消息。
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ChatMessage {
+    /// 消息的角色。
+    pub role: ChatRole,
+    /// 消息的文本。
+    pub content: String,
+}
+
+/// 多轮对话会话。
+///
+/// 包装一个 [`Session`]，保证用户输入和回答交替出现，并记录对话的文本历史。
+/// 对话模板和系统提示词由服务的模板处理，回答末尾的结束符不会出现在文本中。
+pub struct ChatSession<M: CausalLM> {
+    session: Session<M>,
+    history: Vec<ChatMessage>,
+}
+
+impl<M: CausalLM> ChatSession<M> {
+    pub(crate) fn new(mut session: Session<M>, system: Option<&str>) -> Self {
+        if let Some(system) = system {
+            session.set_system_prompt(system);
+        }
+        Self {
+            session,
+            history: Vec::new(),
+        }
+    }
+
+    /// 加入一条用户输入。
+    ///
+    /// 上一条用户输入尚未得到回答时返回错误。
+    pub fn add_user_message(&mut self, text: &str) -> Result<(), ChatError> {
+        if self.waiting_for_answer() {
+            return Err(ChatError::Position);
+        }
+        self.session.extend([text]);
+        self.history.push(ChatMessage {
+            role: ChatRole::User,
+            content: text.into(),
+        });
+        Ok(())
+    }
+
+    /// 对最后一条用户输入生成完整的回答，回答将加入对话历史。
+    ///
+    /// 没有待回答的用户输入时返回错误。
+    pub async fn generate_assistant_response(&mut self) -> Result<String, ChatError> {
+        if !self.waiting_for_answer() {
+            return Err(ChatError::Position);
+        }
+        let mut answer = String::new();
+        {
+            let mut busy = self.session.chat().map_err(|_| ChatError::ShuttingDown)?;
+            while let Some(s) = busy.decode().await {
+                answer.push_str(&s);
+            }
+        }
+        // 没有生成任何 token 时会话不会加入回答，补充一个空回答以保持轮次
+        if self.session.dialog_pos() % 2 != 0 {
+            self.session.extend([""]);
+        }
+        self.history.push(ChatMessage {
+            role: ChatRole::Assistant,
+            content: answer.clone(),
+        });
+        Ok(answer)
+    }
+
+    /// 对话的文本历史，不包括系统提示词。
+    #[inline]
+    pub fn get_history(&self) -> &[ChatMessage] {
+        &self.history
+    }
+
+    /// 被包装的会话。
+    #[inline]
+    pub fn session(&self) -> &Session<M> {
+        &self.session
+    }
+
+    #[inline]
+    fn waiting_for_answer(&self) -> bool {
+        matches!(
+            self.history.last(),
+            Some(ChatMessage {
+                role: ChatRole::User,
+                ..
+            })
+        )
+    }
+}
@@ -1,46 +1,75 @@
 ﻿use common::utok;
-use std::sync::Arc;
+use std::{iter::once, sync::Arc};
 
 #[derive(Clone, Default, Debug)]
-pub(crate) struct Dialog(Vec<Arc<(Vec<utok>, usize)>>);
+pub(crate) struct Dialog {
+    /// 系统提示词，位于对话开头且不计入句子。
+    system: Arc<Vec<utok>>,
+    sentences: Vec<Arc<(Vec<utok>, usize)>>,
+}
 
 impl Dialog {
+    /// 以系统提示词 `system` 开始一段新的对话。
+    #[inline]
+    pub fn with_system(system: Vec<utok>) -> Self {
+        Self {
+            system: Arc::new(system),
+            sentences: Vec::new(),
+        }
+    }
+
     #[inline]
     pub fn num_sentences(&self) -> usize {
-        self.0.len()
+        self.sentences.len()
     }
 
     #[inline]
     pub fn num_tokens(&self) -> usize {
-        self.0.last().map_or(0, |s| s.1)
+        self.sentences.last().map_or(self.system.len(), |s| s.1)
     }
 
     #[inline]
     pub fn revert(&mut self, len: usize) {
-        self.0.truncate(len);
+        self.sentences.truncate(len);
     }
 
     #[inline]
     pub fn last_prompt(&self) -> Option<&[utok]> {
-        self.0
+        self.sentences
             .last()
-            .filter(|_| self.0.len() % 2 != 0)
+            .filter(|_| self.sentences.len() % 2 != 0)
             .map(|s| &*s.0)
     }
 
     #[inline]
     pub fn push(&mut self, tokens: Vec<utok>) {
         let len = self.num_tokens() + tokens.len();
-        self.0.push(Arc::new((tokens, len)))
+        self.sentences.push(Arc::new((tokens, len)))
+    }
+
+    /// 选择一个由完整句子组成的对话窗口，返回窗口在对话中的起始位置。
+    ///
+    /// 窗口总是以用户输入开始，并包含最近的句子。窗口中不超过 `max_tokens` 个 token
+    /// 且不超过 `max_messages` 个句子，因此最早的句子最先被丢弃。
+    /// 如果最后一轮对话本身就超出限制，窗口只包含最后一轮对话。
+    pub fn fit(&self, max_tokens: usize, max_messages: usize) -> usize {
+        let n = self.sentences.len();
+        let total = self.num_tokens();
+        let start = |i: usize| i.checked_sub(1).map_or(0, |i| self.sentences[i].1);
+        (0..n)
+            .step_by(2)
+            .find(|&i| n - i <= max_messages && total - start(i) <= max_tokens)
+            .or_else(|| n.checked_sub(1).map(|last| last - last % 2))
+            .map_or(0, start)
     }
 
     #[inline]
     pub fn window(&self, len: usize) -> (Vec<utok>, usize) {
         let start = self.num_tokens().saturating_sub(len);
-        let mut iter = self.0.iter().map(|s| &*s.0);
+        let mut iter = once(&**self.system).chain(self.sentences.iter().map(|s| &*s.0));
         let mut pos = 0;
         for tokens in iter.by_ref() {
-            if let Some(len) = start.checked_sub(pos) {
+            if let Some(len) = start.checked_sub(pos).filter(|&len| len <= tokens.len()) {
                 let ans = tokens[len..]
                     .iter()
                     .chain(iter.flatten())
@@ -54,3 +83,44 @@ impl Dialog {
         unreachable!()
     }
 }
+
+#[test]
+fn test_fit() {
+    let mut dialog = Dialog::default();
+    assert_eq!(dialog.fit(0, 0), 0);
+    // 20 个句子，每个 3 个 token
+    for i in 0..20 {
+        dialog.push(vec![i; 3]);
+    }
+    assert_eq!(dialog.num_tokens(), 60);
+    // 不需要截断
+    assert_eq!(dialog.fit(60, usize::MAX), 0);
+    // token 数限制，从最早的句子开始丢弃，并以用户输入开始
+    assert_eq!(dialog.fit(59, usize::MAX), 6);
+    assert_eq!(dialog.fit(50, usize::MAX), 12);
+    // 句子数限制
+    assert_eq!(dialog.fit(usize::MAX, 4), 48);
+    assert_eq!(dialog.fit(usize::MAX, 5), 48);
+    // 最后一轮本身超出限制
+    assert_eq!(dialog.fit(1, usize::MAX), 54);
+    dialog.push(vec![20; 3]);
+    assert_eq!(dialog.fit(1, usize::MAX), 60);
+}
+
+#[test]
+fn test_system() {
+    let mut dialog = Dialog::with_system(vec![0; 4]);
+    assert_eq!(dialog.num_sentences(), 0);
+    assert_eq!(dialog.num_tokens(), 4);
+    assert_eq!(dialog.last_prompt(), None);
+
+    dialog.push(vec![1; 3]);
+    dialog.push(vec![2; 3]);
+    assert_eq!(dialog.num_tokens(), 10);
+    assert_eq!(dialog.last_prompt(), None);
+    assert_eq!(dialog.window(5), (vec![1, 1, 2, 2, 2], 5));
+    assert_eq!(dialog.window(10).1, 0);
+
+    dialog.revert(0);
+    assert_eq!(dialog.num_tokens(), 4);
+}
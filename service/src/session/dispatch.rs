@@ -1,14 +1,23 @@
-﻿use super::{batcher::Batcher, cache::Cache, task::Task};
-use crate::ServiceComponent;
-use causal_lm::{CausalLM, DecodingMeta, SampleArgs, SampleMeta};
+﻿use super::{
+    batcher::Batcher,
+    beam::Beam,
+    cache::Cache,
+    task::{InFlight, InFlightCounter, Task},
+};
+use crate::{BatchingPolicy, InferenceMetrics, ServiceComponent, ServiceMetrics};
+use causal_lm::{CausalLM, DecodingMeta, MirostatState, SampleArgs, SampleMeta};
 use common::utok;
+use log::warn;
 use std::{
     iter::zip,
-    mem::{replace, size_of},
-    str,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokenizer::Utf8Buffer;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 pub(super) struct TaskHandle<M: CausalLM> {
     receiver: Option<UnboundedReceiver<utok>>,
@@ -17,25 +26,76 @@ pub(super) struct TaskHandle<M: CausalLM> {
 }
 
 impl<M: CausalLM> TaskHandle<M> {
+    /// 等待推理任务结束。
+    pub async fn wait(&mut self) {
+        while self.receiver.as_mut().unwrap().recv().await.is_some() {}
+    }
+
     #[inline]
     pub fn take(&mut self) -> Cache<M::Storage> {
+        self.try_take().unwrap()
+    }
+
+    /// 停止接收响应，取走缓存。重放的任务没有缓存。
+    #[inline]
+    pub fn try_take(&mut self) -> Option<Cache<M::Storage>> {
         // 停止响应接收
         let _ = self.receiver.take();
         // 取走 cache
-        self.cache.lock().unwrap().take().unwrap()
+        self.cache.lock().unwrap().take()
+    }
+
+    /// 生成不经推理、直接重放 `tokens` 的任务句柄。
+    pub fn replay(tokens: &[utok]) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        for &token in tokens {
+            sender.send(token).unwrap();
+        }
+        Self {
+            receiver: Some(receiver),
+            cache: Default::default(),
+            buffer: Default::default(),
+        }
     }
 }
 
 impl<M: CausalLM> ServiceComponent<M> {
-    pub(super) fn infer(&self, sample: SampleArgs, mut cache: Cache<M::Storage>) -> TaskHandle<M> {
+    /// 启动推理任务，任务执行中清理缓存时保留对话中 `keep` 及之后的 token。
+    pub(super) fn infer(
+        &self,
+        sample: SampleArgs,
+        mirostat: MirostatState,
+        cache: Cache<M::Storage>,
+        keep: usize,
+    ) -> TaskHandle<M> {
+        self.enq(cache, |cache, sender, inflight| {
+            Task::new(cache, sample, mirostat, keep, sender, inflight)
+        })
+    }
+
+    /// 启动只计算缓存而不采样的预填充任务。
+    pub(super) fn prefill(&self, cache: Cache<M::Storage>) -> TaskHandle<M> {
+        self.enq(cache, Task::prefill)
+    }
+
+    fn enq(
+        &self,
+        mut cache: Cache<M::Storage>,
+        task: impl FnOnce(
+            Arc<Mutex<Option<Cache<M::Storage>>>>,
+            UnboundedSender<utok>,
+            InFlight,
+        ) -> Task<M::Storage>,
+    ) -> TaskHandle<M> {
         let max = self.handle.model.max_seq_len() as usize;
         cache.reset_within(max / 4, max / 4 * 3);
         // 生成推理任务与会话的交互管道
         let cache = Arc::new(Mutex::new(Some(cache)));
         let (sender, receiver) = unbounded_channel();
+        let inflight = InFlight::new(&self.handle.inflight);
         self.handle
             .batcher
-            .enq(Task::new(cache.clone(), sample, sender));
+            .enq(task(cache.clone(), sender, inflight));
         TaskHandle {
             receiver: Some(receiver),
             cache,
@@ -45,18 +105,24 @@ impl<M: CausalLM> ServiceComponent<M> {
 
     pub(super) async fn decode(&self, x: &mut TaskHandle<M>) -> Option<String> {
         loop {
-            let s = x.receiver.as_mut().unwrap().recv().await.map(|token| {
-                // detokenize and denormalize the token
-                let ServiceComponent {
-                    normalizer,
-                    tokenizer,
-                    ..
-                } = self;
-                normalizer.decode(tokenizer.decode(token))
-            })?;
-            let s = x.buffer.push(s.as_bytes());
-            if !s.is_empty() {
-                return Some(s);
+            let Some(token) = x.receiver.as_mut().unwrap().recv().await else {
+                // 推理结束，取出缓存中剩余的字节
+                let s = x.buffer.flush();
+                return if s.is_empty() {
+                    None
+                } else {
+                    Some(s.to_string())
+                };
+            };
+            // detokenize and denormalize the token
+            let ServiceComponent {
+                normalizer,
+                tokenizer,
+                ..
+            } = self;
+            let s = normalizer.decode(tokenizer.decode(token));
+            if let Some(s) = x.buffer.push(s.as_bytes()) {
+                return Some(s.to_string());
             }
         }
     }
@@ -64,25 +130,78 @@ impl<M: CausalLM> ServiceComponent<M> {
 
 pub(crate) struct Dispatcher<M: CausalLM> {
     pub model: M,
+    policy: BatchingPolicy,
     pub(super) batcher: Batcher<Task<M::Storage>>,
+    pub metrics: Mutex<ServiceMetrics>,
+    inflight: Arc<InFlightCounter>,
+    panicked: AtomicBool,
 }
 
-impl<M: CausalLM> From<M> for Dispatcher<M> {
+impl<M: CausalLM> Dispatcher<M> {
     #[inline]
-    fn from(model: M) -> Self {
+    pub fn new(model: M, policy: BatchingPolicy) -> Self {
         Self {
             model,
+            policy,
             batcher: Batcher::new(),
+            metrics: Default::default(),
+            inflight: Default::default(),
+            panicked: Default::default(),
         }
     }
-}
 
-impl<M: CausalLM> Dispatcher<M> {
     /// 通过关闭任务队列通知推理线程退出。
     #[inline]
     pub fn stop(&self) {
         self.batcher.shutdown();
     }
+
+    /// 尚未完成的推理任务数。
+    #[inline]
+    pub fn inflight(&self) -> usize {
+        self.inflight.get()
+    }
+
+    /// 阻塞至多 `timeout` 等待在途的推理任务全部完成，返回是否已全部完成。
+    #[inline]
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        self.inflight.wait_idle(timeout)
+    }
+
+    /// 异步等待在途的推理任务全部完成。
+    #[inline]
+    pub async fn idle(&self) {
+        self.inflight.idle().await
+    }
+
+    /// 等待组批的推理任务数。
+    #[inline]
+    pub fn queue_depth(&self) -> usize {
+        self.batcher.len()
+    }
+
+    /// 推理线程是否因 panic 退出。
+    #[inline]
+    pub fn panicked(&self) -> bool {
+        self.panicked.load(Relaxed)
+    }
+
+    /// 生成一个守卫，持有守卫的线程 panic 时标记推理线程已崩溃。
+    #[inline]
+    pub(crate) fn panic_guard(&self) -> PanicGuard {
+        PanicGuard(&self.panicked)
+    }
+}
+
+pub(crate) struct PanicGuard<'a>(&'a AtomicBool);
+
+impl Drop for PanicGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.store(true, Relaxed);
+        }
+    }
 }
 
 impl<M> Dispatcher<M>
@@ -91,34 +210,89 @@ where
     M::Storage: Send,
 {
     pub fn run(self: Arc<Self>) {
-        while let Some(tasks) = Some(self.batcher.deq()).filter(|t| !t.is_empty()) {
+        let _guard = self.panic_guard();
+        let BatchingPolicy {
+            max_batch_size,
+            prefill_chunk_tokens: chunk,
+            max_decode_per_step,
+        } = self.policy;
+        while let Some(tasks) = Some(self.batcher.deq(max_batch_size)).filter(|t| !t.is_empty()) {
+            // 束搜索任务按活跃的假设数占用批大小，放不下的任务留到下一轮推理
+            let mut batch_size = 0;
+            let (tasks, deferred): (Vec<_>, Vec<_>) = tasks.into_iter().partition(|t| {
+                let n = t.batch_size();
+                batch_size + n <= max_batch_size && {
+                    batch_size += n;
+                    true
+                }
+            });
+            deferred.into_iter().for_each(|t| self.batcher.enq(t));
+            // 束搜索任务单独推理一步
+            let (beams, tasks): (Vec<_>, Vec<_>) = tasks.into_iter().partition(Task::is_beam);
+            if !beams.is_empty() {
+                self.beam_step(beams, chunk, max_batch_size);
+            }
+            // 超出单步采样上限的任务留到下一轮推理
+            let mut num_sampling = 0;
+            let (tasks, deferred): (Vec<_>, Vec<_>) = tasks.into_iter().partition(|t| {
+                t.is_prefill() || {
+                    num_sampling += 1;
+                    num_sampling <= max_decode_per_step
+                }
+            });
+            deferred.into_iter().for_each(|t| self.batcher.enq(t));
+            if tasks.is_empty() {
+                continue;
+            }
             // 锁定所有请求的缓存
             let mut caches = tasks.iter().map(Task::lock_cache).collect::<Vec<_>>();
-            // 统计每个任务的查询长度
+            // 统计每个任务这次计算的查询长度
             let num_query = caches
                 .iter()
-                .map(|c| c.as_ref().map_or(0, |c| c.query().len()))
+                .map(|c| c.as_ref().map_or(0, |c| c.chunk(chunk).len()))
+                .collect::<Vec<_>>();
+            // 查询超出分块长度的任务这次不采样，只计算缓存
+            let chunked = caches
+                .iter()
+                .map(|c| c.as_ref().is_some_and(|c| c.query().len() > chunk))
                 .collect::<Vec<_>>();
             if num_query.iter().all(|&n| n == 0) {
                 continue;
             }
+            let time = Instant::now();
+            let prefill = num_query.iter().filter(|&&n| n > 1).sum::<usize>();
+            let decode = num_query.iter().filter(|&&n| n == 1).count();
             // 词嵌入
             let queries = caches
                 .iter()
-                .filter_map(|c| c.as_ref().map(Cache::query).filter(|q| !q.is_empty()))
+                .filter_map(|c| c.as_ref().map(|c| c.chunk(chunk)).filter(|q| !q.is_empty()))
                 .flatten()
                 .copied();
             let token_embedded = self.model.token_embed(queries);
             // 推理
-            let queries = caches
-                .iter_mut()
-                .filter_map(|c| c.as_mut().map(Cache::as_ctx).filter(|q| q.seq_len() > 0));
+            let queries = caches.iter_mut().filter_map(|c| {
+                c.as_mut()
+                    .map(|c| c.as_ctx_within(chunk))
+                    .filter(|q| q.seq_len() > 0)
+            });
             let hidden_state = self.model.forward(queries, token_embedded);
+            // 需要重复惩罚的任务收集上下文中的词
+            let histories = zip(&tasks, &caches)
+                .map(|(t, c)| match c.as_ref() {
+                    Some(c) if t.sample().repetition_penalty != 1. => {
+                        c.slice_tail(c.pos()).to_vec()
+                    }
+                    _ => vec![],
+                })
+                .collect::<Vec<_>>();
             drop(caches);
+            // 预填充任务和分块计算的任务不采样，直接将查询标记为已缓存
+            zip(&tasks, &chunked)
+                .filter(|(t, &chunked)| t.is_prefill() || chunked)
+                .for_each(|(t, _)| t.commit_cache(chunk));
             // 采样
-            let num_decode = tasks
-                .iter()
-                .map(|t| if t.is_alive() { 1 } else { 0 })
+            let num_decode = zip(&tasks, &chunked)
+                .map(|(t, &chunked)| if t.is_decoding() && !chunked { 1 } else { 0 })
                 .collect::<Vec<_>>();
             let decoding =
                 zip(num_query, &num_decode).map(|(num_query, &num_decode)| DecodingMeta {
@@ -127,11 +301,31 @@ where
                 });
             let logits = self.model.decode(decoding, hidden_state);
             // 采样
-            let args = zip(&tasks, &num_decode).map(|(t, &num_decode)| SampleMeta {
-                num_decode,
-                args: t.sample().clone(),
-            });
+            let args =
+                zip(zip(&tasks, &num_decode), histories).map(|((t, &num_decode), history)| {
+                    SampleMeta {
+                        num_decode,
+                        args: t.sample().clone(),
+                        history,
+                        mirostat: t.mirostat().clone(),
+                    }
+                });
             let tokens = self.model.sample(args, logits);
+            // 分块计算的任务重新入队，在下一轮推理中继续计算剩余的查询
+            let (tasks, num_decode): (Vec<_>, Vec<_>) = zip(zip(tasks, num_decode), chunked)
+                .filter_map(|((task, num_decode), chunked)| {
+                    if !chunked {
+                        return Some((task, num_decode));
+                    }
+                    if task.is_alive() {
+                        self.batcher.enq(task);
+                    }
+                    None
+                })
+                .unzip();
+            // 统计
+            let metrics = InferenceMetrics::new(prefill, decode, time.elapsed());
+            self.metrics.lock().unwrap().record(&metrics);
             // 为每次推理启动一个任务执行发射
             let self_ = self.clone();
             tokio::task::spawn_blocking(move || {
@@ -144,6 +338,9 @@ where
                     .zip(tokens)
                     .filter(|(_, token)| *token != eos)
                     .for_each(|(mut task, token)| {
+                        if let Some(latency) = task.first_token() {
+                            self_.metrics.lock().unwrap().record_first_token(latency);
+                        }
                         if task.push(token, min, max) {
                             self_.batcher.enq(task);
                         }
@@ -151,27 +348,135 @@ where
             });
         }
     }
-}
-
-#[derive(Clone, Default, Debug)]
-struct Utf8Buffer(Vec<u8>);
 
-impl Utf8Buffer {
-    fn push(&mut self, bytes: impl AsRef<[u8]>) -> String {
-        self.0.extend_from_slice(bytes.as_ref());
-        let mut len = match str::from_utf8(&self.0) {
-            Ok(_) => self.0.len(),
-            Err(e) => e.valid_up_to(),
+    /// 对束搜索任务执行一步推理，尚未开始搜索的任务计算提示词，已经开始的任务扩展每个活跃的假设。
+    ///
+    /// 搜索结束时以得分最高的假设替换任务的缓存，然后发射生成的词。束宽不超过 `max_batch_size`。
+    fn beam_step(&self, mut tasks: Vec<Task<M::Storage>>, chunk: usize, max_batch_size: usize) {
+        tasks.retain(Task::is_alive);
+        let mut beams = tasks
+            .iter_mut()
+            .map(|t| t.beam_mut().take())
+            .collect::<Vec<_>>();
+        let mut caches = tasks.iter().map(Task::lock_cache).collect::<Vec<_>>();
+        // 尚未开始搜索的任务计算查询的分块，最后一块解码；已经开始的任务为每个活跃的假设解码一个词
+        let mut queries = Vec::new();
+        let mut decoding = Vec::new();
+        for (c, b) in zip(&caches, &beams) {
+            match (c.as_ref(), b) {
+                (None, _) => {}
+                (Some(c), None) => {
+                    let query = c.chunk(chunk);
+                    if !query.is_empty() {
+                        queries.extend_from_slice(query);
+                        decoding.push(DecodingMeta {
+                            num_query: query.len(),
+                            num_decode: (query.len() == c.query().len()) as usize,
+                        });
+                    }
+                }
+                (Some(_), Some(b)) => {
+                    queries.extend(b.queries());
+                    decoding.extend(b.queries().map(|_| DecodingMeta {
+                        num_query: 1,
+                        num_decode: 1,
+                    }));
+                }
+            }
+        }
+        if decoding.is_empty() {
+            return;
+        }
+        let time = Instant::now();
+        let prefill = decoding
+            .iter()
+            .map(|d| d.num_query)
+            .filter(|&n| n > 1)
+            .sum::<usize>();
+        let decode = decoding.iter().filter(|d| d.num_query == 1).count();
+        // 推理
+        let token_embedded = self.model.token_embed(queries);
+        let queries = zip(&mut caches, &mut beams).flat_map(|(c, b)| match (c.as_mut(), b) {
+            (None, _) => vec![],
+            (Some(c), None) => Some(c.as_ctx_within(chunk))
+                .filter(|q| q.seq_len() > 0)
+                .into_iter()
+                .collect(),
+            (Some(c), Some(b)) => b.as_ctx(c.cached_len()).collect(),
+        });
+        let hidden_state = self.model.forward(queries, token_embedded);
+        let logits = self.model.decode(decoding, hidden_state);
+        let Some(log_probs) = self.model.log_softmax(logits) else {
+            warn!("beam search is not supported by this model, fall back to sampling");
+            // 查询没有标记为已缓存，在普通采样中重新计算
+            drop(caches);
+            for mut task in tasks {
+                task.disable_beam();
+                self.batcher.enq(task);
+            }
+            return;
         };
-        while len + size_of::<char>() <= self.0.len() {
-            len += 1;
-            match str::from_utf8(&self.0[len..]) {
-                Ok(s) => len += s.as_bytes().len(),
-                Err(e) => len += e.valid_up_to(),
+        // 扩展假设，搜索结束的任务取出生成的词
+        let mut log_probs = log_probs.into_iter();
+        let mut generated = Vec::with_capacity(tasks.len());
+        for ((t, c), b) in zip(zip(&tasks, &mut caches), &mut beams) {
+            // 会话已经取回缓存的任务直接结束
+            let Some(c) = c.as_mut() else {
+                generated.push(Some(vec![]));
+                continue;
+            };
+            match b {
+                // 没有查询的任务直接结束
+                None if c.query().is_empty() => {
+                    generated.push(Some(vec![]));
+                    continue;
+                }
+                None => {
+                    let decode = c.query().len() <= chunk;
+                    c.commit_within(chunk);
+                    if decode {
+                        let width = t.sample().beam_width.min(max_batch_size);
+                        let max_tokens = t.sample().beam_max_tokens;
+                        let lp = log_probs.next().unwrap();
+                        *b = Some(Beam::start(&self.model, c, &lp, width, max_tokens));
+                    }
+                }
+                Some(b) => {
+                    let lp = log_probs.by_ref().take(b.num_active()).collect::<Vec<_>>();
+                    b.step(&self.model, c, &lp);
+                }
+            }
+            generated.push(match b.take_if(|b| b.is_done()) {
+                Some(b) => {
+                    let (cache, tokens) = b.finish();
+                    c.accept(cache, &tokens);
+                    Some(tokens)
+                }
+                None => None,
+            });
+        }
+        drop(caches);
+        // 统计
+        let metrics = InferenceMetrics::new(prefill, decode, time.elapsed());
+        self.metrics.lock().unwrap().record(&metrics);
+        // 发射搜索结束的任务生成的词，其他任务重新入队
+        for ((mut task, beam), tokens) in zip(zip(tasks, beams), generated) {
+            match tokens {
+                Some(tokens) => {
+                    if let Some(latency) = task.first_token() {
+                        self.metrics.lock().unwrap().record_first_token(latency);
+                    }
+                    for token in tokens {
+                        if !task.send(token) {
+                            break;
+                        }
+                    }
+                }
+                None => {
+                    *task.beam_mut() = beam;
+                    self.batcher.enq(task);
+                }
             }
         }
-        let s = self.0.split_off(len);
-        let s = replace(&mut self.0, s);
-        unsafe { String::from_utf8_unchecked(s) }
     }
 }
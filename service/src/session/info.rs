@@ -0,0 +1,83 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// 会话信息，用于监控服务状态。
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    /// 会话在服务中的唯一标识。
+    pub id: usize,
+    /// 会话中对话的 token 总数。
+    pub token_count: usize,
+    /// 会话中已缓存的 token 数。
+    pub cached_tokens: usize,
+    /// 会话创建的时刻。
+    pub created_at: Instant,
+    /// 会话最后一次活动的时刻。
+    pub last_active: Instant,
+}
+
+/// 服务中所有会话的登记表。
+#[derive(Default)]
+pub(crate) struct SessionRegistry {
+    next: AtomicUsize,
+    sessions: Mutex<HashMap<usize, SessionInfo>>,
+}
+
+impl SessionRegistry {
+    /// 登记一个新的会话，返回会话的标识。
+    pub fn register(&self) -> usize {
+        let id = self.next.fetch_add(1, Relaxed);
+        let now = Instant::now();
+        self.sessions.lock().unwrap().insert(
+            id,
+            SessionInfo {
+                id,
+                token_count: 0,
+                cached_tokens: 0,
+                created_at: now,
+                last_active: now,
+            },
+        );
+        id
+    }
+
+    /// 更新会话状态并刷新活动时刻。
+    pub fn update(&self, id: usize, token_count: usize, cached_tokens: usize) {
+        if let Some(info) = self.sessions.lock().unwrap().get_mut(&id) {
+            info.token_count = token_count;
+            info.cached_tokens = cached_tokens;
+            info.last_active = Instant::now();
+        }
+    }
+
+    /// 注销会话。
+    #[inline]
+    pub fn remove(&self, id: usize) {
+        self.sessions.lock().unwrap().remove(&id);
+    }
+
+    /// 存活的会话数。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// 按标识顺序列出所有会话。
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut ans = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        ans.sort_unstable_by_key(|info| info.id);
+        ans
+    }
+}
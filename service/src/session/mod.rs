@@ -1,44 +1,60 @@
 ﻿mod batcher;
+mod beam;
 mod cache;
+mod chat;
 mod dialog;
 mod dispatch;
+mod info;
 mod task;
 
-use crate::ServiceComponent;
+use crate::{ServiceComponent, ServiceError};
 use cache::Cache;
-use causal_lm::{CausalLM, SampleArgs};
+use causal_lm::{CausalLM, MirostatState, SampleArgs};
+use common::utok;
 use dialog::Dialog;
 use dispatch::TaskHandle;
 use log::info;
 use std::{
     cmp::Ordering::{Equal, Greater, Less},
     error, fmt,
-    sync::Arc,
+    sync::{atomic::Ordering::Relaxed, Arc},
     vec,
 };
 
+pub use chat::{ChatMessage, ChatRole, ChatSession};
 pub(crate) use dispatch::Dispatcher;
+pub use info::SessionInfo;
+pub(crate) use info::SessionRegistry;
 
 /// 会话。
 pub struct Session<M: CausalLM> {
     component: Arc<ServiceComponent<M>>,
+    id: usize,
     pub sample: SampleArgs,
+    /// Mirostat 采样的状态，在会话的多次推理之间延续。
+    pub mirostat: MirostatState,
 
     dialog: Dialog,
     cache: Option<Cache<M::Storage>>,
 }
 
 /// 对话错误类型。
-///
-/// 目前唯一可能的对话错误是增量对话中句子位置异常。
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-pub struct ChatError;
+pub enum ChatError {
+    /// 增量对话中句子位置异常。
+    Position,
+    /// 服务正在关闭，不再接受新的推理任务。
+    ShuttingDown,
+}
 
 impl error::Error for ChatError {}
 impl fmt::Display for ChatError {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "chat error")
+        match self {
+            Self::Position => write!(f, "unexpected dialog position"),
+            Self::ShuttingDown => write!(f, "service is shutting down"),
+        }
     }
 }
 
@@ -46,8 +62,10 @@ impl<M: CausalLM> From<Arc<ServiceComponent<M>>> for Session<M> {
     #[inline]
     fn from(component: Arc<ServiceComponent<M>>) -> Self {
         Self {
+            id: component.sessions.register(),
             component,
             sample: Default::default(),
+            mirostat: Default::default(),
 
             dialog: Default::default(),
             cache: Default::default(),
@@ -55,23 +73,61 @@ impl<M: CausalLM> From<Arc<ServiceComponent<M>>> for Session<M> {
     }
 }
 
+impl<M: CausalLM> Drop for Session<M> {
+    #[inline]
+    fn drop(&mut self) {
+        self.component.sessions.remove(self.id);
+    }
+}
+
 impl<M: CausalLM> Session<M> {
+    /// 会话在服务中的唯一标识。
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
     #[inline]
     pub fn dialog_pos(&self) -> usize {
         self.dialog.num_sentences()
     }
 
+    /// 会话当前的 token 序列，包括系统提示词、所有对话和预填充的回答。
+    pub fn get_tokens(&self) -> Vec<utok> {
+        let end = self.dialog.num_tokens();
+        let (mut tokens, _) = self.dialog.window(end);
+        if let Some(cache) = &self.cache {
+            tokens.extend_from_slice(cache.slice_tail(end));
+        }
+        tokens
+    }
+
+    /// 将会话当前的 token 序列解码为文本。
+    pub fn get_text(&self) -> String {
+        let ServiceComponent {
+            tokenizer,
+            normalizer,
+            ..
+        } = &*self.component;
+        let text = tokenizer.decode_batch(&self.get_tokens());
+        normalizer.decode(&text).into_owned()
+    }
+
     /// 复制当前会话。
     pub fn fork(&self) -> Self {
-        Self {
+        let ans = Self {
             component: self.component.clone(),
+            id: self.component.sessions.register(),
             sample: self.sample.clone(),
+            mirostat: self.mirostat.fork(),
             dialog: self.dialog.clone(),
             cache: self
                 .cache
                 .as_ref()
                 .map(|cache| cache.duplicate(&self.component.handle.model)),
-        }
+        };
+        ans.update_info();
+        ans
     }
 
     /// 回滚对话到第 `dialog_pos` 个句子。
@@ -88,62 +144,161 @@ impl<M: CausalLM> Session<M> {
                     let (tokens, pos) = self.dialog.window(len);
                     cache.reset_with(tokens, pos);
                 }
+                self.update_info();
                 Ok(())
             }
             Equal => Ok(()),
-            Greater => Err(ChatError),
+            Greater => Err(ChatError::Position),
         }
     }
 
+    /// 以新的系统提示词重置会话，清空对话历史，保留会话标识和缓存空间。
+    pub fn set_system_prompt(&mut self, system: &str) {
+        let system = self.component.template.apply_system(system);
+        let tokens = self.component.encode_templated(&system);
+
+        self.dialog = Dialog::with_system(tokens.clone());
+        self.mirostat.reset();
+        self.cache
+            .get_or_insert_with(|| Cache::new(&self.component.handle.model, vec![]))
+            .reset_with(tokens, 0);
+        self.update_info();
+    }
+
     /// 用 dialog 填充会话。
     pub fn extend<'a>(&mut self, dialog: impl IntoIterator<Item = &'a str>) {
         let eos = self.component.handle.model.eos_token();
         let cache = self
             .cache
             .get_or_insert_with(|| Cache::new(&self.component.handle.model, vec![]));
+        // 预填充的回答尚未加入对话，先将其结束
+        Self::commit_answer(&mut self.dialog, cache, eos);
+        // 套用模板后成批规范化
+        let first = self.dialog.num_sentences();
+        let texts = dialog
+            .into_iter()
+            .enumerate()
+            .map(|(i, s)| {
+                if (first + i) % 2 == 0 {
+                    self.component.template.apply_chat(s)
+                } else {
+                    s.into()
+                }
+            })
+            .collect::<Vec<_>>();
+        let texts = texts.iter().map(|s| &**s).collect::<Vec<_>>();
         // 填充对话
-        for s in dialog {
+        for s in self.component.normalizer.normalize_batch(&texts) {
             let prompt = self.dialog.num_sentences() % 2 == 0;
 
+            let ServiceComponent {
+                tokenizer,
+                template,
+                ..
+            } = &*self.component;
             let s = if prompt {
-                self.component.template.apply_chat(s)
+                tokenizer.encode_with_options(&s, template.add_bos(), template.add_eos())
             } else {
-                s.into()
-            };
-            let s = self.component.normalizer.encode(&s);
-            let mut s = self.component.tokenizer.encode(&s);
-            if !prompt {
+                let mut s = tokenizer.encode(&s);
                 s.push(eos);
-            }
+                s
+            };
 
             cache.extend(&s);
             self.dialog.push(s);
             assert_eq!(cache.end(), self.dialog.num_tokens());
         }
+        self.update_info();
+    }
+
+    /// 用已知的回答片段预填充缓存，之后启动的推理将接续这段回答生成。
+    ///
+    /// 只能在用户输入之后调用，预填充的 token 和之后生成的 token 将组成同一个回答。
+    /// 服务正在关闭时返回 [`ChatError::ShuttingDown`]。
+    pub async fn prefill(&mut self, text: &str) -> Result<(), ChatError> {
+        if self.component.check_running().is_err() {
+            return Err(ChatError::ShuttingDown);
+        }
+        if self.dialog.num_sentences() % 2 == 0 {
+            return Err(ChatError::Position);
+        }
+        let text = self.component.normalizer.encode(text);
+        let tokens = self.component.tokenizer.encode(&text);
+
+        let mut cache = self.cache.take().unwrap();
+        cache.extend(&tokens);
+        let mut handle = self.component.prefill(cache);
+        handle.wait().await;
+        let mut cache = handle.take();
+        cache.cleanup();
+        self.cache = Some(cache);
+        self.update_info();
+        Ok(())
     }
 
     /// 启动推理任务，返回忙会话。
-    pub fn chat(&mut self) -> BusySession<M> {
+    ///
+    /// 服务正在关闭时不再启动新的推理任务，返回 [`ServiceError::ShuttingDown`]。
+    pub fn chat(&mut self) -> Result<BusySession<M>, ServiceError> {
+        self.component.check_running()?;
+        self.fit_history();
+        self.update_info();
         let sample = self.sample.clone();
         let cache = self.cache.take().unwrap();
-        let handle = self.component.infer(sample, cache);
-        BusySession {
+        // 回答在任务结束后加入对话，不能清理
+        let keep = self.dialog.num_tokens();
+        let mirostat = self.mirostat.clone();
+        let handle = self.component.infer(sample, mirostat, cache, keep);
+        Ok(BusySession {
             session: self,
             handle,
-        }
+        })
     }
 
     fn restore_cache(&mut self, mut cache: Cache<M::Storage>) {
-        let end = self.dialog.num_tokens();
+        let eos = self.component.handle.model.eos_token();
+        Self::commit_answer(&mut self.dialog, &mut cache, eos);
+        cache.cleanup();
+        info!("Cache restored at {} tokens", cache.end());
+        self.cache = Some(cache);
+        self.update_info();
+    }
+
+    /// 对话过长时，以完整的句子为单位丢弃最早的对话，重置缓存。
+    fn fit_history(&mut self) {
+        let max_tokens = self.component.handle.model.max_seq_len() as usize / 4 * 3;
+        let max_messages = self.component.max_history_messages.load(Relaxed);
+        let start = self.dialog.fit(max_tokens, max_messages);
+
+        let cache = self.cache.as_mut().unwrap();
+        if start > cache.pos() {
+            let end = self.dialog.num_tokens();
+            let (mut tokens, pos) = self.dialog.window(end - start);
+            // 保留预填充的回答
+            tokens.extend_from_slice(cache.slice_tail(end));
+            cache.reset_with(tokens, pos);
+            info!("History truncated at {pos} tokens");
+        }
+    }
+
+    /// 将缓存中超出对话的 token 作为一个回答加入对话。
+    fn commit_answer(dialog: &mut Dialog, cache: &mut Cache<M::Storage>, eos: utok) {
+        let end = dialog.num_tokens();
         if cache.end() > end {
             // 无论忙会话为何丢弃，只要生成了新句子，就补充一个结束符
-            cache.push(self.component.handle.model.eos_token());
+            cache.push(eos);
             // 只要忙会话收集到任何 token，就生成一个新的句子
-            self.dialog.push(cache.slice_tail(end).to_vec());
+            dialog.push(cache.slice_tail(end).to_vec());
         }
-        cache.cleanup();
-        info!("Cache restored at {} tokens", cache.end());
-        self.cache = Some(cache);
+    }
+
+    /// 向服务登记表报告会话状态。
+    fn update_info(&self) {
+        self.component.sessions.update(
+            self.id,
+            self.dialog.num_tokens(),
+            self.cache.as_ref().map_or(0, Cache::cached_len),
+        );
     }
 }
 
@@ -172,6 +327,9 @@ impl<M: CausalLM> Drop for BusySession<'_, M> {
 pub struct Generator<M: CausalLM> {
     component: Arc<ServiceComponent<M>>,
     handle: TaskHandle<M>,
+    /// 生成结束后需要存入精确匹配缓存的提示词。
+    prompt: Option<Vec<utok>>,
+    finished: bool,
 }
 
 impl<M: CausalLM> Generator<M> {
@@ -181,22 +339,57 @@ impl<M: CausalLM> Generator<M> {
         sample: SampleArgs,
     ) -> Self {
         let prompt = component.template.normalize(prompt.as_ref());
-        let prompt = component.normalizer.encode(&prompt);
-        let tokens = component.tokenizer.encode(&prompt);
-        let handle = component.infer(sample, Cache::new(&component.handle.model, tokens));
-        Self { handle, component }
+        let tokens = component.encode_templated(&prompt);
+        // 只有贪心采样且不修改 logits 的结果只由提示词决定，可以缓存
+        let deterministic = sample.is_argmax()
+            && sample.beam_width == 1
+            && sample.repetition_penalty == 1.
+            && sample.logit_bias.is_empty();
+        let (cached, caching) = match &mut *component.exact_match.lock().unwrap() {
+            Some(exact_match) if deterministic => (exact_match.get(&tokens), true),
+            _ => (None, false),
+        };
+        let (handle, prompt) = match cached {
+            Some(response) => (TaskHandle::replay(&response), None),
+            None => {
+                let prompt = caching.then(|| tokens.clone());
+                let cache = Cache::new(&component.handle.model, tokens);
+                // 需要存入精确匹配缓存时保留所有 token
+                let keep = if caching { 0 } else { usize::MAX };
+                let mirostat = MirostatState::default();
+                (component.infer(sample, mirostat, cache, keep), prompt)
+            }
+        };
+        Self {
+            component,
+            handle,
+            prompt,
+            finished: false,
+        }
     }
 
     /// 接收模型解码产生的文本。
     #[inline]
     pub async fn decode(&mut self) -> Option<String> {
-        self.component.decode(&mut self.handle).await
+        let ans = self.component.decode(&mut self.handle).await;
+        self.finished |= ans.is_none();
+        ans
     }
 }
 
 impl<M: CausalLM> Drop for Generator<M> {
     #[inline]
     fn drop(&mut self) {
-        let _ = self.handle.take();
+        let cache = self.handle.try_take();
+        // 只缓存完整生成的结果
+        let Some(prompt) = self.prompt.as_ref().filter(|_| self.finished) else {
+            return;
+        };
+        if let Some(cache) = cache.filter(|c| c.pos() == 0) {
+            let response = cache.slice_tail(prompt.len()).to_vec();
+            if let Some(exact_match) = &mut *self.component.exact_match.lock().unwrap() {
+                exact_match.insert(prompt, response);
+            }
+        }
     }
 }
@@ -1,12 +1,33 @@
-﻿use super::cache::Cache;
-use causal_lm::SampleArgs;
+﻿use super::{beam::Beam, cache::Cache};
+use causal_lm::{MirostatState, SampleArgs};
 use common::utok;
-use std::sync::{Arc, Mutex, MutexGuard};
-use tokio::sync::mpsc::UnboundedSender;
+use std::{
+    pin::pin,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering::{AcqRel, Acquire},
+        },
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc::UnboundedSender, Notify};
 
 pub(super) struct Task<Storage> {
     sample: SampleArgs,
+    /// 会话的 Mirostat 采样状态。
+    mirostat: MirostatState,
     sender: UnboundedSender<utok>,
+    /// 任务创建的时刻，产生首个 token 后清空。
+    created: Option<Instant>,
+    /// 是否为不采样的预填充任务。
+    prefill: bool,
+    /// 清理缓存时需要保留的 token 在对话中的起始位置。
+    keep: usize,
+    /// 束搜索的状态，尚未开始搜索时为空。
+    beam: Option<Beam<Storage>>,
+    _inflight: InFlight,
 
     cache: Arc<Mutex<Option<Cache<Storage>>>>,
 }
@@ -16,37 +37,213 @@ impl<Storage> Task<Storage> {
     pub fn new(
         cache: Arc<Mutex<Option<Cache<Storage>>>>,
         sample: SampleArgs,
+        mirostat: MirostatState,
+        keep: usize,
         sender: UnboundedSender<utok>,
+        inflight: InFlight,
     ) -> Self {
         Self {
             sample,
+            mirostat,
             sender,
+            created: Some(Instant::now()),
+            prefill: false,
+            keep,
+            beam: None,
+            _inflight: inflight,
             cache,
         }
     }
 
+    /// 生成只计算缓存而不采样的预填充任务。
+    #[inline]
+    pub fn prefill(
+        cache: Arc<Mutex<Option<Cache<Storage>>>>,
+        sender: UnboundedSender<utok>,
+        inflight: InFlight,
+    ) -> Self {
+        Self {
+            prefill: true,
+            ..Self::new(
+                cache,
+                Default::default(),
+                Default::default(),
+                0,
+                sender,
+                inflight,
+            )
+        }
+    }
+
     #[inline]
     pub fn sample(&self) -> &SampleArgs {
         &self.sample
     }
     #[inline]
+    pub fn mirostat(&self) -> &MirostatState {
+        &self.mirostat
+    }
+    #[inline]
     pub fn is_alive(&self) -> bool {
         !self.sender.is_closed()
     }
     #[inline]
+    pub fn is_prefill(&self) -> bool {
+        self.prefill
+    }
+    #[inline]
+    pub fn is_decoding(&self) -> bool {
+        !self.prefill && self.is_alive()
+    }
+    /// 是否使用束搜索解码。
+    #[inline]
+    pub fn is_beam(&self) -> bool {
+        !self.prefill && self.sample.beam_width > 1
+    }
+    /// 任务在一次推理中占用的批大小，已经开始的束搜索任务为活跃的假设数。
+    #[inline]
+    pub fn batch_size(&self) -> usize {
+        self.beam.as_ref().map_or(1, Beam::num_active)
+    }
+    #[inline]
+    pub fn beam_mut(&mut self) -> &mut Option<Beam<Storage>> {
+        &mut self.beam
+    }
+    /// 模型不支持束搜索时退化为普通采样。
+    #[inline]
+    pub fn disable_beam(&mut self) {
+        self.sample.beam_width = 1;
+        self.beam = None;
+    }
+    #[inline]
     pub fn lock_cache(&self) -> MutexGuard<Option<Cache<Storage>>> {
         self.cache.lock().unwrap()
     }
 
+    /// 如果这是任务产生的首个 token，返回首 token 延迟。
+    #[inline]
+    pub fn first_token(&mut self) -> Option<Duration> {
+        self.created.take().map(|t| t.elapsed())
+    }
+
+    /// 直接发射 `token` 而不加入缓存，返回接收方是否仍然存在。
+    #[inline]
+    pub fn send(&self, token: utok) -> bool {
+        self.sender.send(token).is_ok()
+    }
+
+    /// 将缓存中的查询中至多前 `max` 个 token 标记为已缓存。
+    #[inline]
+    pub fn commit_cache(&self, max: usize) {
+        if let Some(cache) = self.cache.lock().unwrap().as_mut() {
+            cache.commit_within(max);
+        }
+    }
+
     #[inline]
     pub fn push(&mut self, token: utok, min: usize, max: usize) -> bool {
+        self.sample.advance_seed();
         if self.sender.send(token).is_ok() {
             if let Some(cache) = self.cache.lock().unwrap().as_mut() {
                 cache.push(token);
                 cache.reset_within(min, max);
+                // 窗口移出的部分积累过多时及时清理，避免长时间生成时 token 序列无限增长
+                if cache.cached_start() >= min {
+                    cache.cleanup_before(self.keep);
+                }
                 return true;
             }
         }
         false
     }
 }
+
+/// 在途任务的计数，归零时唤醒等待的线程和异步任务。
+#[derive(Default)]
+pub(super) struct InFlightCounter {
+    count: AtomicUsize,
+    lock: Mutex<()>,
+    idle: Condvar,
+    notify: Notify,
+}
+
+impl InFlightCounter {
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.count.load(Acquire)
+    }
+
+    /// 阻塞至多 `timeout` 等待计数归零，返回是否已归零。
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let guard = self.lock.lock().unwrap();
+        let _guard = self
+            .idle
+            .wait_timeout_while(guard, timeout, |_| self.get() != 0)
+            .unwrap();
+        self.get() == 0
+    }
+
+    /// 异步等待计数归零。
+    pub async fn idle(&self) {
+        loop {
+            // 先登记等待再检查计数，避免错过检查之后的唤醒
+            let mut notified = pin!(self.notify.notified());
+            notified.as_mut().enable();
+            if self.get() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// 在途任务的计数凭证，随任务释放而归还。
+pub(super) struct InFlight(Arc<InFlightCounter>);
+
+impl InFlight {
+    #[inline]
+    pub fn new(counter: &Arc<InFlightCounter>) -> Self {
+        counter.count.fetch_add(1, AcqRel);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let counter = &*self.0;
+        if counter.count.fetch_sub(1, AcqRel) == 1 {
+            // 经过锁再唤醒，保证等待方检查计数后已经进入等待
+            drop(counter.lock.lock());
+            counter.idle.notify_all();
+            counter.notify.notify_waiters();
+        }
+    }
+}
+
+#[test]
+fn test_inflight_counter() {
+    use std::thread;
+
+    let counter = Arc::new(InFlightCounter::default());
+    assert!(counter.wait_idle(Duration::ZERO));
+
+    let inflight = InFlight::new(&counter);
+    assert_eq!(counter.get(), 1);
+    assert!(!counter.wait_idle(Duration::from_millis(10)));
+
+    let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+    let idle = runtime.spawn({
+        let counter = counter.clone();
+        async move { counter.idle().await }
+    });
+    let waiting = thread::spawn({
+        let counter = counter.clone();
+        move || counter.wait_idle(Duration::from_secs(60))
+    });
+    thread::sleep(Duration::from_millis(10));
+    drop(inflight);
+    // 计数归零时同步和异步的等待方都被唤醒
+    assert!(waiting.join().unwrap());
+    runtime.block_on(idle).unwrap();
+    assert_eq!(counter.get(), 0);
+}
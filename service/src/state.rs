@@ -0,0 +1,51 @@
+use std::{error, fmt};
+
+/// 服务状态。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[repr(u8)]
+pub enum ServiceState {
+    /// 正常接受新的会话。
+    Running,
+    /// 不再接受新的会话，等待在途的推理任务完成。
+    Draining,
+    /// 推理线程已通知退出。
+    Stopped,
+}
+
+impl From<u8> for ServiceState {
+    #[inline]
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Running,
+            1 => Self::Draining,
+            2 => Self::Stopped,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// 服务错误类型。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ServiceError {
+    /// 服务正在关闭，不再接受新的会话。
+    ShuttingDown,
+    /// 多模型服务中没有指定名字的模型。
+    NoSuchModel,
+    /// 多模型服务中已存在指定名字的模型。
+    ModelExists,
+    /// 加载模型将使多模型服务占用的设备内存超出限制。
+    OutOfMemory,
+}
+
+impl error::Error for ServiceError {}
+impl fmt::Display for ServiceError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ShuttingDown => write!(f, "service is shutting down"),
+            Self::NoSuchModel => write!(f, "no such model"),
+            Self::ModelExists => write!(f, "model already exists"),
+            Self::OutOfMemory => write!(f, "device memory limit exceeded"),
+        }
+    }
+}
@@ -5,6 +5,18 @@ use std::borrow::Cow;
 pub trait Template {
     fn normalize<'a>(&self, prompt: &'a str) -> Cow<'a, str>;
     fn apply_chat<'a>(&self, prompt: &'a str) -> Cow<'a, str>;
+    fn apply_system<'a>(&self, system: &'a str) -> Cow<'a, str>;
+
+    /// 编码套用模板的文本时是否在开头加入句子开始符。
+    #[inline]
+    fn add_bos(&self) -> bool {
+        false
+    }
+    /// 编码套用模板的文本时是否在末尾加入句子结束符。
+    #[inline]
+    fn add_eos(&self) -> bool {
+        false
+    }
 }
 
 pub struct ChatCPM;
@@ -14,12 +26,22 @@ pub struct ChatTinyLlama;
 impl Template for ChatCPM {
     #[inline]
     fn normalize<'a>(&self, prompt: &'a str) -> Cow<'a, str> {
-        Cow::Owned(format!("<s>{}", prompt.trim()))
+        Cow::Borrowed(prompt.trim())
     }
 
     #[inline]
     fn apply_chat<'a>(&self, prompt: &'a str) -> Cow<'a, str> {
-        Cow::Owned(format!("<s><用户>{}<AI>", prompt.trim()))
+        Cow::Owned(format!("<用户>{}<AI>", prompt.trim()))
+    }
+
+    #[inline]
+    fn apply_system<'a>(&self, system: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(system.trim())
+    }
+
+    #[inline]
+    fn add_bos(&self) -> bool {
+        true
     }
 }
 
@@ -33,4 +55,9 @@ impl Template for ChatTinyLlama {
     fn apply_chat<'a>(&self, prompt: &'a str) -> Cow<'a, str> {
         Cow::Owned(format!("<|user|>\n{prompt}</s><|assistant|>\n"))
     }
+
+    #[inline]
+    fn apply_system<'a>(&self, system: &'a str) -> Cow<'a, str> {
+        Cow::Owned(format!("<|system|>\n{system}</s>\n"))
+    }
 }
@@ -0,0 +1,292 @@
+use crate::{matmul::matmul_f32, udim, Tensor};
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 计算缩放点积 `q k^T * scale`，`scale` 缺省为 `1 / sqrt(head_dim)`。
+    ///
+    /// `q` 的形状为 `[..., seq_q, head_dim]`，`k` 的形状为 `[..., seq_k, head_dim]`，
+    /// 结果的形状为 `[..., seq_q, seq_k]`，数据类型与 `q` 相同，以 f32 累加。
+    pub fn scaled_dot_product<U: Deref<Target = [u8]>>(
+        q: &Self,
+        k: &Tensor<U>,
+        scale: Option<f32>,
+    ) -> Tensor<Vec<u8>> {
+        let &[ref batch @ .., seq_q, dh] = &*q.shape else {
+            panic!("q must have at least 2 dimensions")
+        };
+        let &[ref batch_k @ .., seq_k, dh_k] = &*k.shape else {
+            panic!("k must have at least 2 dimensions")
+        };
+        assert_eq!(batch, batch_k);
+        assert_eq!(dh, dh_k);
+        assert_eq!(q.layout, k.layout);
+
+        let scale = scale.unwrap_or_else(|| (dh as f32).sqrt().recip());
+        let (seq_q, seq_k, dh) = (seq_q as usize, seq_k as usize, dh as usize);
+        // 交换 k 的最后两维得到 k^T
+        let n = k.shape.len();
+        let perm = (0..n - 2).chain([n - 1, n - 2]).collect::<Vec<_>>();
+        let k_t = k.as_ref().map_physical(|p| &**p).transpose(&perm);
+
+        let mut ans = matmul_f32(
+            &q.to_f32_vec(),
+            &k_t.to_f32_vec(),
+            [seq_q, dh, seq_k],
+            false,
+        );
+        ans.iter_mut().for_each(|x| *x *= scale);
+
+        let mut shape = batch.to_vec();
+        shape.extend([seq_q as udim, seq_k as udim]);
+        Tensor::from_f32(q.layout, &shape, &ans)
+    }
+
+    /// 分块计算注意力 `softmax(q k^T / sqrt(head_dim)) v`，不构造完整的注意力分数矩阵。
+    ///
+    /// `q` 的形状为 `[..., seq_q, head_dim]`，`k`、`v` 的形状为 `[..., seq_k, head_dim]`。
+    /// q 和 kv 按 `block_size` 分块，以在线 softmax 累加输出，额外内存为 `O(block_size^2)`。
+    /// `causal` 为真时，第 `i` 个查询只关注前 `seq_k - seq_q + i + 1` 个键。
+    pub fn flash_attention<U: Deref<Target = [u8]>, V: Deref<Target = [u8]>>(
+        q: &Self,
+        k: &Tensor<U>,
+        v: &Tensor<V>,
+        causal: bool,
+        block_size: usize,
+    ) -> Tensor<Vec<u8>> {
+        let &[ref batch @ .., seq_q, dh] = &*q.shape else {
+            panic!("q must have at least 2 dimensions")
+        };
+        let &[ref batch_k @ .., seq_k, dh_k] = &*k.shape else {
+            panic!("k must have at least 2 dimensions")
+        };
+        assert_eq!(batch, batch_k);
+        assert_eq!(dh, dh_k);
+        assert_eq!(k.shape, v.shape);
+        assert!(seq_k >= seq_q);
+        assert!(block_size > 0);
+
+        let scale = (dh as f32).sqrt().recip();
+        let (seq_q, seq_k, dh) = (seq_q as usize, seq_k as usize, dh as usize);
+        let q_ = q.to_f32_vec();
+        let k_ = k.to_f32_vec();
+        let v_ = v.to_f32_vec();
+
+        let mut ans = vec![0.; q_.len()];
+        let mut tile = vec![0.; block_size * block_size];
+        let batches = ans
+            .chunks_mut(seq_q * dh)
+            .zip(q_.chunks(seq_q * dh))
+            .zip(k_.chunks(seq_k * dh).zip(v_.chunks(seq_k * dh)));
+        for ((out, q), (k, v)) in batches {
+            for i0 in (0..seq_q).step_by(block_size) {
+                let rows = block_size.min(seq_q - i0);
+                // 每行的当前最大值与指数和
+                let mut max = vec![f32::NEG_INFINITY; rows];
+                let mut sum = vec![0.; rows];
+                let end = if causal {
+                    seq_k - seq_q + i0 + rows
+                } else {
+                    seq_k
+                };
+                for j0 in (0..end).step_by(block_size) {
+                    let cols = block_size.min(end - j0);
+                    // 计算分数块
+                    for r in 0..rows {
+                        let q = &q[(i0 + r) * dh..][..dh];
+                        let visible = if causal {
+                            (seq_k - seq_q + i0 + r + 1).saturating_sub(j0)
+                        } else {
+                            cols
+                        };
+                        for c in 0..cols {
+                            tile[r * block_size + c] = if c < visible {
+                                let k = &k[(j0 + c) * dh..][..dh];
+                                q.iter().zip(k).map(|(a, b)| a * b).sum::<f32>() * scale
+                            } else {
+                                f32::NEG_INFINITY
+                            };
+                        }
+                    }
+                    // 在线 softmax 更新
+                    for r in 0..rows {
+                        let scores = &mut tile[r * block_size..][..cols];
+                        let max_ = scores.iter().copied().fold(max[r], f32::max);
+                        if max_ == f32::NEG_INFINITY {
+                            continue;
+                        }
+                        let out = &mut out[(i0 + r) * dh..][..dh];
+                        let rescale = (max[r] - max_).exp();
+                        out.iter_mut().for_each(|x| *x *= rescale);
+                        sum[r] *= rescale;
+                        for (c, s) in scores.iter_mut().enumerate() {
+                            *s = (*s - max_).exp();
+                            sum[r] += *s;
+                            let v = &v[(j0 + c) * dh..][..dh];
+                            out.iter_mut().zip(v).for_each(|(x, v)| *x += *s * v);
+                        }
+                        max[r] = max_;
+                    }
+                }
+                for r in 0..rows {
+                    let out = &mut out[(i0 + r) * dh..][..dh];
+                    out.iter_mut().for_each(|x| *x /= sum[r]);
+                }
+            }
+        }
+        Tensor::from_f32(q.layout, &q.shape, &ans)
+    }
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::{F16, F32};
+
+    // [batch = 1, heads = 2, seq = 2, head_dim = 4]
+    let q = (0..16).map(|x| x as f32 / 8.).collect::<Vec<_>>();
+    let k = (0..16).map(|x| (16 - x) as f32 / 8.).collect::<Vec<_>>();
+    let reference = |scale: f32| {
+        let mut ans = vec![];
+        for h in 0..2 {
+            for i in 0..2 {
+                for j in 0..2 {
+                    let q = &q[(h * 2 + i) * 4..][..4];
+                    let k = &k[(h * 2 + j) * 4..][..4];
+                    ans.push(q.iter().zip(k).map(|(a, b)| a * b).sum::<f32>() * scale);
+                }
+            }
+        }
+        ans
+    };
+
+    let q_ = Tensor::from_slice(F32, &[1, 2, 2, 4], &q);
+    let k_ = Tensor::from_slice(F32, &[1, 2, 2, 4], &k);
+    let ans = Tensor::scaled_dot_product(&q_, &k_, None);
+    assert_eq!(ans.shape(), &[1, 2, 2, 2]);
+    for (a, b) in ans.to_vec::<f32>().into_iter().zip(reference(0.5)) {
+        assert!((a - b).abs() < 1e-6);
+    }
+
+    // k 以 [batch, heads, head_dim, seq] 存储，转置后不连续
+    let k_t = (0..16)
+        .map(|i| k[i / 8 * 8 + i % 2 * 4 + i % 8 / 2])
+        .collect::<Vec<_>>();
+    let k_t = Tensor::from_slice(F32, &[1, 2, 4, 2], &k_t).transpose(&[0, 1, 3, 2]);
+    let ans = Tensor::scaled_dot_product(&q_, &k_t, Some(1.));
+    for (a, b) in ans.to_vec::<f32>().into_iter().zip(reference(1.)) {
+        assert!((a - b).abs() < 1e-6);
+    }
+
+    let q_ = Tensor::from_f32(F16, &[1, 2, 2, 4], &q);
+    let k_ = Tensor::from_f32(F16, &[1, 2, 2, 4], &k);
+    let ans = Tensor::scaled_dot_product(&q_, &k_, None);
+    assert_eq!(ans.data_layout(), F16);
+    for (a, b) in ans.to_f32_vec().into_iter().zip(reference(0.5)) {
+        assert!((a - b).abs() < 1e-2);
+    }
+}
+
+#[test]
+fn test_flash_attention() {
+    use digit_layout::types::F32;
+
+    const SEQ: usize = 1024;
+    const DH: usize = 16;
+    // 线性同余生成伪随机数
+    let mut seed = 1u32;
+    let mut random = |n: usize| {
+        (0..n)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+            })
+            .collect::<Vec<_>>()
+    };
+    let (q, k, v) = (random(SEQ * DH), random(SEQ * DH), random(SEQ * DH));
+    let shape = [1, SEQ as udim, DH as udim];
+    let q = Tensor::from_slice(F32, &shape, &q);
+    let k = Tensor::from_slice(F32, &shape, &k);
+    let v = Tensor::from_slice(F32, &shape, &v);
+
+    for causal in [false, true] {
+        // 构造完整分数矩阵的参考实现
+        let scores = Tensor::scaled_dot_product(&q, &k, None).to_vec::<f32>();
+        let v_ = v.to_vec::<f32>();
+        let mut reference = vec![0f32; SEQ * DH];
+        for (i, row) in scores.chunks(SEQ).enumerate() {
+            let row = if causal { &row[..=i] } else { row };
+            let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let exp = row.iter().map(|s| (s - max).exp()).collect::<Vec<_>>();
+            let sum = exp.iter().sum::<f32>();
+            for (j, e) in exp.iter().enumerate() {
+                for d in 0..DH {
+                    reference[i * DH + d] += e / sum * v_[j * DH + d];
+                }
+            }
+        }
+
+        let ans = Tensor::flash_attention(&q, &k, &v, causal, 64);
+        assert_eq!(ans.shape(), &shape);
+        for (a, b) in ans.to_vec::<f32>().into_iter().zip(reference) {
+            assert!((a - b).abs() < 1e-5, "{a} != {b}");
+        }
+    }
+}
+
+#[test]
+fn test_flash_attention_memory() {
+    use digit_layout::types::F32;
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    thread_local! {
+        static CURRENT: Cell<usize> = const { Cell::new(0) };
+        static PEAK: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// 统计每个线程当前占用和峰值占用的堆内存。
+    struct Counter;
+
+    #[global_allocator]
+    static ALLOCATOR: Counter = Counter;
+
+    unsafe impl GlobalAlloc for Counter {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = CURRENT.try_with(|c| {
+                c.set(c.get() + layout.size());
+                let _ = PEAK.try_with(|p| p.set(p.get().max(c.get())));
+            });
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let _ = CURRENT.try_with(|c| c.set(c.get().saturating_sub(layout.size())));
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    /// 执行 `f`，返回执行期间当前线程的堆内存峰值相比开始时的增量。
+    fn peak(f: impl FnOnce()) -> usize {
+        let base = CURRENT.with(Cell::get);
+        PEAK.with(|p| p.set(base));
+        f();
+        PEAK.with(Cell::get) - base
+    }
+
+    const SEQ: usize = 1024;
+    const DH: usize = 16;
+    let shape = [1, SEQ as udim, DH as udim];
+    let data = (0..SEQ * DH).map(|x| x as f32 / 1e4).collect::<Vec<_>>();
+    let q = Tensor::from_slice(F32, &shape, &data);
+    let k = Tensor::from_slice(F32, &shape, &data);
+    let v = Tensor::from_slice(F32, &shape, &data);
+
+    // 完整的注意力分数矩阵
+    let scores = SEQ * SEQ * size_of::<f32>();
+    assert!(peak(|| drop(Tensor::scaled_dot_product(&q, &k, None))) >= scores);
+    for causal in [false, true] {
+        let peak = peak(|| drop(Tensor::flash_attention(&q, &k, &v, causal, 64)));
+        assert!(peak < scores / 4, "{peak} bytes");
+    }
+}
@@ -1,4 +1,4 @@
-﻿use crate::{pattern::Pattern, udim, Affine, Shape, Tensor};
+﻿use crate::{pattern::Pattern, udim, Affine, BroadcastError, Shape, Tensor};
 use std::iter::zip;
 
 impl<Physical> Tensor<Physical> {
@@ -9,6 +9,25 @@ impl<Physical> Tensor<Physical> {
             ..self
         }
     }
+
+    /// 按 NumPy 规则将张量广播到 `shape`，广播的维度步长为 0，不复制数据。
+    pub fn broadcast_to(self, shape: &[udim]) -> Result<Self, BroadcastError> {
+        let (src, dst) = (self.shape.len(), shape.len());
+        if src > dst {
+            return Err(BroadcastError::RankMismatch { src, dst });
+        }
+        let prefix = dst - src;
+        for (axis, (&i, &o)) in zip(&*self.shape, &shape[prefix..]).enumerate() {
+            if i != 1 && i != o {
+                return Err(BroadcastError::IncompatibleDim {
+                    axis: prefix + axis,
+                    src: i,
+                    dst: o,
+                });
+            }
+        }
+        Ok(self.broadcast(shape))
+    }
 }
 
 fn build(dst: &[udim], src: &[udim]) -> Affine {
@@ -44,3 +63,32 @@ fn test() {
         ]
     );
 }
+
+#[test]
+fn test_broadcast_to() {
+    use digit_layout::types::F32;
+
+    let data = [1f32, 2., 3.];
+    let t = Tensor::from_slice(F32, &[1, 3], &data);
+    let t = t.as_ref().map_physical(|b| &**b);
+    let ptr = t.physical().as_ptr();
+
+    let ans = t.clone().broadcast_to(&[2, 4, 3]).unwrap();
+    assert_eq!(ans.physical().as_ptr(), ptr);
+    assert_eq!(ans.shape(), &[2, 4, 3]);
+    assert_eq!(ans.strides(), &[0, 0, 1]);
+    assert_eq!(ans.to_vec::<f32>(), data.repeat(8));
+
+    assert_eq!(
+        t.clone().broadcast_to(&[3]).unwrap_err(),
+        BroadcastError::RankMismatch { src: 2, dst: 1 }
+    );
+    assert_eq!(
+        t.broadcast_to(&[2, 2]).unwrap_err(),
+        BroadcastError::IncompatibleDim {
+            axis: 1,
+            src: 3,
+            dst: 2
+        }
+    );
+}
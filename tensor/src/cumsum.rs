@@ -0,0 +1,79 @@
+use crate::{udim, Tensor};
+use digit_layout::types::{BF16, F16, F32, F64, I16, I32, I64, I8, U16, U32, U64, U8};
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 沿 `axis` 计算包含当前元素的前缀和，结果形状和数据类型不变。
+    ///
+    /// 浮点类型在 f32 中累加，整数类型以自身类型累加。
+    pub fn cumsum(&self, axis: usize) -> Tensor<Vec<u8>> {
+        assert!(axis < self.shape.len());
+        let product = |s: &[udim]| s.iter().map(|&d| d as usize).product::<usize>();
+        let lanes = [
+            product(&self.shape[..axis]),
+            self.shape[axis] as usize,
+            product(&self.shape[axis + 1..]),
+        ];
+
+        macro_rules! int {
+            ($ty:ty) => {{
+                let mut data = self.to_vec::<$ty>();
+                scan(&mut data, lanes, |a, b| a + b);
+                Tensor::from_slice(self.layout, &self.shape, &data)
+            }};
+        }
+        match self.layout {
+            F16 | BF16 | F32 | F64 => {
+                let mut data = self.to_f32_vec();
+                scan(&mut data, lanes, |a, b| a + b);
+                Tensor::from_f32(self.layout, &self.shape, &data)
+            }
+            I8 => int!(i8),
+            I16 => int!(i16),
+            I32 => int!(i32),
+            I64 => int!(i64),
+            U8 => int!(u8),
+            U16 => int!(u16),
+            U32 => int!(u32),
+            U64 => int!(u64),
+            layout => panic!("unsupported dtype {layout}"),
+        }
+    }
+}
+
+/// 对形状为 `[outer, n, inner]` 的连续数据沿中间维原地扫描。
+fn scan<T: Copy>(data: &mut [T], [outer, n, inner]: [usize; 3], add: impl Fn(T, T) -> T) {
+    for o in 0..outer {
+        let block = &mut data[o * n * inner..][..n * inner];
+        for k in 1..n {
+            let (prev, cur) = block.split_at_mut(k * inner);
+            let prev = &prev[(k - 1) * inner..];
+            cur[..inner]
+                .iter_mut()
+                .zip(prev)
+                .for_each(|(y, &x)| *y = add(x, *y));
+        }
+    }
+}
+
+#[test]
+fn test() {
+    use half::f16;
+
+    let t = Tensor::from_slice(F32, &[4], &[1f32, 2., 3., 4.]);
+    assert_eq!(t.cumsum(0).to_vec::<f32>(), [1., 3., 6., 10.]);
+
+    let t = Tensor::from_slice(F16, &[4], &[1., 2., 3., 4.].map(f16::from_f32));
+    let ans = t.cumsum(0).to_vec::<f16>();
+    assert_eq!(ans, [1., 3., 6., 10.].map(f16::from_f32));
+
+    // 2 维张量沿行和列分别累加
+    let t = Tensor::from_slice(I32, &[2, 3], &[1i32, 2, 3, 4, 5, 6]);
+    assert_eq!(t.cumsum(0).shape(), &[2, 3]);
+    assert_eq!(t.cumsum(0).to_vec::<i32>(), [1, 2, 3, 5, 7, 9]);
+    assert_eq!(t.cumsum(1).to_vec::<i32>(), [1, 3, 6, 4, 9, 15]);
+
+    // 非连续张量
+    let t = Tensor::from_slice(U32, &[2, 3], &[1u32, 2, 3, 4, 5, 6]).transpose(&[1, 0]);
+    assert_eq!(t.cumsum(1).to_vec::<u32>(), [1, 5, 2, 7, 3, 9]);
+}
@@ -0,0 +1,42 @@
+use crate::udim;
+use std::{error::Error, fmt};
+
+/// 张量形状错误。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ShapeError {
+    /// 没有输入张量。
+    Empty,
+    /// 输入张量的数据类型不一致。
+    DataTypeMismatch,
+    /// 输入张量的形状不一致。
+    NonUniformShape,
+    /// 指定的维度超出范围。
+    AxisOutOfRange,
+    /// 指定维度的长度不能被均分。
+    IndivisibleSize,
+}
+
+impl Error for ShapeError {}
+impl fmt::Display for ShapeError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// 张量广播错误。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum BroadcastError {
+    /// 目标形状的维数少于张量的维数。
+    RankMismatch { src: usize, dst: usize },
+    /// 张量在 `axis` 维的长度既不是 1 也不等于目标长度。
+    IncompatibleDim { axis: usize, src: udim, dst: udim },
+}
+
+impl Error for BroadcastError {}
+impl fmt::Display for BroadcastError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
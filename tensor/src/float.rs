@@ -0,0 +1,42 @@
+use crate::{udim, Tensor};
+use digit_layout::{
+    types::{BF16, F16, F32, F64},
+    DigitLayout,
+};
+use half::{bf16, f16};
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 将浮点张量按行主序转换为 `Vec<f32>`。
+    pub(crate) fn to_f32_vec(&self) -> Vec<f32> {
+        match self.layout {
+            F16 => self.to_vec::<f16>().into_iter().map(f16::to_f32).collect(),
+            BF16 => self
+                .to_vec::<bf16>()
+                .into_iter()
+                .map(bf16::to_f32)
+                .collect(),
+            F32 => self.to_vec(),
+            F64 => self.to_vec::<f64>().into_iter().map(|x| x as _).collect(),
+            layout => panic!("unsupported dtype {layout}"),
+        }
+    }
+}
+
+impl Tensor<Vec<u8>> {
+    /// 从按行主序排列的 `[f32]` 构造指定浮点类型的连续张量。
+    pub(crate) fn from_f32(data_type: DigitLayout, shape: &[udim], data: &[f32]) -> Self {
+        match data_type {
+            F16 => Self::from_slice(F16, shape, &cast(data, f16::from_f32)),
+            BF16 => Self::from_slice(BF16, shape, &cast(data, bf16::from_f32)),
+            F32 => Self::from_slice(F32, shape, data),
+            F64 => Self::from_slice(F64, shape, &cast(data, |x| x as f64)),
+            layout => panic!("unsupported dtype {layout}"),
+        }
+    }
+}
+
+#[inline]
+fn cast<T>(data: &[f32], f: impl Fn(f32) -> T) -> Vec<T> {
+    data.iter().copied().map(f).collect()
+}
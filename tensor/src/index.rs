@@ -0,0 +1,84 @@
+use crate::{idim, pattern::Pattern, udim, ShapeError, Tensor};
+use nalgebra::DVector;
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 沿 `axis` 按 `indices` 的顺序选取切片，组成一个连续张量，`axis` 维的长度变为 `indices.len()`。
+    ///
+    /// 索引可以重复、乱序，对 2 维张量的 0 维选取即词表查询。
+    pub fn index_select(
+        &self,
+        axis: usize,
+        indices: &[usize],
+    ) -> Result<Tensor<Vec<u8>>, ShapeError> {
+        if axis >= self.shape.len() {
+            return Err(ShapeError::AxisOutOfRange);
+        }
+        let len = self.shape[axis] as usize;
+
+        let mut shape = self.shape.clone();
+        shape[axis] = indices.len() as udim;
+        let mut ans = Tensor::alloc(self.layout, &shape, |len| vec![0u8; len]);
+
+        let mut src_strides = self.pattern.strides().to_vec();
+        let src_step = src_strides.remove(axis);
+        let mut dst_strides = Pattern::from_shape(&shape, 0).strides().to_vec();
+        let dst_step = dst_strides.remove(axis);
+        let mut slice_shape = self.shape.clone();
+        slice_shape.remove(axis);
+        for (j, &i) in indices.iter().enumerate() {
+            assert!(i < len, "index {i} out of range {len}");
+            let pattern = |mut strides: Vec<idim>, offset| {
+                strides.push(offset);
+                Pattern(DVector::from_vec(strides))
+            };
+            let src = Tensor {
+                layout: self.layout,
+                shape: slice_shape.clone(),
+                pattern: pattern(
+                    src_strides.clone(),
+                    self.pattern.offset() + i as idim * src_step,
+                ),
+                physical: &*self.physical,
+            };
+            let mut dst = Tensor {
+                layout: ans.layout,
+                shape: slice_shape.clone(),
+                pattern: pattern(dst_strides.clone(), j as idim * dst_step),
+                physical: &mut *ans.physical,
+            };
+            src.reform_to(&mut dst);
+        }
+        Ok(ans)
+    }
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::U32;
+
+    let t = Tensor::from_slice(U32, &[3, 4], &(0..12u32).collect::<Vec<_>>());
+
+    let ans = t.index_select(0, &[2, 0, 1]).unwrap();
+    assert_eq!(ans.shape(), &[3, 4]);
+    assert_eq!(ans.to_vec::<u32>(), [8, 9, 10, 11, 0, 1, 2, 3, 4, 5, 6, 7]);
+
+    let ans = t.index_select(1, &[3, 3, 0]).unwrap();
+    assert_eq!(ans.shape(), &[3, 3]);
+    assert_eq!(ans.to_vec::<u32>(), [3, 3, 0, 7, 7, 4, 11, 11, 8]);
+
+    // 非连续的输入
+    let ans = t
+        .as_ref()
+        .map_physical(|b| &**b)
+        .transpose(&[1, 0])
+        .index_select(0, &[1])
+        .unwrap();
+    assert_eq!(ans.shape(), &[1, 3]);
+    assert_eq!(ans.to_vec::<u32>(), [1, 5, 9]);
+
+    assert_eq!(
+        t.index_select(2, &[0]).unwrap_err(),
+        ShapeError::AxisOutOfRange
+    );
+}
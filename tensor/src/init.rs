@@ -0,0 +1,94 @@
+use crate::{udim, Tensor};
+use digit_layout::{
+    types::{BF16, BOOL, F16, F32, F64, I16, I32, I64, I8, U16, U32, U64, U8},
+    DigitLayout,
+};
+use half::{bf16, f16};
+
+impl Tensor<Vec<u8>> {
+    /// 构造全 0 的连续张量。
+    #[inline]
+    pub fn zeros(data_type: DigitLayout, shape: &[udim]) -> Self {
+        Self::alloc(data_type, shape, |len| vec![0u8; len])
+    }
+
+    /// 构造全 1 的连续张量。
+    #[inline]
+    pub fn ones(data_type: DigitLayout, shape: &[udim]) -> Self {
+        Self::full(data_type, shape, 1.)
+    }
+
+    /// 构造所有元素都为 `value` 的连续张量，`value` 按数据类型转换。
+    pub fn full(data_type: DigitLayout, shape: &[udim], value: f64) -> Self {
+        let value = encode(data_type, value);
+        let mut ans = Self::zeros(data_type, shape);
+        for x in ans.physical_mut().chunks_exact_mut(value.len()) {
+            x.copy_from_slice(&value);
+        }
+        ans
+    }
+
+    /// 构造 `n x n` 的单位矩阵。
+    #[inline]
+    pub fn eye(data_type: DigitLayout, n: udim) -> Self {
+        Self::diag(data_type, &vec![1.; n as usize])
+    }
+
+    /// 构造以 `v` 为对角线的方阵，`v` 按数据类型转换。
+    pub fn diag(data_type: DigitLayout, v: &[f32]) -> Self {
+        let n = v.len();
+        let mut ans = Self::zeros(data_type, &[n as _, n as _]);
+        let size = data_type.nbytes();
+        let data = ans.physical_mut();
+        for (i, &x) in v.iter().enumerate() {
+            data[(i * n + i) * size..][..size].copy_from_slice(&encode(data_type, x as _));
+        }
+        ans
+    }
+}
+
+/// 将 `value` 编码为一个 `data_type` 类型的元素。
+fn encode(data_type: DigitLayout, value: f64) -> Vec<u8> {
+    match data_type {
+        F16 => f16::from_f64(value).to_le_bytes().to_vec(),
+        BF16 => bf16::from_f64(value).to_le_bytes().to_vec(),
+        F32 => (value as f32).to_le_bytes().to_vec(),
+        F64 => value.to_le_bytes().to_vec(),
+        I8 => (value as i8).to_le_bytes().to_vec(),
+        I16 => (value as i16).to_le_bytes().to_vec(),
+        I32 => (value as i32).to_le_bytes().to_vec(),
+        I64 => (value as i64).to_le_bytes().to_vec(),
+        U8 => (value as u8).to_le_bytes().to_vec(),
+        U16 => (value as u16).to_le_bytes().to_vec(),
+        U32 => (value as u32).to_le_bytes().to_vec(),
+        U64 => (value as u64).to_le_bytes().to_vec(),
+        BOOL => vec![(value != 0.) as u8],
+        _ => panic!("unsupported dtype {data_type}"),
+    }
+}
+
+#[test]
+fn test() {
+    let t = Tensor::zeros(F32, &[2, 3]);
+    assert_eq!(t.shape(), &[2, 3]);
+    assert_eq!(t.to_vec::<f32>(), [0.; 6]);
+
+    assert_eq!(Tensor::ones(F16, &[3]).to_vec::<f16>(), [f16::ONE; 3]);
+    assert_eq!(Tensor::ones(BF16, &[2]).to_vec::<bf16>(), [bf16::ONE; 2]);
+    assert_eq!(Tensor::ones(I64, &[2]).to_vec::<i64>(), [1; 2]);
+    assert_eq!(Tensor::full(U8, &[2], 7.).to_vec::<u8>(), [7; 2]);
+    assert_eq!(Tensor::ones(BOOL, &[2]).to_vec::<u8>(), [1; 2]);
+
+    let eye = Tensor::eye(F32, 3);
+    assert_eq!(eye.shape(), &[3, 3]);
+    assert_eq!(eye.to_vec::<f32>(), [1., 0., 0., 0., 1., 0., 0., 0., 1.]);
+    assert_eq!(Tensor::eye(I32, 2).to_vec::<i32>(), [1, 0, 0, 1]);
+
+    let diag = Tensor::diag(F16, &[1., 2.]);
+    assert_eq!(diag.to_vec::<f16>(), [1., 0., 0., 2.].map(f16::from_f32));
+
+    // I x A = A
+    let a = Tensor::from_slice(F32, &[3, 2], &[1f32, 2., 3., 4., 5., 6.]);
+    let ia = Tensor::eye(F32, 3).matmul_batched(&a);
+    assert_eq!(ia.to_vec::<f32>(), a.to_vec::<f32>());
+}
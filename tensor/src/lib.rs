@@ -1,11 +1,25 @@
+mod attention;
 mod broadcast;
 mod compatibility;
+mod cumsum;
+mod error;
+mod float;
 mod fmt;
+mod index;
+mod init;
+mod mask;
+mod matmul;
+mod pad;
 mod pattern;
+mod pool;
 mod reshape;
+mod select;
 mod slice;
+mod softmax;
 mod split;
+mod stack;
 mod tensor;
+mod topk;
 mod transpose;
 
 #[allow(non_camel_case_types)]
@@ -15,11 +29,12 @@ pub type udim = u32;
 pub type idim = i32;
 
 pub use compatibility::Compatibility;
+pub use error::{BroadcastError, ShapeError};
 pub use nalgebra::DVector;
 pub use pattern::{expand_indices, idx_strides, Affine, Shape};
 pub use slice::SliceDim;
 pub use split::{LocalSplitable, Splitable};
-pub use tensor::Tensor;
+pub use tensor::{Pod, Tensor};
 
 use std::mem::{align_of, size_of, size_of_val};
 
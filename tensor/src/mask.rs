@@ -0,0 +1,83 @@
+use crate::{BroadcastError, Tensor};
+use digit_layout::types::{BF16, F16, F32, F64};
+use half::{bf16, f16};
+use std::{iter::zip, ops::Deref};
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 将 `mask` 广播到张量的形状，以 `fill` 替换掩码非 0 位置的元素，生成新的连续张量。
+    ///
+    /// `mask` 的元素为单字节。`fill` 将转换为张量的数据类型，f16 没有足够的表示范围，
+    /// 超出的值截断到 `±65504`，因此可以用 `f32::NEG_INFINITY` 表示有效的 `-inf`。
+    pub fn masked_fill<U: Deref<Target = [u8]>>(
+        &self,
+        mask: &Tensor<U>,
+        fill: f32,
+    ) -> Result<Tensor<Vec<u8>>, BroadcastError> {
+        let mask = mask
+            .as_ref()
+            .map_physical(|b| &**b)
+            .broadcast_to(&self.shape)?
+            .to_vec::<u8>();
+
+        let fill = match self.layout {
+            F16 => f16::from_f32(fill.clamp(f16::MIN.to_f32(), f16::MAX.to_f32()))
+                .to_ne_bytes()
+                .to_vec(),
+            BF16 => bf16::from_f32(fill).to_ne_bytes().to_vec(),
+            F32 => fill.to_ne_bytes().to_vec(),
+            F64 => (fill as f64).to_ne_bytes().to_vec(),
+            layout => panic!("unsupported dtype {layout}"),
+        };
+        let mut ans = Tensor::alloc(self.layout, &self.shape, |len| vec![0u8; len]);
+        self.reform_to(&mut ans);
+        for (x, m) in zip(ans.as_mut_slice().chunks_exact_mut(fill.len()), mask) {
+            if m != 0 {
+                x.copy_from_slice(&fill);
+            }
+        }
+        Ok(ans)
+    }
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::U8;
+
+    let data = (0..16).map(|x| x as f32).collect::<Vec<_>>();
+    let t = Tensor::from_slice(F32, &[4, 4], &data);
+    let causal = (0..4)
+        .flat_map(|i| (0..4).map(move |j| (j > i) as u8))
+        .collect::<Vec<_>>();
+    let mask = Tensor::from_slice(U8, &[4, 4], &causal);
+
+    let ans = t.masked_fill(&mask, f32::NEG_INFINITY).unwrap();
+    assert_eq!(ans.shape(), &[4, 4]);
+    for (i, row) in ans.to_vec::<f32>().chunks(4).enumerate() {
+        for (j, &x) in row.iter().enumerate() {
+            if j > i {
+                assert_eq!(x, f32::NEG_INFINITY);
+            } else {
+                assert_eq!(x, data[i * 4 + j]);
+            }
+        }
+    }
+
+    // 掩码按列广播到每一行
+    let t = Tensor::from_slice(F16, &[2, 3], &[f16::ONE; 6]);
+    let mask = Tensor::from_slice(U8, &[3], &[0u8, 1, 0]);
+    let ans = t.masked_fill(&mask, f32::NEG_INFINITY).unwrap();
+    assert_eq!(
+        ans.to_vec::<f16>(),
+        [1., -65504., 1., 1., -65504., 1.].map(f16::from_f32)
+    );
+
+    let mask = Tensor::from_slice(U8, &[2], &[0u8, 1]);
+    assert_eq!(
+        t.masked_fill(&mask, 0.).unwrap_err(),
+        BroadcastError::IncompatibleDim {
+            axis: 1,
+            src: 2,
+            dst: 3
+        }
+    );
+}
@@ -0,0 +1,100 @@
+use crate::{udim, Tensor};
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 计算批量矩阵乘 `self x rhs`，以 f32 累加。
+    ///
+    /// `self` 的形状为 `[..., m, k]`，`rhs` 的形状为 `[..., k, n]` 或 `[k, n]`，
+    /// 后者表示所有批次共享同一个右矩阵。结果的形状为 `[..., m, n]`，数据类型与 `self` 相同。
+    pub fn matmul_batched<U: Deref<Target = [u8]>>(&self, rhs: &Tensor<U>) -> Tensor<Vec<u8>> {
+        let &[ref batch @ .., m, k] = &*self.shape else {
+            panic!("lhs must have at least 2 dimensions")
+        };
+        let &[ref batch_rhs @ .., k_rhs, n] = &*rhs.shape else {
+            panic!("rhs must have at least 2 dimensions")
+        };
+        assert!(batch_rhs.is_empty() || batch_rhs == batch);
+        assert_eq!(k, k_rhs);
+        assert_eq!(self.layout, rhs.layout);
+
+        let (m, k, n) = (m as usize, k as usize, n as usize);
+        let ans = matmul_f32(
+            &self.to_f32_vec(),
+            &rhs.to_f32_vec(),
+            [m, k, n],
+            batch_rhs.is_empty(),
+        );
+
+        let mut shape = batch.to_vec();
+        shape.extend([m as udim, n as udim]);
+        Tensor::from_f32(self.layout, &shape, &ans)
+    }
+}
+
+/// 对行主序的 f32 数据计算批量矩阵乘，`a` 的每个批次为 `m x k`，`b` 的每个批次为 `k x n`。
+///
+/// `shared_rhs` 为真时 `b` 只有一个批次，由 `a` 的所有批次共享。
+pub(crate) fn matmul_f32(
+    a: &[f32],
+    b: &[f32],
+    [m, k, n]: [usize; 3],
+    shared_rhs: bool,
+) -> Vec<f32> {
+    let mut ans = vec![0.; a.len() / k * n];
+    for (i, (a, c)) in a.chunks(m * k).zip(ans.chunks_mut(m * n)).enumerate() {
+        let b = if shared_rhs {
+            b
+        } else {
+            &b[i * k * n..][..k * n]
+        };
+        // i-k-j 顺序，内层循环连续访问 b 和 c 的行
+        for (a, c) in a.chunks(k).zip(c.chunks_mut(n)) {
+            for (&a, b) in a.iter().zip(b.chunks(n)) {
+                c.iter_mut().zip(b).for_each(|(c, &b)| *c += a * b);
+            }
+        }
+    }
+    ans
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::F32;
+
+    let (batch, m, k, n) = (4, 8, 16, 12);
+    let a = (0..batch * m * k)
+        .map(|x| (x % 7) as f32 - 3.)
+        .collect::<Vec<_>>();
+    let b = (0..batch * k * n)
+        .map(|x| (x % 5) as f32 - 2.)
+        .collect::<Vec<_>>();
+    let shape = |s: &[usize]| s.iter().map(|&d| d as udim).collect::<Vec<_>>();
+
+    let ans = Tensor::from_slice(F32, &shape(&[batch, m, k]), &a)
+        .matmul_batched(&Tensor::from_slice(F32, &shape(&[batch, k, n]), &b));
+    assert_eq!(ans.shape(), &shape(&[batch, m, n])[..]);
+    let ans = ans.to_vec::<f32>();
+
+    for i in 0..batch {
+        let a = Tensor::from_slice(F32, &shape(&[m, k]), &a[i * m * k..][..m * k]);
+        let b = Tensor::from_slice(F32, &shape(&[k, n]), &b[i * k * n..][..k * n]);
+        let c = a.matmul_batched(&b);
+        assert_eq!(c.to_vec::<f32>(), &ans[i * m * n..][..m * n]);
+        // 逐元素验证
+        let (a, b) = (a.to_vec::<f32>(), b.to_vec::<f32>());
+        let c = c.to_vec::<f32>();
+        for r in 0..m {
+            for j in 0..n {
+                let expected = (0..k).map(|x| a[r * k + x] * b[x * n + j]).sum::<f32>();
+                assert_eq!(c[r * n + j], expected);
+            }
+        }
+    }
+
+    // 共享右矩阵
+    let a = Tensor::from_slice(F32, &[2, 1, 2], &[1f32, 2., 3., 4.]);
+    let b = Tensor::from_slice(F32, &[2, 1], &[1f32, 1.]);
+    let c = a.matmul_batched(&b);
+    assert_eq!(c.shape(), &[2, 1, 1]);
+    assert_eq!(c.to_vec::<f32>(), [3., 7.]);
+}
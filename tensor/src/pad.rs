@@ -0,0 +1,67 @@
+use crate::{idim, pattern::Pattern, udim, Tensor};
+use digit_layout::types::{BF16, F16, F32, F64};
+use half::{bf16, f16};
+use nalgebra::DVector;
+use std::{iter::zip, ops::Deref};
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 在每个维度的前后分别填充 `(before, after)` 个元素，填充值 `fill` 将转换为张量的数据类型。
+    pub fn pad(&self, paddings: &[(udim, udim)], fill: f32) -> Tensor<Vec<u8>> {
+        assert_eq!(paddings.len(), self.shape.len());
+
+        let fill = match self.layout {
+            F16 => f16::from_f32(fill).to_ne_bytes().to_vec(),
+            BF16 => bf16::from_f32(fill).to_ne_bytes().to_vec(),
+            F32 => fill.to_ne_bytes().to_vec(),
+            F64 => (fill as f64).to_ne_bytes().to_vec(),
+            layout => panic!("unsupported dtype {layout}"),
+        };
+        let shape = self
+            .shape
+            .iter()
+            .zip(paddings)
+            .map(|(&d, &(before, after))| before + d + after)
+            .collect::<Vec<_>>();
+        let mut ans = Tensor::alloc(self.layout, &shape, |len| -> Vec<u8> {
+            fill.iter().copied().cycle().take(len).collect()
+        });
+
+        let pattern = Pattern::from_shape(&shape, 0);
+        let strides = pattern.strides();
+        let offset = zip(strides, paddings)
+            .map(|(&s, &(before, _))| s * before as idim)
+            .sum();
+        let mut pattern = strides.to_vec();
+        pattern.push(offset);
+        let mut dst = Tensor {
+            layout: ans.layout,
+            shape: self.shape.clone(),
+            pattern: Pattern(DVector::from_vec(pattern)),
+            physical: &mut *ans.physical,
+        };
+        self.reform_to(&mut dst);
+        ans
+    }
+}
+
+#[test]
+fn test() {
+    let data = (1..=6).map(|x| x as f32).collect::<Vec<_>>();
+    let t = Tensor::from_slice(F32, &[2, 3], &data);
+
+    let ans = t.pad(&[(1, 0), (0, 2)], 0.);
+    assert_eq!(ans.shape(), &[3, 5]);
+    #[rustfmt::skip]
+    assert_eq!(
+        ans.to_vec::<f32>(),
+        [
+            0., 0., 0., 0., 0.,
+            1., 2., 3., 0., 0.,
+            4., 5., 6., 0., 0.,
+        ]
+    );
+
+    let t = Tensor::from_slice(F16, &[2], &[f16::ONE, f16::ONE]);
+    let ans = t.pad(&[(1, 1)], -1.5);
+    assert_eq!(ans.to_vec::<f16>(), [-1.5, 1., 1., -1.5].map(f16::from_f32));
+}
@@ -0,0 +1,178 @@
+use crate::{udim, Tensor};
+use digit_layout::types::F32;
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 沿 `axis` 求平均，结果去掉 `axis` 维，以 f32 累加。
+    #[inline]
+    pub fn mean_pool(&self, axis: usize) -> Tensor<Vec<u8>> {
+        self.mean_pool_impl(axis, None)
+    }
+
+    /// 沿 `axis` 只对 `mask` 非 0 的位置求平均，结果去掉 `axis` 维，以 f32 累加。
+    ///
+    /// `mask` 的元素为单字节，形状为张量形状的前 `axis + 1` 维，例如对 `[batch, seq, d]` 的张量在 1 维池化，
+    /// 掩码为 `[batch, seq]`。没有有效位置的结果为 0。
+    #[inline]
+    pub fn mean_pool_masked<U: Deref<Target = [u8]>>(
+        &self,
+        axis: usize,
+        mask: &Tensor<U>,
+    ) -> Tensor<Vec<u8>> {
+        self.mean_pool_impl(axis, Some(self.pool_mask(axis, mask)))
+    }
+
+    /// 取 `axis` 维的最后一个位置，结果去掉 `axis` 维。
+    #[inline]
+    pub fn last_token_pool(&self, axis: usize) -> Tensor<Vec<u8>> {
+        self.last_token_pool_impl(axis, None)
+    }
+
+    /// 取 `axis` 维上 `mask` 非 0 的最后一个位置，结果去掉 `axis` 维。
+    ///
+    /// `mask` 的要求与 [`Tensor::mean_pool_masked`] 相同。没有有效位置的结果为 0。
+    #[inline]
+    pub fn last_token_pool_masked<U: Deref<Target = [u8]>>(
+        &self,
+        axis: usize,
+        mask: &Tensor<U>,
+    ) -> Tensor<Vec<u8>> {
+        self.last_token_pool_impl(axis, Some(self.pool_mask(axis, mask)))
+    }
+
+    /// 沿最后一维做 L2 归一化，结果总是 f32 类型。零向量归一化的结果仍为零向量。
+    ///
+    /// 池化得到的嵌入向量归一化后，余弦相似度等于点积。
+    pub fn l2_normalize(&self) -> Tensor<Vec<u8>> {
+        let &[.., d] = &*self.shape else {
+            panic!("l2_normalize requires at least one dimension")
+        };
+        let mut data = self.to_f32_vec();
+        for row in data.chunks_mut(d as usize) {
+            let norm = row.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm > 0. {
+                row.iter_mut().for_each(|x| *x /= norm);
+            }
+        }
+        Tensor::from_f32(F32, &self.shape, &data)
+    }
+
+    fn mean_pool_impl(&self, axis: usize, mask: Option<Vec<u8>>) -> Tensor<Vec<u8>> {
+        let (outer, n, inner, shape) = self.pool_shape(axis);
+        let data = self.to_f32_vec();
+        let mut ans = vec![0.; outer * inner];
+        for o in 0..outer {
+            let ans = &mut ans[o * inner..][..inner];
+            let mut count = 0;
+            for k in 0..n {
+                if mask.as_ref().is_some_and(|m| m[o * n + k] == 0) {
+                    continue;
+                }
+                count += 1;
+                let row = &data[(o * n + k) * inner..][..inner];
+                ans.iter_mut().zip(row).for_each(|(y, x)| *y += x);
+            }
+            if count > 0 {
+                ans.iter_mut().for_each(|y| *y /= count as f32);
+            }
+        }
+        Tensor::from_f32(self.layout, &shape, &ans)
+    }
+
+    fn last_token_pool_impl(&self, axis: usize, mask: Option<Vec<u8>>) -> Tensor<Vec<u8>> {
+        let (outer, n, inner, shape) = self.pool_shape(axis);
+        let mut src = Tensor::alloc(self.layout, &self.shape, |len| vec![0u8; len]);
+        self.reform_to(&mut src);
+        let src = src.as_slice();
+
+        let row = inner * self.layout.nbytes();
+        let mut ans = Tensor::alloc(self.layout, &shape, |len| vec![0u8; len]);
+        let dst = ans.as_mut_slice();
+        for o in 0..outer {
+            let last = match &mask {
+                Some(mask) => mask[o * n..][..n].iter().rposition(|&m| m != 0),
+                None => n.checked_sub(1),
+            };
+            if let Some(k) = last {
+                dst[o * row..][..row].copy_from_slice(&src[(o * n + k) * row..][..row]);
+            }
+        }
+        ans
+    }
+
+    /// 返回 `axis` 之前各维的总长、`axis` 维长度、之后各维的总长和去掉 `axis` 的形状。
+    fn pool_shape(&self, axis: usize) -> (usize, usize, usize, Vec<udim>) {
+        assert!(axis < self.shape.len());
+        let product = |s: &[udim]| s.iter().map(|&d| d as usize).product::<usize>();
+        let mut shape = self.shape.to_vec();
+        let n = shape.remove(axis) as usize;
+        (
+            product(&self.shape[..axis]),
+            n,
+            product(&self.shape[axis + 1..]),
+            shape,
+        )
+    }
+
+    /// 检查掩码形状并展开为按行主序排列的字节。
+    fn pool_mask<U: Deref<Target = [u8]>>(&self, axis: usize, mask: &Tensor<U>) -> Vec<u8> {
+        assert_eq!(&*mask.shape, &self.shape[..=axis]);
+        mask.to_vec()
+    }
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::{F16, U8};
+    use half::f16;
+
+    // [batch = 2, seq = 3, d = 2]
+    let data = (0..12).map(|x| x as f32).collect::<Vec<_>>();
+    let t = Tensor::from_slice(F32, &[2, 3, 2], &data);
+
+    let ans = t.mean_pool(1);
+    assert_eq!(ans.shape(), &[2, 2]);
+    assert_eq!(ans.to_vec::<f32>(), [2., 3., 8., 9.]);
+
+    let ans = t.last_token_pool(1);
+    assert_eq!(ans.shape(), &[2, 2]);
+    assert_eq!(ans.to_vec::<f32>(), [4., 5., 10., 11.]);
+
+    // 第 1 个序列长度为 2，第 2 个序列长度为 1
+    let mask = Tensor::from_slice(U8, &[2, 3], &[1u8, 1, 0, 1, 0, 0]);
+    let ans = t.mean_pool_masked(1, &mask);
+    assert_eq!(ans.to_vec::<f32>(), [1., 2., 6., 7.]);
+    let ans = t.last_token_pool_masked(1, &mask);
+    assert_eq!(ans.to_vec::<f32>(), [2., 3., 6., 7.]);
+
+    // f16 以 f32 累加
+    let t = Tensor::from_slice(F16, &[4], &[60000., 60000., 0., 0.].map(f16::from_f32));
+    let ans = t.mean_pool(0);
+    assert_eq!(ans.shape(), &[] as &[udim]);
+    assert_eq!(ans.to_vec::<f16>(), [f16::from_f32(30000.)]);
+}
+
+#[test]
+fn test_l2_normalize() {
+    use digit_layout::types::F16;
+    use half::f16;
+
+    let data = [3., 4., 0., 0., 1., 2., 2., 0., 0., 0., 0., 0.];
+    let t = Tensor::from_slice(F16, &[3, 4], &data.map(f16::from_f32));
+    let ans = t.l2_normalize();
+    assert_eq!(ans.data_layout(), F32);
+    assert_eq!(ans.shape(), &[3, 4]);
+
+    let ans = ans.to_vec::<f32>();
+    let rows = ans.chunks(4).collect::<Vec<_>>();
+    let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    for row in &rows[..2] {
+        assert!((dot(row, row).sqrt() - 1.).abs() < 1e-6);
+    }
+    // 零向量
+    assert_eq!(rows[2], [0.; 4]);
+    // 归一化后点积等于余弦相似度
+    let cos = |a: &[f32], b: &[f32]| dot(a, b) / (dot(a, a) * dot(b, b)).sqrt();
+    let (a, b) = (&data[..4], &data[4..8]);
+    assert!((dot(rows[0], rows[1]) - cos(a, b)).abs() < 1e-6);
+}
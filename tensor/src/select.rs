@@ -0,0 +1,113 @@
+use crate::{udim, ShapeError, Tensor};
+use digit_layout::types::BOOL;
+use std::{iter::zip, ops::Deref};
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 按 `condition` 逐元素选择 `on_true` 或 `on_false` 中的值，生成新的连续张量。
+    ///
+    /// 三个张量按 NumPy 规则广播到共同的形状。`condition` 的数据类型必须是 [`BOOL`]，
+    /// `on_true` 和 `on_false` 的数据类型必须相同，也是结果的数据类型。
+    pub fn where_cond<U: Deref<Target = [u8]>, V: Deref<Target = [u8]>>(
+        condition: &Self,
+        on_true: &Tensor<U>,
+        on_false: &Tensor<V>,
+    ) -> Result<Tensor<Vec<u8>>, ShapeError> {
+        if condition.layout != BOOL || on_true.layout != on_false.layout {
+            return Err(ShapeError::DataTypeMismatch);
+        }
+        let shape = broadcast_shape(&[&*condition.shape, &*on_true.shape, &*on_false.shape])
+            .ok_or(ShapeError::NonUniformShape)?;
+
+        let condition = contiguous(condition, &shape).take_physical();
+        let on_true = contiguous(on_true, &shape).take_physical();
+        let mut ans = contiguous(on_false, &shape);
+        let dt = ans.layout.nbytes();
+        let selected = zip(
+            ans.as_mut_slice().chunks_exact_mut(dt),
+            on_true.chunks_exact(dt),
+        );
+        for ((y, x), c) in zip(selected, condition) {
+            if c != 0 {
+                y.copy_from_slice(x);
+            }
+        }
+        Ok(ans)
+    }
+}
+
+/// 按 NumPy 规则计算多个形状广播后的共同形状。
+fn broadcast_shape(shapes: &[&[udim]]) -> Option<Vec<udim>> {
+    let rank = shapes.iter().map(|s| s.len()).max().unwrap_or(0);
+    (0..rank)
+        .map(|i| {
+            shapes
+                .iter()
+                .filter_map(|s| (i + s.len()).checked_sub(rank).map(|j| s[j]))
+                .try_fold(1, |acc, d| match (acc, d) {
+                    (acc, 1) => Some(acc),
+                    (1, d) => Some(d),
+                    (acc, d) if acc == d => Some(acc),
+                    _ => None,
+                })
+        })
+        .collect()
+}
+
+/// 将张量广播到 `shape` 并复制为连续张量。
+fn contiguous<T: Deref<Target = [u8]>>(t: &Tensor<T>, shape: &[udim]) -> Tensor<Vec<u8>> {
+    let mut ans = Tensor::alloc(t.layout, shape, |len| vec![0u8; len]);
+    t.as_ref()
+        .map_physical(|b| &**b)
+        .broadcast(shape)
+        .reform_to(&mut ans);
+    ans
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::{F16, F32};
+    use half::f16;
+
+    // 1 维条件
+    let cond = Tensor::from_slice(BOOL, &[4], &[1u8, 0, 0, 1]);
+    let x = Tensor::from_slice(F32, &[4], &[1f32, 2., 3., 4.]);
+    let y = Tensor::from_slice(F32, &[4], &[-1f32, -2., -3., -4.]);
+    let ans = Tensor::where_cond(&cond, &x, &y).unwrap();
+    assert_eq!(ans.shape(), &[4]);
+    assert_eq!(ans.to_vec::<f32>(), [1., -2., -3., 4.]);
+
+    // 2 维条件按行广播，on_false 为标量
+    let cond = Tensor::from_slice(BOOL, &[1, 3], &[1u8, 0, 1]);
+    let x = Tensor::from_slice(F32, &[2, 3], &[1f32, 2., 3., 4., 5., 6.]);
+    let y = Tensor::from_slice(F32, &[1], &[f32::NEG_INFINITY]);
+    let ans = Tensor::where_cond(&cond, &x, &y).unwrap();
+    assert_eq!(ans.shape(), &[2, 3]);
+    let inf = f32::NEG_INFINITY;
+    assert_eq!(ans.to_vec::<f32>(), [1., inf, 3., 4., inf, 6.]);
+
+    // 条件全假
+    let cond = Tensor::from_slice(BOOL, &[2, 2], &[0u8; 4]);
+    let x = Tensor::from_slice(F16, &[2, 2], &[f16::ONE; 4]);
+    let y = Tensor::from_slice(F16, &[2], &[f16::ZERO, f16::NEG_ONE]);
+    let ans = Tensor::where_cond(&cond, &x, &y).unwrap();
+    assert_eq!(
+        ans.to_vec::<f16>(),
+        [f16::ZERO, f16::NEG_ONE, f16::ZERO, f16::NEG_ONE]
+    );
+
+    // 错误
+    let x = Tensor::from_slice(F32, &[2, 2], &[0f32; 4]);
+    assert_eq!(
+        Tensor::where_cond(&cond, &x, &y).unwrap_err(),
+        ShapeError::DataTypeMismatch
+    );
+    assert_eq!(
+        Tensor::where_cond(&x, &x, &x).unwrap_err(),
+        ShapeError::DataTypeMismatch
+    );
+    let y = Tensor::from_slice(F32, &[3], &[0f32; 3]);
+    assert_eq!(
+        Tensor::where_cond(&cond, &x, &y).unwrap_err(),
+        ShapeError::NonUniformShape
+    );
+}
@@ -1,5 +1,9 @@
-﻿use crate::{idim, pattern::Pattern, udim, Affine, Shape, Tensor};
-use std::{cmp::Ordering, iter::zip};
+﻿use crate::{idim, pattern::Pattern, udim, Affine, Shape, ShapeError, Tensor};
+use std::{
+    cmp::Ordering,
+    iter::zip,
+    ops::{Deref, DerefMut},
+};
 
 impl<Physical> Tensor<Physical> {
     pub fn slice(self, dims: &[SliceDim]) -> Self {
@@ -12,6 +16,25 @@ impl<Physical> Tensor<Physical> {
     }
 }
 
+impl<Physical: DerefMut<Target = [u8]>> Tensor<Physical> {
+    /// 将 `src` 写入张量的切片 `dims` 处，切片外的数据保持不变。
+    pub fn slice_assign<U: Deref<Target = [u8]>>(
+        &mut self,
+        dims: &[SliceDim],
+        src: &Tensor<U>,
+    ) -> Result<(), ShapeError> {
+        if self.layout != src.layout {
+            return Err(ShapeError::DataTypeMismatch);
+        }
+        let mut dst = self.as_mut().map_physical(|p| &mut **p).slice(dims);
+        if dst.shape != src.shape {
+            return Err(ShapeError::NonUniformShape);
+        }
+        src.reform_to(&mut dst);
+        Ok(())
+    }
+}
+
 fn build(meta: &[SliceDim], input: &[udim]) -> (Shape, Affine) {
     assert_eq!(input.len(), meta.len());
     let meta = zip(meta, input)
@@ -156,3 +179,28 @@ fn test_macro() {
     assert_eq!(slice![3 =>=> 5], slice![3; 1; 5]);
     assert_eq!(slice![3 => 2 => 5], slice![3; 2; 5]);
 }
+
+#[test]
+fn test_slice_assign() {
+    use digit_layout::types::U32;
+
+    let mut t = Tensor::from_slice(U32, &[4, 4], &(0..16u32).collect::<Vec<_>>());
+    let src = Tensor::from_slice(U32, &[2, 2], &[100u32, 101, 102, 103]);
+    t.slice_assign(&[slice![1 => 3], slice![1 => 3]], &src)
+        .unwrap();
+    #[rustfmt::skip]
+    assert_eq!(
+        t.to_vec::<u32>(),
+        [
+             0,   1,   2,  3,
+             4, 100, 101,  7,
+             8, 102, 103, 11,
+            12,  13,  14, 15,
+        ]
+    );
+
+    assert_eq!(
+        t.slice_assign(&[slice![=>3], slice![1 => 3]], &src),
+        Err(ShapeError::NonUniformShape)
+    );
+}
@@ -0,0 +1,113 @@
+use crate::{udim, Tensor};
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 沿 `axis` 计算 softmax，结果形状和数据类型不变。
+    ///
+    /// 以 `exp(x - max(x))` 计算以保证数值稳定，f16 等低精度类型在 f32 中计算。
+    #[inline]
+    pub fn softmax(&self, axis: usize) -> Tensor<Vec<u8>> {
+        self.map_lanes(axis, softmax)
+    }
+
+    /// 沿 `axis` 计算 log-softmax，结果形状和数据类型不变。
+    ///
+    /// 以 `x - max(x) - ln(sum(exp(x - max(x))))` 计算，避免先求 softmax 下溢为 0，在 f32 中计算。
+    #[inline]
+    pub fn log_softmax(&self, axis: usize) -> Tensor<Vec<u8>> {
+        self.map_lanes(axis, log_softmax)
+    }
+
+    /// 将 `axis` 上的每条数据收集为连续的 f32 序列，用 `f` 原地变换。
+    fn map_lanes(&self, axis: usize, f: fn(&mut [f32])) -> Tensor<Vec<u8>> {
+        assert!(axis < self.shape.len());
+        let product = |s: &[udim]| s.iter().map(|&d| d as usize).product::<usize>();
+        let outer = product(&self.shape[..axis]);
+        let n = self.shape[axis] as usize;
+        let inner = product(&self.shape[axis + 1..]);
+
+        let mut data = self.to_f32_vec();
+        let mut row = vec![0.; n];
+        for o in 0..outer {
+            let block = &mut data[o * n * inner..][..n * inner];
+            for i in 0..inner {
+                // 收集到连续的缓冲区中再计算
+                row.iter_mut()
+                    .zip(block[i..].iter().step_by(inner))
+                    .for_each(|(y, &x)| *y = x);
+                f(&mut row);
+                block[i..]
+                    .iter_mut()
+                    .step_by(inner)
+                    .zip(&row)
+                    .for_each(|(y, &x)| *y = x);
+            }
+        }
+        Tensor::from_f32(self.layout, &self.shape, &data)
+    }
+}
+
+fn softmax(x: &mut [f32]) {
+    let max = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    x.iter_mut().for_each(|x| *x = (*x - max).exp());
+    let sum = x.iter().sum::<f32>();
+    x.iter_mut().for_each(|x| *x /= sum);
+}
+
+fn log_softmax(x: &mut [f32]) {
+    let max = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let sum = x.iter().map(|x| (x - max).exp()).sum::<f32>();
+    let sub = max + sum.ln();
+    x.iter_mut().for_each(|x| *x -= sub);
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::{F16, F32};
+    use half::f16;
+
+    let t = Tensor::from_slice(F32, &[4], &[1f32, 2., 3., 4.]);
+    let ans = t.softmax(0).to_vec::<f32>();
+    assert!((ans.iter().sum::<f32>() - 1.).abs() < 1e-6);
+    assert!(ans.windows(2).all(|w| w[0] < w[1]));
+
+    // 数值稳定
+    let t = Tensor::from_slice(F32, &[3], &[f32::MAX, 0., -f32::MAX]);
+    assert_eq!(t.softmax(0).to_vec::<f32>(), [1., 0., 0.]);
+
+    // 2 维张量在第 1 维上计算，每行和为 1
+    let t = Tensor::from_slice(F16, &[2, 3], &[0., 0., 0., 1., 1., 1.].map(f16::from_f32));
+    let ans = t.softmax(1);
+    assert_eq!(ans.shape(), &[2, 3]);
+    for x in ans.to_vec::<f16>() {
+        assert!((x.to_f32() - 1. / 3.).abs() < 1e-3);
+    }
+    // 在第 0 维上计算，每列和为 1
+    let t = Tensor::from_slice(F32, &[2, 3], &[0f32, 1., 2., 0., 2., 4.]);
+    let ans = t.softmax(0).to_vec::<f32>();
+    assert_eq!(ans[0], 0.5);
+    for j in 0..3 {
+        assert!((ans[j] + ans[3 + j] - 1.).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_log_softmax() {
+    use digit_layout::types::F32;
+
+    let data = [0.5f32, -1., 3., 2., 0., -4., 1.5, 2.5];
+    let t = Tensor::from_slice(F32, &[2, 4], &data);
+    let ans = t.log_softmax(1).to_vec::<f32>();
+    let softmax = t.softmax(1).to_vec::<f32>();
+    for row in ans.chunks(4) {
+        assert!((row.iter().map(|x| x.exp()).sum::<f32>() - 1.).abs() < 1e-6);
+    }
+    for (l, s) in ans.iter().zip(&softmax) {
+        assert!((l - s.ln()).abs() < 1e-6);
+    }
+
+    // softmax 下溢为 0 时 log-softmax 仍然有限
+    let t = Tensor::from_slice(F32, &[2], &[0f32, 200.]);
+    let ans = t.log_softmax(0).to_vec::<f32>();
+    assert_eq!(ans, [-200., 0.]);
+}
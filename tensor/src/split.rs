@@ -1,4 +1,4 @@
-﻿use crate::{idim, pattern::Pattern, udim, Affine, Shape, Tensor};
+﻿use crate::{idim, pattern::Pattern, udim, Affine, Shape, ShapeError, Tensor};
 use std::{
     collections::VecDeque,
     ops::{Deref, DerefMut},
@@ -66,6 +66,44 @@ impl<Physical: Splitable> Tensor<Physical> {
             })
             .collect()
     }
+
+    /// 在 `axis` 维的 `at` 处将张量分为两个共享存储的视图。
+    pub fn split_at(&self, axis: usize, at: udim) -> (Self, Self) {
+        let len = self.shape[axis];
+        assert!(at <= len);
+        let mut vec = self.split(axis, &[at, len - at]);
+        let first = vec.pop_front().unwrap();
+        let second = vec.pop_front().unwrap();
+        (first, second)
+    }
+
+    /// 沿 `axis` 将张量分为至多 `num_chunks` 个共享存储的视图。
+    ///
+    /// 每块的长度为 `ceil(len / num_chunks)`，不能整除时最后一块较短，块数也可能少于 `num_chunks`。
+    pub fn chunk(&self, num_chunks: usize, axis: usize) -> Vec<Self> {
+        assert!(num_chunks > 0);
+        let len = self.shape[axis];
+        let size = len.div_ceil(num_chunks as udim);
+        let segments = if size == 0 {
+            vec![0]
+        } else {
+            (0..len.div_ceil(size))
+                .map(|i| size.min(len - i * size))
+                .collect()
+        };
+        self.split(axis, &segments).into()
+    }
+
+    /// 沿 `axis` 将张量均分为 `num_chunks` 个共享存储的视图，不能均分时返回错误。
+    pub fn chunk_exact(&self, num_chunks: usize, axis: usize) -> Result<Vec<Self>, ShapeError> {
+        assert!(num_chunks > 0);
+        let &len = self.shape.get(axis).ok_or(ShapeError::AxisOutOfRange)?;
+        if len % num_chunks as udim != 0 {
+            return Err(ShapeError::IndivisibleSize);
+        }
+        let size = len / num_chunks as udim;
+        Ok(self.split(axis, &vec![size; num_chunks]).into())
+    }
 }
 
 fn build(axis: usize, segments: &[udim], input: &[udim]) -> Vec<(Shape, Affine)> {
@@ -154,3 +192,55 @@ fn test_macro() {
     use digit_layout::types::U8;
     let (_a, _b, _c) = split!(Tensor::new(U8, &[10], ()); [0]: 2, 3, 4);
 }
+
+#[test]
+fn test_split_at() {
+    use digit_layout::types::U32;
+
+    let t = Tensor::from_slice(U32, &[6, 4], &(0..24u32).collect::<Vec<_>>());
+    let (a, b) = t.as_ref().map_physical(|p| &**p).split_at(0, 4);
+    assert_eq!(a.shape(), &[4, 4]);
+    assert_eq!(b.shape(), &[2, 4]);
+    assert_eq!(a.physical().as_ptr(), t.physical().as_ptr());
+    assert_eq!(b.physical().as_ptr(), t.physical().as_ptr());
+    assert_eq!(a.to_vec::<u32>(), (0..16).collect::<Vec<_>>());
+    assert_eq!(b.to_vec::<u32>(), (16..24).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_chunk() {
+    use crate::LocalSplitable;
+    use digit_layout::types::U32;
+
+    let t = Tensor::from_slice(U32, &[9, 2], &(0..18u32).collect::<Vec<_>>())
+        .map_physical(LocalSplitable::from);
+    let chunks = t.chunk(3, 0);
+    assert_eq!(chunks.len(), 3);
+    for (i, c) in chunks.iter().enumerate() {
+        assert_eq!(c.shape(), &[3, 2]);
+        let start = i as u32 * 6;
+        assert_eq!(c.to_vec::<u32>(), (start..start + 6).collect::<Vec<_>>());
+    }
+    // 视图与原张量共享存储
+    let mut t = t;
+    t.physical_mut()[6 * 4..][..4].copy_from_slice(&100u32.to_ne_bytes());
+    assert_eq!(chunks[1].to_vec::<u32>()[0], 100);
+
+    // 不能整除时最后一块较短
+    fn shapes<T>(chunks: Vec<Tensor<T>>) -> Vec<udim> {
+        chunks.iter().map(|c| c.shape()[1]).collect()
+    }
+    assert_eq!(shapes(t.chunk(4, 1)), [1, 1]);
+    let t = Tensor::new(U32, &[2, 7], ());
+    assert_eq!(shapes(t.chunk(3, 1)), [3, 3, 1]);
+    assert_eq!(shapes(t.chunk(4, 1)), [2, 2, 2, 1]);
+    assert_eq!(shapes(t.chunk_exact(7, 1).unwrap()), [1; 7]);
+    assert!(matches!(
+        t.chunk_exact(3, 1),
+        Err(ShapeError::IndivisibleSize)
+    ));
+    assert!(matches!(
+        t.chunk_exact(3, 2),
+        Err(ShapeError::AxisOutOfRange)
+    ));
+}
@@ -0,0 +1,70 @@
+use crate::{idim, pattern::Pattern, udim, ShapeError, Tensor};
+use nalgebra::DVector;
+use std::ops::Deref;
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 在 `axis` 处插入一个新维度，将形状相同的张量堆叠成一个连续张量。
+    pub fn stack(tensors: &[Self], axis: usize) -> Result<Tensor<Vec<u8>>, ShapeError> {
+        let Some((first, others)) = tensors.split_first() else {
+            return Err(ShapeError::Empty);
+        };
+        if others.iter().any(|t| t.layout != first.layout) {
+            return Err(ShapeError::DataTypeMismatch);
+        }
+        if others.iter().any(|t| t.shape != first.shape) {
+            return Err(ShapeError::NonUniformShape);
+        }
+        if axis > first.shape.len() {
+            return Err(ShapeError::AxisOutOfRange);
+        }
+
+        let mut shape = first.shape.clone();
+        shape.insert(axis, tensors.len() as udim);
+        let mut ans = Tensor::alloc(first.layout, &shape, |len| vec![0u8; len]);
+
+        let pattern = Pattern::from_shape(&shape, 0);
+        let mut strides = pattern.strides().to_vec();
+        let step = strides.remove(axis);
+        for (i, t) in tensors.iter().enumerate() {
+            let mut pattern = strides.clone();
+            pattern.push(i as idim * step);
+            let mut dst = Tensor {
+                layout: ans.layout,
+                shape: t.shape.clone(),
+                pattern: Pattern(DVector::from_vec(pattern)),
+                physical: &mut *ans.physical,
+            };
+            t.reform_to(&mut dst);
+        }
+        Ok(ans)
+    }
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::U32;
+
+    let a = (0..6u32).flat_map(u32::to_ne_bytes).collect::<Vec<_>>();
+    let b = (6..12u32).flat_map(u32::to_ne_bytes).collect::<Vec<_>>();
+    let a = Tensor::new(U32, &[2, 3], &*a);
+    let b = Tensor::new(U32, &[2, 3], &*b);
+    let data = |t: &Tensor<Vec<u8>>| crate::reslice::<u8, u32>(t.as_slice()).to_vec();
+
+    let t = Tensor::stack(&[a.clone(), b.clone()], 0).unwrap();
+    assert_eq!(t.shape(), &[2, 2, 3]);
+    assert_eq!(data(&t), (0..12).collect::<Vec<_>>());
+
+    let t = Tensor::stack(&[a.clone(), b.clone()], 1).unwrap();
+    assert_eq!(t.shape(), &[2, 2, 3]);
+    assert_eq!(data(&t), [0, 1, 2, 6, 7, 8, 3, 4, 5, 9, 10, 11]);
+
+    let t = Tensor::stack(&[a.clone(), b.clone()], 2).unwrap();
+    assert_eq!(t.shape(), &[2, 3, 2]);
+    assert_eq!(data(&t), [0, 6, 1, 7, 2, 8, 3, 9, 4, 10, 5, 11]);
+
+    let c = Tensor::new(U32, &[3, 2], a.physical);
+    assert_eq!(
+        Tensor::stack(&[a, c], 0).unwrap_err(),
+        ShapeError::NonUniformShape
+    );
+}
@@ -3,7 +3,8 @@ use digit_layout::DigitLayout;
 use nalgebra::DVector;
 use rayon::iter::*;
 use std::{
-    mem::{align_of, size_of},
+    iter::zip,
+    mem::{align_of, size_of, size_of_val},
     ops::{Deref, DerefMut},
     panic,
 };
@@ -109,6 +110,35 @@ impl<Physical> Tensor<Physical> {
         self.contiguous_len() == self.shape.len()
     }
 
+    /// 张量是否按列主序（Fortran 序）连续存储，即第一维步长为 1，之后每一维的步长为前一维的跨度。
+    pub fn is_column_major(&self) -> bool {
+        zip(self.pattern.strides(), &*self.shape)
+            .try_fold(1 as idim, |mul, (&s, &d)| {
+                (s == mul || d == 1).then_some(mul * d as idim)
+            })
+            .is_some()
+    }
+
+    /// 矩阵在 BLAS 意义上的主维度（元素数），用于 `lda` 等参数。
+    ///
+    /// 取最后两维作为矩阵：行内连续时为行的步长，列内连续时为列的步长。
+    /// 两者都不连续时无法直接传递给 BLAS，返回 `None`。
+    pub fn leading_dim(&self) -> Option<usize> {
+        let &[.., rs, cs] = self.pattern.strides() else {
+            panic!("leading_dim requires at least 2 dimensions")
+        };
+        let &[.., rows, cols] = &*self.shape else {
+            unreachable!()
+        };
+        if cs == 1 || cols == 1 {
+            Some(rs as usize)
+        } else if rs == 1 || rows == 1 {
+            Some(cs as usize)
+        } else {
+            None
+        }
+    }
+
     /// 连续维度的数量。
     pub fn contiguous_len(&self) -> usize {
         self.pattern
@@ -117,7 +147,7 @@ impl<Physical> Tensor<Physical> {
             .enumerate()
             .rev()
             .scan(1 as idim, |mul, (i, &s)| {
-                if s == *mul || s == 0 {
+                if s == *mul || self.shape[i] == 1 {
                     *mul *= self.shape[i] as idim;
                     Some(())
                 } else {
@@ -224,6 +254,21 @@ impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
         }
     }
 
+    /// 将张量数据按行主序复制到一个 `Vec<T>`，`T` 的大小必须与数据类型相同。
+    pub fn to_vec<T: Pod>(&self) -> Vec<T> {
+        assert_eq!(size_of::<T>(), self.layout.nbytes());
+        let mut bytes = vec![0u8; self.bytes_size()];
+        unsafe { self.reform_to_raw(&mut bytes) };
+
+        let len = self.size();
+        let mut ans = Vec::<T>::with_capacity(len);
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), ans.as_mut_ptr().cast(), bytes.len());
+            ans.set_len(len);
+        }
+        ans
+    }
+
     pub fn reform_to<U>(&self, dst: &mut Tensor<U>)
     where
         U: DerefMut<Target = [u8]>,
@@ -252,6 +297,17 @@ impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
     }
 }
 
+impl Tensor<Vec<u8>> {
+    /// 从一个按行主序排列的 `[T]` 构造连续张量，`T` 的大小必须与数据类型相同。
+    pub fn from_slice<T: Pod>(data_type: DigitLayout, shape: &[udim], data: &[T]) -> Self {
+        assert_eq!(size_of::<T>(), data_type.nbytes());
+        assert_eq!(shape.iter().product::<udim>() as usize, data.len());
+        let len = size_of_val(data);
+        let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr().cast::<u8>(), len) };
+        Self::new(data_type, shape, bytes.to_vec())
+    }
+}
+
 impl<Physical: DerefMut<Target = [u8]>> Tensor<Physical> {
     #[inline]
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
@@ -262,6 +318,21 @@ impl<Physical: DerefMut<Target = [u8]>> Tensor<Physical> {
     }
 }
 
+/// 可以与字节直接互相转换的元素类型。
+///
+/// # Safety
+///
+/// 实现的类型不能有填充字节，且任意字节序列都是合法的值。`bool`、枚举等类型不能实现。
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! pod {
+    ($($ty:ty)+) => {
+        $(unsafe impl Pod for $ty {})+
+    };
+}
+
+pod!(u8 u16 u32 u64 usize i8 i16 i32 i64 isize f32 f64 half::f16 half::bf16);
+
 #[test]
 fn test() {
     use digit_layout::types::F32;
@@ -296,3 +367,74 @@ fn test() {
     assert_eq!(t.contiguous_len(), 4);
     assert_eq!(t.is_contiguous(), false);
 }
+
+#[test]
+fn test_to_vec() {
+    use digit_layout::types::F32;
+
+    let data = (0..24).map(|i| i as f32 * 0.5).collect::<Vec<_>>();
+    let t = Tensor::from_slice(F32, &[2, 3, 4], &data);
+    assert_eq!(t.shape(), &[2, 3, 4]);
+    assert_eq!(t.to_vec::<f32>(), data);
+
+    let t = t.transpose(&[1, 0, 2]);
+    let expected = (0..3)
+        .flat_map(|i| {
+            (0..2).flat_map(move |j| (0..4).map(move |k| (j * 12 + i * 4 + k) as f32 * 0.5))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(t.to_vec::<f32>(), expected);
+}
+
+#[test]
+#[should_panic]
+fn test_to_vec_size_mismatch() {
+    use digit_layout::types::F32;
+
+    Tensor::from_slice(F32, &[2], &[1f32, 2.]).to_vec::<u16>();
+}
+
+#[test]
+fn test_major() {
+    use digit_layout::types::F32;
+
+    let t = Tensor::new(F32, &[3, 4], ());
+    assert!(!t.is_column_major());
+    assert_eq!(t.leading_dim(), Some(4));
+
+    let t = t.transpose(&[1, 0]);
+    assert_eq!(t.strides(), &[1, 4]);
+    assert!(t.is_column_major());
+    assert_eq!(t.leading_dim(), Some(4));
+
+    let t = Tensor::new(F32, &[2, 3, 4], ()).transpose(&[0, 2, 1]);
+    assert!(!t.is_column_major());
+    assert_eq!(t.leading_dim(), Some(4));
+
+    let t = Tensor::new(F32, &[2, 3, 4], ()).transpose(&[1, 0, 2]);
+    assert_eq!(t.strides(), &[4, 12, 1]);
+    assert_eq!(t.leading_dim(), Some(12));
+    let t = Tensor::new(F32, &[2, 3, 4], ()).transpose(&[2, 0, 1]);
+    assert_eq!(t.strides(), &[1, 12, 4]);
+    assert_eq!(t.leading_dim(), None);
+}
+
+#[test]
+fn test_contiguous_len() {
+    use digit_layout::types::F32;
+
+    // 广播的维度步长为 0，且长度大于 1，不连续
+    let t = Tensor::new(F32, &[1, 4], ()).broadcast(&[3, 4]);
+    assert_eq!(t.strides(), &[0, 1]);
+    assert_eq!(t.contiguous_len(), 1);
+    assert!(!t.is_contiguous());
+    let t = Tensor::new(F32, &[3, 1], ()).broadcast(&[3, 4]);
+    assert_eq!(t.strides(), &[1, 0]);
+    assert_eq!(t.contiguous_len(), 0);
+
+    // 长度为 1 的维度不论步长都不影响连续性
+    let t = Tensor::new(F32, &[2, 3, 1], ()).transpose(&[2, 0, 1]);
+    assert_eq!(t.strides(), &[1, 3, 1]);
+    assert_eq!(t.contiguous_len(), 3);
+    assert!(t.is_contiguous());
+}
@@ -0,0 +1,119 @@
+use crate::{udim, Tensor};
+use digit_layout::types::I64;
+use std::{cmp::Ordering, ops::Deref};
+
+impl<Physical: Deref<Target = [u8]>> Tensor<Physical> {
+    /// 沿 `axis` 选出最大（`largest`）或最小的 `k` 个元素，返回 `(索引, 值)`。
+    ///
+    /// 索引的数据类型为 I64，值的数据类型与张量相同，两者的形状都是在 `axis` 维长度为 `k` 的张量形状。
+    /// `sorted` 为真时，结果按从大到小（`largest`）或从小到大排列，否则顺序不确定。
+    pub fn topk(
+        &self,
+        k: usize,
+        axis: usize,
+        largest: bool,
+        sorted: bool,
+    ) -> (Tensor<Vec<u8>>, Tensor<Vec<u8>>) {
+        let rank = self.shape.len();
+        assert!(axis < rank);
+        let n = self.shape[axis] as usize;
+        assert!(k <= n);
+
+        // 将 axis 维换到最后并连续化
+        let perm = (0..rank)
+            .filter(|&i| i != axis)
+            .chain([axis])
+            .collect::<Vec<_>>();
+        let src = self.as_ref().map_physical(|b| &**b).transpose(&perm);
+        let mut lanes = Tensor::alloc(self.layout, &src.shape, |len| vec![0u8; len]);
+        src.reform_to(&mut lanes);
+
+        let dt = self.layout.nbytes();
+        let values = lanes.to_f32_vec();
+        let bytes = lanes.as_slice();
+        let cmp = |a: &(usize, f32), b: &(usize, f32)| -> Ordering {
+            let ord = a.1.total_cmp(&b.1);
+            if largest {
+                ord.reverse()
+            } else {
+                ord
+            }
+        };
+
+        let num_lanes = values.len().checked_div(n).unwrap_or(0);
+        let mut indices = Vec::with_capacity(num_lanes * k);
+        let mut selected = Vec::with_capacity(num_lanes * k * dt);
+        let mut lane = Vec::with_capacity(n);
+        for (l, values) in values.chunks(n.max(1)).enumerate().take(num_lanes) {
+            lane.clear();
+            lane.extend(values.iter().copied().enumerate());
+            if k < n && k > 0 {
+                lane.select_nth_unstable_by(k - 1, cmp);
+            }
+            let lane = &mut lane[..k];
+            if sorted {
+                lane.sort_unstable_by(cmp);
+            }
+            for &(i, _) in &*lane {
+                indices.push(i as i64);
+                selected.extend_from_slice(&bytes[(l * n + i) * dt..][..dt]);
+            }
+        }
+
+        // 将 axis 维换回原位
+        let mut shape = src.shape.clone();
+        shape[rank - 1] = k as udim;
+        let mut inv = vec![0; rank];
+        for (r, &i) in perm.iter().enumerate() {
+            inv[i] = r;
+        }
+        let restore = |layout, data: Vec<u8>| {
+            let t = Tensor::new(layout, &shape, data).transpose(&inv);
+            let mut ans = Tensor::alloc(layout, &t.shape, |len| vec![0u8; len]);
+            t.reform_to(&mut ans);
+            ans
+        };
+        let indices = Tensor::from_slice(I64, &shape, &indices).take_physical();
+        (restore(I64, indices), restore(self.layout, selected))
+    }
+}
+
+#[test]
+fn test() {
+    use digit_layout::types::{F16, F32};
+    use half::f16;
+
+    let logits = [0.1f32, 2.5, -1., 3.7, 0.9, 3.7, -4.2];
+    let t = Tensor::from_slice(F32, &[7], &logits);
+
+    let (indices, values) = t.topk(3, 0, true, true);
+    assert_eq!(indices.shape(), &[3]);
+    let indices = indices.to_vec::<i64>();
+    assert!(indices == [3, 5, 1] || indices == [5, 3, 1]);
+    assert_eq!(values.to_vec::<f32>(), [3.7, 3.7, 2.5]);
+
+    let (indices, values) = t.topk(2, 0, false, true);
+    assert_eq!(indices.to_vec::<i64>(), [6, 2]);
+    assert_eq!(values.to_vec::<f32>(), [-4.2, -1.]);
+
+    let (indices, _) = t.topk(3, 0, true, false);
+    let mut indices = indices.to_vec::<i64>();
+    indices.sort_unstable();
+    assert_eq!(indices, [1, 3, 5]);
+
+    // 沿非最后一维选择，保持数据类型
+    let data = [1., 6., 2., 5., 3., 4.].map(f16::from_f32);
+    let t = Tensor::from_slice(F16, &[3, 2], &data);
+    let (indices, values) = t.topk(1, 0, true, true);
+    assert_eq!(indices.shape(), &[1, 2]);
+    assert_eq!(indices.to_vec::<i64>(), [2, 0]);
+    assert_eq!(values.to_vec::<f16>(), [3., 6.].map(f16::from_f32));
+
+    let (indices, values) = t.topk(2, 1, true, true);
+    assert_eq!(indices.shape(), &[3, 2]);
+    assert_eq!(indices.to_vec::<i64>(), [1, 0, 1, 0, 1, 0]);
+    assert_eq!(
+        values.to_vec::<f16>(),
+        [6., 1., 5., 2., 4., 3.].map(f16::from_f32)
+    );
+}
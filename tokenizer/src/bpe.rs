@@ -1,6 +1,6 @@
-﻿use crate::{ByteDecoder, Tokenizer};
+﻿use crate::{ByteDecoder, Detokenizer, Tokenizer};
 use common::utok;
-use std::{io::Result, path::Path};
+use std::{io::Result, ops::Range, path::Path};
 
 /// 由 tokenizer.model 文件定义的 bpe 分词器。
 ///
@@ -21,6 +21,11 @@ impl BPE {
         // 打开文件
         let file = std::fs::File::open(model_file)?;
         let mmap = unsafe { memmap2::Mmap::map(&file) }?;
+        Ok(Self::from_mmap(mmap))
+    }
+
+    /// 从 tokenizer.model 格式的内容构造一个 bpe 分词器。
+    pub(crate) fn from_mmap(mmap: memmap2::Mmap) -> Self {
         // 遍历文件，标记所有词汇的位置并记录最大长度
         let mut max_piece_len = 0usize;
         let offsets = (0..)
@@ -42,13 +47,19 @@ impl BPE {
             std::str::from_utf8(&slice[1..][..len]).unwrap()
         });
         // 生成分词器
-        Ok(Self {
+        Self {
             mmap,
             offsets,
             sorted_indices,
             max_piece_len: 0,
             byte_pieces: ByteDecoder::new(),
-        })
+        }
+    }
+
+    /// 构造一个流式反分词器，用于逐 token 解码。
+    #[inline]
+    pub fn decode_incremental(&self) -> Detokenizer<'_, Self> {
+        Detokenizer::new(self)
     }
 
     /// 根据词汇查找代码。
@@ -69,9 +80,30 @@ impl BPE {
         std::str::from_utf8(&slice[1..][..len]).unwrap()
     }
 
+    /// 遍历所有特殊词汇，包括控制词、未知词和用户定义的词。
+    pub fn special_tokens_iter(&self) -> impl Iterator<Item = (utok, &str)> + '_ {
+        (0..self.offsets.len() as utok)
+            .filter(|&i| !matches!(self.get_type(i), NORMAL | BYTE))
+            .map(|i| (i, self.get_piece(i)))
+    }
+
+    /// 根据代码查找词汇类型。
+    ///
+    /// 类型字段是可选的，缺省为 [NORMAL]。
+    #[inline]
+    fn get_type(&self, i: utok) -> u8 {
+        let offset = self.offsets[i as usize];
+        let end = offset - 1 + self.mmap[offset - 2] as usize;
+        let len = self.mmap[offset] as usize;
+        match &self.mmap[offset + len + 6..end] {
+            [24, ty, ..] => *ty,
+            _ => NORMAL,
+        }
+    }
+
     /// 根据代码查找合词评分。
     #[inline]
-    fn get_score(&self, i: utok) -> f32 {
+    pub(crate) fn get_score(&self, i: utok) -> f32 {
         let offset = self.offsets[i as usize];
         let slice = &self.mmap[offset..];
         let len = slice[0] as usize;
@@ -80,6 +112,17 @@ impl BPE {
     }
 }
 
+/// 普通词汇。
+pub(crate) const NORMAL: u8 = 1;
+/// 未知词。
+pub(crate) const UNKNOWN: u8 = 2;
+/// 控制词。
+pub(crate) const CONTROL: u8 = 3;
+/// 用户定义的词。
+pub(crate) const USER_DEFINED: u8 = 4;
+/// 单字节词汇。
+pub(crate) const BYTE: u8 = 6;
+
 impl Tokenizer for BPE {
     fn vocab_size(&self) -> usize {
         self.offsets.len()
@@ -90,15 +133,35 @@ impl Tokenizer for BPE {
         self.max_piece_len
     }
 
+    #[inline]
     fn encode(&self, text: &str) -> Vec<utok> {
-        let text = text.replace(' ', "▁"); // FIXME: 从 tokenizer.json 读取 normalizer
+        self.encode_with_offsets(text)
+            .into_iter()
+            .map(|(tok, _)| tok)
+            .collect()
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Vec<(utok, Range<usize>)> {
         let mut tokens = Vec::new();
+        // 每个词在原文中的范围，随合词合并
+        let mut ranges = Vec::new();
 
-        text.chars().map(|c| c.to_string()).for_each(|c| {
-            if let Some(index) = self.find_piece(&c) {
-                tokens.extend([index]);
+        text.char_indices().for_each(|(i, c)| {
+            let range = i..i + c.len_utf8();
+            // FIXME: 从 tokenizer.json 读取 normalizer
+            let piece = if c == ' ' { '▁' } else { c }.to_string();
+            if let Some(index) = self.find_piece(&piece) {
+                tokens.push(index);
+                ranges.push(range);
             } else {
-                tokens.extend(c.bytes().map(|c| c as utok + 3));
+                tokens.extend(piece.bytes().map(|b| b as utok + 3));
+                if piece.len() == range.len() {
+                    ranges.extend(range.map(|j| j..j + 1));
+                } else {
+                    // 规范化改变了字符长度，整个字符归于第一个单字节词汇
+                    ranges.push(range.clone());
+                    ranges.extend((1..piece.len()).map(|_| range.end..range.end));
+                }
             }
         });
 
@@ -111,7 +174,7 @@ impl Tokenizer for BPE {
             .map(|tok| (tok, bpe.get_score(tok)))
         }
 
-        let mut merges = (0..tokens.len() - 1)
+        let mut merges = (0..tokens.len().saturating_sub(1))
             .map(|i| map_pair(self, &tokens, i))
             .collect::<Vec<_>>();
         while let Some((i, (tok, _))) = merges
@@ -122,22 +185,33 @@ impl Tokenizer for BPE {
         {
             tokens[i] = tok;
             tokens.remove(i + 1);
+            ranges[i].end = ranges.remove(i + 1).end;
             merges.remove(i);
             if let Some(i) = i.checked_sub(1) {
                 merges[i] = map_pair(self, &tokens, i);
             }
-            if i + 1 < merges.len() {
+            if i < merges.len() {
                 merges[i] = map_pair(self, &tokens, i);
             }
         }
 
-        tokens
+        tokens.into_iter().zip(ranges).collect()
     }
 
     #[inline]
     fn decode(&self, token: utok) -> &str {
         self.byte_pieces.decode(self.get_piece(token))
     }
+
+    #[inline]
+    fn bos_token(&self) -> Option<utok> {
+        self.find_piece("<s>")
+    }
+
+    #[inline]
+    fn eos_token(&self) -> Option<utok> {
+        self.find_piece("</s>")
+    }
 }
 
 #[test]
@@ -154,6 +228,98 @@ fn read_tokenizer() {
     }
 }
 
+#[test]
+fn test_vocab_iter() {
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    println!("model_dir: {}", model_dir.display());
+
+    if let Ok(bpe) = BPE::from_model_file(model_dir.join("tokenizer.model")) {
+        let mut count = 0;
+        for (i, (tok, piece)) in bpe.vocab_iter().enumerate() {
+            assert_eq!(tok, i as utok);
+            assert_eq!(bpe.decode(tok), piece);
+            count += 1;
+        }
+        assert_eq!(count, bpe.vocab_size());
+
+        for (tok, piece) in bpe.special_tokens_iter() {
+            println!("special token {tok}: {piece}");
+        }
+    }
+}
+
+#[test]
+fn test_special_tokens() {
+    let mut file = Vec::new();
+    for (piece, ty) in [
+        ("<unk>", Some(2)),
+        ("<s>", Some(3)),
+        ("</s>", Some(3)),
+        ("<0x41>", Some(BYTE)),
+        ("a", None),
+        ("b", Some(NORMAL)),
+    ] {
+        let mut content = vec![10, piece.len() as u8];
+        content.extend_from_slice(piece.as_bytes());
+        content.push(21);
+        content.extend_from_slice(&0f32.to_le_bytes());
+        if let Some(ty) = ty {
+            content.extend_from_slice(&[24, ty]);
+        }
+        file.extend_from_slice(&[10, content.len() as u8]);
+        file.extend_from_slice(&content);
+    }
+    let path = std::env::temp_dir().join("transformer-rs-special-tokens.model");
+    std::fs::write(&path, file).unwrap();
+    let bpe = BPE::from_model_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(bpe.vocab_size(), 6);
+    assert_eq!(
+        bpe.special_tokens_iter().collect::<Vec<_>>(),
+        [(0, "<unk>"), (1, "<s>"), (2, "</s>")]
+    );
+    assert_eq!(bpe.vocab_iter().nth(3), Some((3, "A")));
+
+    assert_eq!(bpe.bos_token(), Some(1));
+    assert_eq!(bpe.eos_token(), Some(2));
+    assert_eq!(bpe.encode("ab"), [4, 5]);
+    assert_eq!(bpe.encode_with_options("ab", true, true), [1, 4, 5, 2]);
+    assert_eq!(bpe.encode_with_options("ab", false, true), [4, 5, 2]);
+}
+
+#[test]
+fn test_merge_last_pair() {
+    let mut file = Vec::new();
+    for (piece, score) in [
+        ("<unk>", 0.),
+        ("<s>", 0.),
+        ("</s>", 0.),
+        ("t", 0.),
+        ("h", 0.),
+        ("e", 0.),
+        ("th", -1.),
+        ("the", -2.),
+    ] {
+        let mut content = vec![10, piece.len() as u8];
+        content.extend_from_slice(piece.as_bytes());
+        content.push(21);
+        content.extend_from_slice(&f32::to_le_bytes(score));
+        file.extend_from_slice(&[10, content.len() as u8]);
+        file.extend_from_slice(&content);
+    }
+    let path = std::env::temp_dir().join("transformer-rs-merge-last-pair.model");
+    std::fs::write(&path, file).unwrap();
+    let bpe = BPE::from_model_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // t + h 合并后，右侧的 th + e 是最后一对，也要重新评分
+    assert_eq!(bpe.encode("the"), [7]);
+    assert_eq!(bpe.encode("thet"), [7, 3]);
+}
+
 #[test]
 fn once_upon_a_time() {
     let Some(model_dir) = common::test_model::find() else {
@@ -0,0 +1,240 @@
+use crate::{
+    bpe::{BYTE, CONTROL, NORMAL, UNKNOWN, USER_DEFINED},
+    BPE,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    io::Result,
+    path::Path,
+};
+
+/// 从文本语料训练 bpe 词表的训练器。
+///
+/// 训练结果保存为 [`BPE::from_model_file`] 可读取的 tokenizer.model 格式。
+pub struct BpeTrainer {
+    vocab_size: usize,
+    min_frequency: usize,
+    special_tokens: Vec<String>,
+}
+
+/// 文件中词汇长度以单字节保存，合并产生的词汇不超过此长度。
+pub(crate) const MAX_PIECE_LEN: usize = 64;
+/// 不在词表中的字符，不参与合并。
+const OOV: u32 = u32::MAX;
+
+impl BpeTrainer {
+    /// 训练总数为 `vocab_size` 的词表，包括控制词、单字节词汇和特殊词汇。
+    #[inline]
+    pub fn new(vocab_size: usize) -> Self {
+        Self {
+            vocab_size,
+            min_frequency: 2,
+            special_tokens: Vec::new(),
+        }
+    }
+
+    /// 词对在语料中至少出现 `n` 次才会被合并。
+    #[inline]
+    pub fn min_frequency(mut self, n: usize) -> Self {
+        self.min_frequency = n;
+        self
+    }
+
+    /// 向词表加入用户定义的特殊词汇。
+    #[inline]
+    pub fn special_tokens(mut self, tokens: &[&str]) -> Self {
+        self.special_tokens = tokens.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// 在 `corpus` 上训练词表，保存到 `model_file` 并构造分词器。
+    pub fn train(&self, corpus: &[&str], model_file: impl AsRef<Path>) -> Result<BPE> {
+        fs::write(&model_file, self.build(corpus))?;
+        BPE::from_model_file(model_file)
+    }
+
+    /// 训练词表并生成 tokenizer.model 文件内容。
+    fn build(&self, corpus: &[&str]) -> Vec<u8> {
+        let mut file = Vec::new();
+        // 单字节词汇的序号为字节值加 3，与编码时的回退规则一致
+        push_piece(&mut file, "<unk>", 0., UNKNOWN);
+        push_piece(&mut file, "<s>", 0., CONTROL);
+        push_piece(&mut file, "</s>", 0., CONTROL);
+        for b in 0..=u8::MAX {
+            push_piece(&mut file, &format!("<0x{b:02X}>"), 0., BYTE);
+        }
+        for token in &self.special_tokens {
+            assert!(token.len() <= MAX_PIECE_LEN, "special token too long");
+            push_piece(&mut file, token, 0., USER_DEFINED);
+        }
+        let mut len = 3 + 256 + self.special_tokens.len();
+
+        // 以 ▁ 开头切分单词并统计词频，与编码时一样以 ▁ 表示空格
+        let mut word_count = HashMap::<String, usize>::new();
+        for text in corpus {
+            let text = text.replace(' ', "▁");
+            let mut start = 0;
+            for (i, _) in text.match_indices('▁').chain([(text.len(), "")]) {
+                if i > start {
+                    *word_count.entry(text[start..i].to_string()).or_default() += 1;
+                }
+                start = i;
+            }
+        }
+
+        // 按频率将字符加入词表
+        let mut char_count = HashMap::<char, usize>::new();
+        for (word, n) in &word_count {
+            for c in word.chars() {
+                *char_count.entry(c).or_default() += n;
+            }
+        }
+        let mut chars = char_count.into_iter().collect::<Vec<_>>();
+        chars.sort_unstable_by(|(a, na), (b, nb)| nb.cmp(na).then(a.cmp(b)));
+
+        let mut pieces = Vec::<String>::new();
+        let mut ids = HashMap::<String, u32>::new();
+        for (c, _) in chars.into_iter().take(self.vocab_size.saturating_sub(len)) {
+            let piece = c.to_string();
+            push_piece(&mut file, &piece, 0., NORMAL);
+            ids.insert(piece.clone(), pieces.len() as _);
+            pieces.push(piece);
+            len += 1;
+        }
+
+        let mut words = word_count
+            .into_iter()
+            .map(|(word, n)| {
+                let symbols = word
+                    .chars()
+                    .map(|c| *ids.get(c.encode_utf8(&mut [0; 4]) as &str).unwrap_or(&OOV))
+                    .collect::<Vec<_>>();
+                (symbols, n)
+            })
+            .collect::<Vec<_>>();
+        // 排序使训练结果与哈希顺序无关
+        words.sort_unstable();
+
+        // 统计词对出现次数及其所在的单词
+        let mut pair_count = HashMap::<(u32, u32), usize>::new();
+        let mut pair_words = HashMap::<(u32, u32), HashSet<usize>>::new();
+        for (i, (symbols, n)) in words.iter().enumerate() {
+            for pair in pairs(symbols) {
+                *pair_count.entry(pair).or_default() += n;
+                pair_words.entry(pair).or_default().insert(i);
+            }
+        }
+        let mut heap = pair_count
+            .iter()
+            .map(|(&pair, &n)| (n, Reverse(pair)))
+            .collect::<BinaryHeap<_>>();
+
+        // 反复合并出现最多的词对
+        while len < self.vocab_size {
+            let Some((n, Reverse((a, b)))) = heap.pop() else {
+                break;
+            };
+            if pair_count.get(&(a, b)) != Some(&n) {
+                continue;
+            }
+            if n < self.min_frequency {
+                break;
+            }
+            let piece = format!("{}{}", pieces[a as usize], pieces[b as usize]);
+            if piece.len() > MAX_PIECE_LEN {
+                continue;
+            }
+            let merged = *ids.entry(piece.clone()).or_insert_with(|| {
+                // 越早合并的词对评分越高
+                push_piece(&mut file, &piece, -(len as f32), NORMAL);
+                pieces.push(piece);
+                len += 1;
+                pieces.len() as u32 - 1
+            });
+
+            let mut touched = HashSet::new();
+            for i in pair_words.remove(&(a, b)).unwrap_or_default() {
+                let (symbols, n) = &mut words[i];
+                for pair in pairs(symbols) {
+                    *pair_count.get_mut(&pair).unwrap() -= *n;
+                    touched.insert(pair);
+                }
+                let mut j = 0;
+                while j + 1 < symbols.len() {
+                    if symbols[j] == a && symbols[j + 1] == b {
+                        symbols[j] = merged;
+                        symbols.remove(j + 1);
+                    }
+                    j += 1;
+                }
+                for pair in pairs(symbols) {
+                    *pair_count.entry(pair).or_default() += *n;
+                    pair_words.entry(pair).or_default().insert(i);
+                    touched.insert(pair);
+                }
+            }
+            for pair in touched {
+                match pair_count[&pair] {
+                    0 => {
+                        pair_count.remove(&pair);
+                    }
+                    n => heap.push((n, Reverse(pair))),
+                }
+            }
+        }
+        file
+    }
+}
+
+/// 单词中所有可合并的相邻词对。
+fn pairs(symbols: &[u32]) -> impl Iterator<Item = (u32, u32)> + '_ {
+    symbols
+        .windows(2)
+        .map(|w| (w[0], w[1]))
+        .filter(|&(a, b)| a != OOV && b != OOV)
+}
+
+/// 按 tokenizer.model 格式写入一个词汇。
+pub(crate) fn push_piece(file: &mut Vec<u8>, piece: &str, score: f32, ty: u8) {
+    let mut content = vec![10, piece.len() as u8];
+    content.extend_from_slice(piece.as_bytes());
+    content.push(21);
+    content.extend_from_slice(&score.to_le_bytes());
+    content.extend_from_slice(&[24, ty]);
+    file.extend_from_slice(&[10, content.len() as u8]);
+    file.extend_from_slice(&content);
+}
+
+#[test]
+fn test_train() {
+    use crate::Tokenizer;
+
+    const CORPUS: &[&str] = &[
+        "the quick brown fox jumps over the lazy dog",
+        "the lazy dog sleeps in the sun",
+        "a quick brown dog outpaces the quick red fox",
+        "the fox and the dog are friends",
+    ];
+    let path = std::env::temp_dir().join("transformer-rs-bpe-trainer.model");
+    let bpe = BpeTrainer::new(300)
+        .special_tokens(&["<pad>"])
+        .train(CORPUS, &path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(bpe.vocab_size() > 3 + 256 + 1);
+    assert!(bpe.vocab_size() <= 300);
+    assert_eq!(
+        bpe.special_tokens_iter().collect::<Vec<_>>(),
+        [(0, "<unk>"), (1, "<s>"), (2, "</s>"), (259, "<pad>")]
+    );
+
+    let sentence = "the quick dog";
+    let tokens = bpe.encode(sentence);
+    assert!(tokens.len() < sentence.len());
+    let text = tokens.iter().map(|&t| bpe.decode(t)).collect::<String>();
+    assert_eq!(text, sentence.replace(' ', "▁"));
+    assert_eq!(bpe.encode(&text), tokens);
+}
@@ -0,0 +1,138 @@
+use crate::Tokenizer;
+use common::utok;
+use std::str;
+
+/// 流式反分词器，逐个接收 token 并只输出完整的 utf-8 字符。
+pub struct Detokenizer<'a, T: ?Sized> {
+    tokenizer: &'a T,
+    buffer: Utf8Buffer,
+}
+
+impl<'a, T: Tokenizer + ?Sized> Detokenizer<'a, T> {
+    #[inline]
+    pub fn new(tokenizer: &'a T) -> Self {
+        Self {
+            tokenizer,
+            buffer: Default::default(),
+        }
+    }
+
+    /// 接收一个 token，如果凑齐了完整的字符则返回。
+    #[inline]
+    pub fn push(&mut self, token: utok) -> Option<&str> {
+        self.buffer.push(self.tokenizer.decode(token).as_bytes())
+    }
+
+    /// 强制取出缓存的所有内容，无效的字节替换为 `U+FFFD`。
+    #[inline]
+    pub fn flush(&mut self) -> &str {
+        self.buffer.flush()
+    }
+}
+
+/// utf-8 字节缓冲区，缓存不完整的多字节序列直到凑齐一个字符。
+#[derive(Clone, Default, Debug)]
+pub struct Utf8Buffer {
+    bytes: Vec<u8>,
+    text: String,
+}
+
+impl Utf8Buffer {
+    /// 压入一段字节，返回其中已经完整的文本。
+    pub fn push(&mut self, bytes: impl AsRef<[u8]>) -> Option<&str> {
+        self.text.clear();
+        self.bytes.extend_from_slice(bytes.as_ref());
+
+        let mut start = 0;
+        loop {
+            match str::from_utf8(&self.bytes[start..]) {
+                Ok(s) => {
+                    self.text.push_str(s);
+                    start = self.bytes.len();
+                    break;
+                }
+                Err(e) => {
+                    let (valid, rest) = self.bytes[start..].split_at(e.valid_up_to());
+                    self.text
+                        .push_str(unsafe { str::from_utf8_unchecked(valid) });
+                    start += valid.len();
+                    match e.error_len() {
+                        // 无效的字节序列，替换为 `U+FFFD`
+                        Some(len) => {
+                            self.text.push(char::REPLACEMENT_CHARACTER);
+                            start += len;
+                        }
+                        // 不完整的字节序列，等待后续字节
+                        None => {
+                            debug_assert!(rest.len() < 4);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.bytes.drain(..start);
+
+        if self.text.is_empty() {
+            None
+        } else {
+            Some(&self.text)
+        }
+    }
+
+    /// 取出缓存的所有内容，无效的字节替换为 `U+FFFD`。
+    pub fn flush(&mut self) -> &str {
+        self.text = String::from_utf8_lossy(&self.bytes).into_owned();
+        self.bytes.clear();
+        &self.text
+    }
+}
+
+#[test]
+fn test_utf8_buffer() {
+    const TEXT: &str = "从前有座山，山里有座庙。";
+
+    let mut buffer = Utf8Buffer::default();
+    let mut ans = String::new();
+    for b in TEXT.as_bytes() {
+        if let Some(s) = buffer.push([*b]) {
+            ans.push_str(s);
+        }
+    }
+    assert_eq!(buffer.flush(), "");
+    assert_eq!(ans, TEXT);
+
+    assert_eq!(buffer.push([0xe4, 0xbb]), None);
+    assert_eq!(buffer.flush(), "\u{FFFD}");
+    assert_eq!(buffer.push([0xff, b'a']), Some("\u{FFFD}a"));
+}
+
+#[test]
+fn test_detokenizer() {
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    println!("model_dir: {}", model_dir.display());
+
+    if let Ok(bpe) = crate::BPE::from_model_file(model_dir.join("tokenizer.model")) {
+        const TEXT: &str = "从前有座山，山里有座庙。";
+        let tokens = bpe.encode(TEXT);
+
+        let bytes = tokens
+            .iter()
+            .flat_map(|&t| bpe.decode(t).as_bytes())
+            .copied()
+            .collect::<Vec<_>>();
+        let expected = String::from_utf8(bytes).unwrap();
+
+        let mut detokenizer = bpe.decode_incremental();
+        let mut ans = String::new();
+        for t in tokens {
+            if let Some(s) = detokenizer.push(t) {
+                ans.push_str(s);
+            }
+        }
+        ans.push_str(detokenizer.flush());
+        assert_eq!(ans, expected);
+    }
+}
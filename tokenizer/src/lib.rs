@@ -1,18 +1,96 @@
 mod bpe;
+mod bpe_trainer;
+mod detokenizer;
 mod normalizer;
+mod roundtrip;
+mod sentencepiece;
+mod tokenizer_json;
 mod vocab_txt;
 
 use common::utok;
+use std::ops::Range;
 
 pub trait Tokenizer {
     fn vocab_size(&self) -> usize;
     fn max_piece_len(&self) -> usize;
     fn encode(&self, text: &str) -> Vec<utok>;
     fn decode(&self, token: utok) -> &str;
+
+    /// 解码整个词序列。
+    ///
+    /// 拆分到多个词中的多字节字符凑齐后才输出，末尾不完整或无效的字节替换为 `U+FFFD`。
+    fn decode_batch(&self, tokens: &[utok]) -> String {
+        let mut buffer = Utf8Buffer::default();
+        let mut ans = String::new();
+        for &token in tokens {
+            if let Some(s) = buffer.push(self.decode(token)) {
+                ans.push_str(s);
+            }
+        }
+        ans.push_str(buffer.flush());
+        ans
+    }
+
+    /// 编码文本，同时给出每个词对应的 `text` 中的字节范围。
+    ///
+    /// 范围首尾相接，覆盖整个 `text`。多字节字符拆分成的单字节词汇各自对应其中一个字节。
+    ///
+    /// 缺省实现按每个词解码的长度依次划分 `text`，只在解码结果与原文逐字节对应时准确。
+    fn encode_with_offsets(&self, text: &str) -> Vec<(utok, Range<usize>)> {
+        let mut start = 0;
+        let mut ans = self
+            .encode(text)
+            .into_iter()
+            .map(|token| {
+                let end = (start + self.decode(token).len()).min(text.len());
+                let range = start..end;
+                start = end;
+                (token, range)
+            })
+            .collect::<Vec<_>>();
+        // 最后一个词延伸到文本末尾，保证覆盖整个文本
+        if let Some((_, range)) = ans.last_mut() {
+            range.end = text.len();
+        }
+        ans
+    }
+
+    /// 词表中的句子开始符，缺省为没有。
+    #[inline]
+    fn bos_token(&self) -> Option<utok> {
+        None
+    }
+    /// 词表中的句子结束符，缺省为没有。
+    #[inline]
+    fn eos_token(&self) -> Option<utok> {
+        None
+    }
+
+    /// 编码文本，并按需在前后加入句子开始符和结束符。
+    ///
+    /// [`Tokenizer::encode`] 不加入任何特殊词汇，相当于 `encode_with_options(text, false, false)`。
+    /// 词表中没有对应的特殊词汇时不加入。
+    fn encode_with_options(&self, text: &str, add_bos: bool, add_eos: bool) -> Vec<utok> {
+        let bos = self.bos_token().filter(|_| add_bos);
+        let eos = self.eos_token().filter(|_| add_eos);
+        bos.into_iter()
+            .chain(self.encode(text))
+            .chain(eos)
+            .collect()
+    }
+
+    /// 按序号顺序遍历词表，产生的词汇与 [`Tokenizer::decode`] 一致。
+    fn vocab_iter(&self) -> Box<dyn Iterator<Item = (utok, &str)> + '_> {
+        Box::new((0..self.vocab_size() as utok).map(|i| (i, self.decode(i))))
+    }
 }
 
 pub use bpe::BPE;
+pub use bpe_trainer::BpeTrainer;
+pub use detokenizer::{Detokenizer, Utf8Buffer};
 pub use normalizer::{BPECommonNormalizer, Normalizer};
+pub use roundtrip::{tokenizer_roundtrip_test, RoundTripError};
+pub use tokenizer_json::TokenizerError;
 pub use vocab_txt::VocabTxt;
 
 struct ByteDecoder([u8; 256]);
@@ -36,3 +114,37 @@ impl ByteDecoder {
         }
     }
 }
+
+#[test]
+fn test_default_methods() {
+    /// 只实现必需方法的逐字符分词器。
+    struct Chars;
+
+    impl Tokenizer for Chars {
+        fn vocab_size(&self) -> usize {
+            3
+        }
+        fn max_piece_len(&self) -> usize {
+            1
+        }
+        fn encode(&self, text: &str) -> Vec<utok> {
+            text.bytes().map(|b| (b - b'a') as _).collect()
+        }
+        fn decode(&self, token: utok) -> &str {
+            ["a", "b", "c"][token as usize]
+        }
+    }
+
+    assert_eq!(Chars.bos_token(), None);
+    assert_eq!(Chars.eos_token(), None);
+    assert_eq!(Chars.encode_with_options("abc", true, true), [0, 1, 2]);
+    assert_eq!(
+        Chars.encode_with_offsets("cab"),
+        [(2, 0..1), (0, 1..2), (1, 2..3)]
+    );
+    assert!(Chars.encode_with_offsets("").is_empty());
+    assert_eq!(
+        Chars.vocab_iter().collect::<Vec<_>>(),
+        [(0, "a"), (1, "b"), (2, "c")]
+    );
+}
@@ -1,8 +1,20 @@
-﻿use std::borrow::Cow;
+﻿use rayon::prelude::*;
+use std::borrow::Cow;
 
 pub trait Normalizer {
     fn encode<'a>(&self, text: &'a str) -> Cow<'a, str>;
     fn decode<'a>(&self, text: &'a str) -> Cow<'a, str>;
+
+    /// 依次编码一批文本，结果与逐个调用 [`Normalizer::encode`] 相同。
+    fn encode_batch(&self, texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|t| self.encode(t).into_owned()).collect()
+    }
+
+    /// 规范化一批文本，同 [`Normalizer::encode_batch`]。
+    #[inline]
+    fn normalize_batch(&self, texts: &[&str]) -> Vec<String> {
+        self.encode_batch(texts)
+    }
 }
 
 impl Normalizer for () {
@@ -40,6 +52,14 @@ impl Normalizer for BPECommonNormalizer {
         Cow::Owned(ans)
     }
 
+    #[inline]
+    fn encode_batch(&self, texts: &[&str]) -> Vec<String> {
+        texts
+            .par_iter()
+            .map(|t| self.encode(t).into_owned())
+            .collect()
+    }
+
     #[inline]
     fn decode<'a>(&self, text: &'a str) -> Cow<'a, str> {
         if text.contains('▁') {
@@ -49,3 +69,16 @@ impl Normalizer for BPECommonNormalizer {
         }
     }
 }
+
+#[test]
+fn test_encode_batch() {
+    let texts = ["Hello world", "你好 世界", "", " leading space"];
+    let expected = texts
+        .iter()
+        .map(|t| BPECommonNormalizer.encode(t).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(BPECommonNormalizer.encode_batch(&texts), expected);
+    assert_eq!(BPECommonNormalizer.normalize_batch(&texts), expected);
+    assert_eq!(expected[0], "▁Hello▁world");
+    assert_eq!(().encode_batch(&texts), texts);
+}
@@ -0,0 +1,203 @@
+use crate::Tokenizer;
+use common::utok;
+use std::{error, fmt};
+
+/// 分词往返检查失败的位置。
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RoundTripError {
+    /// 第一个与原文不符的词在编码结果中的序号，等于词数时表示解码结果比原文短。
+    pub index: usize,
+    /// 与原文不符的词，解码结果比原文短时为 `None`。
+    pub token: Option<utok>,
+    /// 这个词在原文中对应的字节偏移。
+    pub offset: usize,
+}
+
+impl error::Error for RoundTripError {}
+impl fmt::Display for RoundTripError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.token {
+            Some(token) => write!(
+                f,
+                "token #{} ({token}) does not match text at byte {}",
+                self.index, self.offset
+            ),
+            None => write!(f, "decoded text ends at byte {}", self.offset),
+        }
+    }
+}
+
+/// 检查 `text` 编码后逐词解码再拼接能否还原为原文。
+///
+/// 比较在规范化之后的空间中进行，传入的 `text` 应已经过 [`Normalizer::encode`](crate::Normalizer::encode)。
+/// 单字节词汇按字节拼接，因此被拆分的多字节字符也能还原。
+pub fn tokenizer_roundtrip_test(
+    tokenizer: &dyn Tokenizer,
+    text: &str,
+) -> Result<(), RoundTripError> {
+    let tokens = tokenizer.encode(text);
+    let text = text.as_bytes();
+    let mut offset = 0;
+    for (index, &token) in tokens.iter().enumerate() {
+        let piece = tokenizer.decode(token).as_bytes();
+        if !text[offset..].starts_with(piece) || piece.is_empty() {
+            return Err(RoundTripError {
+                index,
+                token: Some(token),
+                offset,
+            });
+        }
+        offset += piece.len();
+    }
+    if offset == text.len() {
+        Ok(())
+    } else {
+        Err(RoundTripError {
+            index: tokens.len(),
+            token: None,
+            offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod fixtures {
+    use super::Tokenizer;
+
+    /// 曾导致问题或容易出错的输入。
+    ///
+    /// 孤立的代理项（WTF-8）不是合法的 `str`，在类型层面就被排除，不需要检查。
+    pub const REGRESSIONS: &[&str] = &[
+        "",
+        "\u{FEFF}",
+        "\u{FEFF}▁BOM",
+        "👨\u{200D}👩\u{200D}👧",
+        "e\u{301}▁n\u{303}",
+        "שלום▁עולם",
+        "مرحبا▁بالعالم",
+        "\0\u{7F}\u{80}\u{10FFFF}",
+        "▁▁▁",
+    ];
+
+    /// 以 xorshift 生成可复现的随机 Unicode 字符串，覆盖 ASCII、组合字符、CJK、表情和私用区。
+    pub fn random_strings(n: usize) -> impl Iterator<Item = String> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..n).map(move |_| {
+            let len = next() % 32;
+            (0..len)
+                .map(|_| {
+                    let x = next();
+                    let (base, range) = match x % 6 {
+                        0 => (0x20, 0x5f),
+                        1 => (0x300, 0x70),
+                        2 => (0x4e00, 0x5200),
+                        3 => (0x1f300, 0x300),
+                        4 => (0, 0x80),
+                        _ => (0, 0x11_0000),
+                    };
+                    char::from_u32(base + (x >> 8) as u32 % range).unwrap_or('\u{FFFD}')
+                })
+                .collect()
+        })
+    }
+
+    /// 检查 [`Tokenizer::encode_with_offsets`] 与 [`Tokenizer::encode`] 一致，
+    /// 范围首尾相接覆盖原文，且每个词覆盖的原文字节与其解码结果相同。
+    pub fn check_offsets(tokenizer: &dyn Tokenizer, text: &str) {
+        let tokens = tokenizer.encode_with_offsets(text);
+        assert!(tokens
+            .iter()
+            .map(|(tok, _)| *tok)
+            .eq(tokenizer.encode(text)));
+        let mut end = 0;
+        for (tok, range) in tokens {
+            assert_eq!(range.start, end, "{text:?}");
+            assert_eq!(
+                &text.as_bytes()[range.clone()],
+                tokenizer.decode(tok).as_bytes(),
+                "{text:?}"
+            );
+            end = range.end;
+        }
+        assert_eq!(end, text.len(), "{text:?}");
+    }
+}
+
+#[test]
+fn test_bpe_roundtrip() {
+    use crate::{BPECommonNormalizer, BpeTrainer, Normalizer};
+
+    const CORPUS: &[&str] = &[
+        "the quick brown fox jumps over the lazy dog",
+        "从前有座山，山里有座庙。",
+        "the fox and the dog are friends",
+    ];
+    let path = std::env::temp_dir().join("transformer-rs-roundtrip.model");
+    let bpe = BpeTrainer::new(400).train(CORPUS, &path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let texts = fixtures::REGRESSIONS
+        .iter()
+        .map(|s| s.to_string())
+        .chain(CORPUS.iter().map(|s| s.to_string()))
+        .chain(fixtures::random_strings(1000));
+    for text in texts {
+        let text = BPECommonNormalizer.encode(&text);
+        if let Err(e) = tokenizer_roundtrip_test(&bpe, &text) {
+            panic!("{text:?}: {e}");
+        }
+        fixtures::check_offsets(&bpe, &text);
+        assert_eq!(bpe.decode_batch(&bpe.encode(&text)), text);
+    }
+
+    let text = BPECommonNormalizer.encode("the fox 从前有座山 🦀👋🏽");
+    assert_eq!(bpe.decode_batch(&bpe.encode(&text)), text);
+
+    // 偏移指向规范化之前的原文
+    let text = "the fox";
+    for (tok, range) in bpe.encode_with_offsets(text) {
+        assert_eq!(text[range].replace(' ', "▁"), bpe.decode(tok));
+    }
+}
+
+#[test]
+fn test_vocab_txt_roundtrip() {
+    use crate::VocabTxt;
+    use std::fmt::Write;
+
+    let mut file = String::from("\"<unk>\"\n\"<s>\"\n\"</s>\"\n");
+    for b in 0..=u8::MAX {
+        writeln!(file, "\"<0x{b:02X}>\"").unwrap();
+    }
+    for word in ["the", "▁quick", "▁fox", "从前", "山"] {
+        writeln!(file, "\"{word}\"").unwrap();
+    }
+    let path = std::env::temp_dir().join("transformer-rs-roundtrip.txt");
+    std::fs::write(&path, file).unwrap();
+    let vocab = VocabTxt::from_txt_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let texts = fixtures::REGRESSIONS
+        .iter()
+        .map(|s| s.to_string())
+        .chain(["the▁quick▁fox".into(), "从前有座山".into()])
+        .chain(fixtures::random_strings(1000));
+    for text in texts {
+        if let Err(e) = tokenizer_roundtrip_test(&vocab, &text) {
+            panic!("{text:?}: {e}");
+        }
+        fixtures::check_offsets(&vocab, &text);
+        assert_eq!(vocab.decode_batch(&vocab.encode(&text)), text);
+    }
+
+    // 不符的词被准确定位
+    let e = tokenizer_roundtrip_test(&vocab, "the<0x41>").unwrap_err();
+    assert_eq!(e.index, 1);
+    assert_eq!(e.offset, 3);
+}
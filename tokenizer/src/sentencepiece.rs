@@ -0,0 +1,208 @@
+use crate::{
+    bpe::NORMAL,
+    bpe_trainer::{push_piece, MAX_PIECE_LEN},
+    TokenizerError, BPE,
+};
+use std::{path::Path, str};
+
+/// protobuf 字段的值。
+enum Value<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32([u8; 4]),
+}
+
+/// 逐个读取 protobuf 消息中的字段。
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64, TokenizerError> {
+        let mut ans = 0;
+        for shift in (0..64).step_by(7) {
+            let (&b, rest) = self
+                .0
+                .split_first()
+                .ok_or(TokenizerError::InvalidProto("truncated varint"))?;
+            self.0 = rest;
+            ans |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(ans);
+            }
+        }
+        Err(TokenizerError::InvalidProto("varint too long"))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], TokenizerError> {
+        if len > self.0.len() {
+            return Err(TokenizerError::InvalidProto("truncated field"));
+        }
+        let (ans, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(ans)
+    }
+
+    /// 读取下一个字段的字段号和值，消息结束时返回 `None`。
+    fn next(&mut self) -> Result<Option<(u64, Value<'a>)>, TokenizerError> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.bytes(8)?;
+                Value::Fixed64
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.bytes(len)?)
+            }
+            5 => Value::Fixed32(self.bytes(4)?.try_into().unwrap()),
+            _ => return Err(TokenizerError::InvalidProto("unsupported wire type")),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+impl BPE {
+    /// 打开 sentencepiece 的 tokenizer.model 文件并构造一个 bpe 分词器。
+    ///
+    /// 完整解析文件中以 protobuf 编码的 `ModelProto`，只取出词汇、评分和类型，忽略其他字段。
+    /// [`BPE::from_model_file`] 要求词汇位于文件开头且长度都以单字节编码，这个函数没有这些限制。
+    pub fn from_sentencepiece_proto(path: impl AsRef<Path>) -> Result<Self, TokenizerError> {
+        use TokenizerError::*;
+
+        let proto = std::fs::read(path).map_err(Io)?;
+        // 生成 tokenizer.model 格式的内容
+        let mut file = Vec::new();
+        let mut model = Fields(&proto);
+        while let Some((number, value)) = model.next()? {
+            // ModelProto.pieces = 1
+            let (1, Value::Bytes(piece)) = (number, value) else {
+                continue;
+            };
+            let mut text = "";
+            let mut score = 0.;
+            let mut ty = NORMAL;
+            let mut piece = Fields(piece);
+            while let Some((number, value)) = piece.next()? {
+                match (number, value) {
+                    (1, Value::Bytes(s)) => {
+                        text = str::from_utf8(s).map_err(|_| InvalidProto("piece not utf-8"))?;
+                    }
+                    (2, Value::Fixed32(s)) => score = f32::from_le_bytes(s),
+                    (3, Value::Varint(t)) => ty = t as _,
+                    _ => {}
+                }
+            }
+            if text.len() > MAX_PIECE_LEN {
+                return Err(PieceTooLong(text.into()));
+            }
+            push_piece(&mut file, text, score, ty);
+        }
+        if file.is_empty() {
+            return Err(MissingField("pieces"));
+        }
+        let mut mmap = memmap2::MmapMut::map_anon(file.len()).map_err(Io)?;
+        mmap.copy_from_slice(&file);
+        Ok(Self::from_mmap(mmap.make_read_only().map_err(Io)?))
+    }
+}
+
+#[test]
+fn test_from_sentencepiece_proto() {
+    use crate::{
+        bpe::{BYTE, CONTROL, UNKNOWN},
+        Tokenizer,
+    };
+    use common::utok;
+
+    fn varint(buf: &mut Vec<u8>, mut x: usize) {
+        while x >= 0x80 {
+            buf.push(x as u8 | 0x80);
+            x >>= 7;
+        }
+        buf.push(x as _);
+    }
+    fn bytes(buf: &mut Vec<u8>, number: usize, content: &[u8]) {
+        varint(buf, number << 3 | 2);
+        varint(buf, content.len());
+        buf.extend_from_slice(content);
+    }
+    fn assert_same_vocab(a: &BPE, b: &BPE) {
+        assert_eq!(a.vocab_size(), b.vocab_size());
+        assert!(a.vocab_iter().eq(b.vocab_iter()));
+        for i in 0..a.vocab_size() as utok {
+            assert_eq!(a.get_score(i), b.get_score(i));
+        }
+    }
+
+    // trainer_spec 位于词汇之前，且有长度超过 127 的字段
+    let mut proto = Vec::new();
+    bytes(&mut proto, 2, &[b'x'; 200]);
+    let trainer_spec = proto.len();
+    let mut pieces = vec![
+        ("<unk>", 0., UNKNOWN),
+        ("<s>", 0., CONTROL),
+        ("</s>", 0., CONTROL),
+    ];
+    let byte_pieces = (0..=u8::MAX)
+        .map(|b| format!("<0x{b:02X}>"))
+        .collect::<Vec<_>>();
+    pieces.extend(byte_pieces.iter().map(|s| (s.as_str(), 0., BYTE)));
+    pieces.extend([
+        ("▁", -1., NORMAL),
+        ("t", -2., NORMAL),
+        ("h", -3., NORMAL),
+        ("e", -4., NORMAL),
+        ("th", -5., NORMAL),
+        ("the", -6., NORMAL),
+        ("▁the", -7., NORMAL),
+    ]);
+    for (piece, score, ty) in &pieces {
+        let mut content = Vec::new();
+        bytes(&mut content, 1, piece.as_bytes());
+        content.push(21);
+        content.extend_from_slice(&f32::to_le_bytes(*score));
+        // 缺省类型不写入
+        if *ty != NORMAL {
+            content.extend_from_slice(&[24, *ty]);
+        }
+        bytes(&mut proto, 1, &content);
+    }
+    // normalizer_spec 位于词汇之后
+    bytes(&mut proto, 3, b"identity");
+
+    let path = std::env::temp_dir().join("transformer-rs-sentencepiece.model");
+    std::fs::write(&path, &proto).unwrap();
+    let bpe = BPE::from_sentencepiece_proto(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(bpe.vocab_size(), pieces.len());
+    assert_eq!(bpe.bos_token(), Some(1));
+    assert_eq!(bpe.encode("▁the"), [265]);
+    assert_eq!(bpe.encode("the▁t"), [264, 259, 260]);
+    assert_eq!(bpe.encode("x"), [b'x' as utok + 3]);
+
+    // 词汇位于文件开头时两种方式都能加载，得到相同的词表和评分
+    let path = std::env::temp_dir().join("transformer-rs-sentencepiece-compatible.model");
+    std::fs::write(&path, &proto[trainer_spec..]).unwrap();
+    let from_proto = BPE::from_sentencepiece_proto(&path).unwrap();
+    let from_model = BPE::from_model_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_same_vocab(&from_proto, &from_model);
+
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    let model = model_dir.join("tokenizer.model");
+    if !model.is_file() {
+        return;
+    }
+    let from_proto = BPE::from_sentencepiece_proto(&model).unwrap();
+    let from_model = BPE::from_model_file(&model).unwrap();
+    assert_same_vocab(&from_proto, &from_model);
+    let text = "▁The▁capital▁of▁France▁is▁Paris.";
+    assert_eq!(from_proto.encode(text), from_model.encode(text));
+}
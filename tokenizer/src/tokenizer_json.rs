@@ -0,0 +1,263 @@
+use crate::{
+    bpe::{BYTE, CONTROL, NORMAL, UNKNOWN, USER_DEFINED},
+    bpe_trainer::{push_piece, MAX_PIECE_LEN},
+    BPE,
+};
+use common::utok;
+use std::{collections::HashMap, error, fmt, fs::File, io, path::Path};
+
+/// 加载分词器可能产生的错误。
+#[derive(Debug)]
+pub enum TokenizerError {
+    /// 文件读取错误。
+    Io(io::Error),
+    /// 文件不是有效的 json。
+    Json(serde_json::Error),
+    /// 缺少必需的字段。
+    MissingField(&'static str),
+    /// 不支持的分词模型，只支持以单字节回退的 bpe 模型。
+    UnsupportedModel(String),
+    /// 词表的序号不连续，`expected` 为词汇数量，`actual` 为最大序号加 1。
+    VocabSizeMismatch { expected: usize, actual: usize },
+    /// 词汇过长，无法保存。
+    PieceTooLong(String),
+    /// 单字节词汇 `<0xNN>` 不在序号 `NN + 3` 的位置。
+    MisplacedBytePiece(u8),
+    /// 文件不是有效的 protobuf 编码。
+    InvalidProto(&'static str),
+}
+
+impl error::Error for TokenizerError {}
+impl fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Json(e) => write!(f, "json error: {e}"),
+            Self::MissingField(field) => write!(f, "missing field `{field}`"),
+            Self::UnsupportedModel(ty) => write!(f, "unsupported tokenizer model `{ty}`"),
+            Self::VocabSizeMismatch { expected, actual } => {
+                write!(f, "vocab size mismatch: {expected} pieces, {actual} ids")
+            }
+            Self::PieceTooLong(piece) => write!(f, "piece too long: {piece:?}"),
+            Self::MisplacedBytePiece(b) => {
+                write!(f, "byte piece <0x{b:02X}> is not at id {}", *b as utok + 3)
+            }
+            Self::InvalidProto(msg) => write!(f, "invalid protobuf: {msg}"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TokenizerJson {
+    model: Option<ModelJson>,
+    #[serde(default)]
+    added_tokens: Vec<AddedToken>,
+}
+
+#[derive(serde::Deserialize)]
+struct ModelJson {
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    vocab: Option<HashMap<String, utok>>,
+    merges: Option<Vec<Merge>>,
+    unk_token: Option<String>,
+    #[serde(default)]
+    byte_fallback: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Merge {
+    Str(String),
+    Pair([String; 2]),
+}
+
+#[derive(serde::Deserialize)]
+struct AddedToken {
+    id: utok,
+    content: String,
+    #[serde(default)]
+    special: bool,
+}
+
+impl BPE {
+    /// 打开 HuggingFace 的 tokenizer.json 文件并构造一个 bpe 分词器。
+    ///
+    /// 只支持以单字节回退的 bpe 模型（sentencepiece 导出的格式）。合并规则越靠前，合并产生的词汇评分越高。
+    pub fn from_tokenizer_json(path: impl AsRef<Path>) -> Result<Self, TokenizerError> {
+        use TokenizerError::*;
+
+        let file = File::open(path).map_err(Io)?;
+        let json: TokenizerJson =
+            serde_json::from_reader(io::BufReader::new(file)).map_err(Json)?;
+        let model = json.model.ok_or(MissingField("model"))?;
+        match model.ty.as_deref() {
+            Some("BPE") if model.byte_fallback => {}
+            ty => return Err(UnsupportedModel(ty.unwrap_or_default().into())),
+        }
+        let vocab = model.vocab.ok_or(MissingField("model.vocab"))?;
+        let merges = model.merges.ok_or(MissingField("model.merges"))?;
+        // 按序号排列所有词汇
+        let mut pieces = HashMap::new();
+        for (piece, id) in vocab {
+            pieces.insert(id, (piece, NORMAL));
+        }
+        for token in json.added_tokens {
+            let ty = if Some(&token.content) == model.unk_token.as_ref() {
+                UNKNOWN
+            } else if token.special {
+                CONTROL
+            } else {
+                USER_DEFINED
+            };
+            pieces.insert(token.id, (token.content, ty));
+        }
+        let actual = pieces.keys().max().map_or(0, |&id| id as usize + 1);
+        if actual != pieces.len() {
+            return Err(VocabSizeMismatch {
+                expected: pieces.len(),
+                actual,
+            });
+        }
+        // 编码时以 `b + 3` 回退到单字节词汇，单字节词汇必须按字节值依次排在 3..=258
+        for b in 0..=u8::MAX {
+            match pieces.get(&(b as utok + 3)) {
+                Some((piece, NORMAL)) if *piece == format!("<0x{b:02X}>") => {}
+                _ => return Err(MisplacedBytePiece(b)),
+            }
+        }
+        // 合并规则越靠前评分越高
+        let mut scores = HashMap::new();
+        for (rank, merge) in merges.iter().enumerate() {
+            let merged = match merge {
+                Merge::Str(s) => s.replacen(' ', "", 1),
+                Merge::Pair([a, b]) => format!("{a}{b}"),
+            };
+            scores.entry(merged).or_insert(-(rank as f32));
+        }
+        let min_score = -(merges.len() as f32);
+        // 生成 tokenizer.model 格式的内容
+        let mut file = Vec::new();
+        for id in 0..pieces.len() as utok {
+            let (piece, ty) = &pieces[&id];
+            if piece.len() > MAX_PIECE_LEN {
+                return Err(PieceTooLong(piece.clone()));
+            }
+            let ty = match *ty {
+                NORMAL if is_byte_piece(piece) => BYTE,
+                ty => ty,
+            };
+            let score = match ty {
+                NORMAL if piece.chars().nth(1).is_some() => {
+                    scores.get(piece).copied().unwrap_or(min_score)
+                }
+                _ => 0.,
+            };
+            push_piece(&mut file, piece, score, ty);
+        }
+        let mut mmap = memmap2::MmapMut::map_anon(file.len()).map_err(Io)?;
+        mmap.copy_from_slice(&file);
+        Ok(Self::from_mmap(mmap.make_read_only().map_err(Io)?))
+    }
+}
+
+/// 形如 `<0xAB>` 的单字节词汇。
+fn is_byte_piece(piece: &str) -> bool {
+    piece
+        .strip_prefix("<0x")
+        .and_then(|s| s.strip_suffix('>'))
+        .is_some_and(|s| s.len() == 2 && u8::from_str_radix(s, 16).is_ok())
+}
+
+#[test]
+fn test_from_tokenizer_json() {
+    use crate::Tokenizer;
+
+    let mut vocab = vec!["<unk>".to_string(), "<s>".into(), "</s>".into()];
+    vocab.extend((0..=u8::MAX).map(|b| format!("<0x{b:02X}>")));
+    vocab.extend(["▁", "t", "h", "e", "th", "the", "▁the"].map(String::from));
+    let vocab = vocab
+        .iter()
+        .enumerate()
+        .map(|(i, piece)| format!("{piece:?}:{i}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let json = format!(
+        r#"{{
+            "added_tokens": [
+                {{"id":0,"content":"<unk>","special":true}},
+                {{"id":1,"content":"<s>","special":true}},
+                {{"id":2,"content":"</s>","special":true}}
+            ],
+            "model": {{
+                "type": "BPE",
+                "unk_token": "<unk>",
+                "byte_fallback": true,
+                "vocab": {{{vocab}}},
+                "merges": ["t h", "th e", ["▁", "the"]]
+            }}
+        }}"#
+    );
+    let path = std::env::temp_dir().join("transformer-rs-tokenizer.json");
+    std::fs::write(&path, json).unwrap();
+    let bpe = BPE::from_tokenizer_json(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(bpe.vocab_size(), 3 + 256 + 7);
+    assert_eq!(
+        bpe.special_tokens_iter().collect::<Vec<_>>(),
+        [(0, "<unk>"), (1, "<s>"), (2, "</s>")]
+    );
+    assert_eq!(bpe.bos_token(), Some(1));
+    assert_eq!(bpe.eos_token(), Some(2));
+    // 特殊词汇原样解码
+    assert_eq!([0, 1, 2].map(|t| bpe.decode(t)), ["<unk>", "<s>", "</s>"]);
+    assert_eq!(bpe.encode("▁the"), [265]);
+    assert_eq!(bpe.encode("the"), [264]);
+    // 不在词表中的字符回退到单字节
+    assert_eq!(bpe.encode("x"), [b'x' as utok + 3]);
+    assert_eq!(bpe.decode(b'x' as utok + 3), "x");
+
+    let Some(model_dir) = common::test_model::find() else {
+        return;
+    };
+    let json = model_dir.join("tokenizer.json");
+    let model = model_dir.join("tokenizer.model");
+    if !json.is_file() || !model.is_file() {
+        return;
+    }
+    let from_json = BPE::from_tokenizer_json(json).unwrap();
+    let from_model = BPE::from_model_file(model).unwrap();
+    assert_eq!(from_json.vocab_size(), from_model.vocab_size());
+    let text = "▁The▁capital▁of▁France▁is▁Paris.";
+    assert_eq!(from_json.encode(text), from_model.encode(text));
+}
+
+#[test]
+fn test_merge_rank_score() {
+    use crate::Tokenizer;
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tokenizer.json");
+    let bpe = BPE::from_tokenizer_json(path).unwrap();
+    assert_eq!(bpe.vocab_size(), 3 + 256 + 8);
+    // 合并规则越靠前评分越高，不在合并规则中的词汇评分最低
+    assert_eq!(
+        [264, 263, 265, 266].map(|t| bpe.get_score(t)),
+        [0., -1., -2., -3.]
+    );
+    // "b c" 先于 "a b" 合并
+    assert_eq!(bpe.encode("abc"), [260, 264]);
+    // "a b" 先于 "▁ a" 合并
+    assert_eq!(bpe.encode("▁ab"), [259, 263]);
+
+    // 单字节词汇的位置与回退编码不一致
+    let json = std::fs::read_to_string(path)
+        .unwrap()
+        .replace(r#""<0x00>": 3"#, r#""<0x00>": 4"#)
+        .replace(r#""<0x01>": 4"#, r#""<0x01>": 3"#);
+    let path = std::env::temp_dir().join("transformer-rs-misplaced-bytes.json");
+    std::fs::write(&path, json).unwrap();
+    let bpe = BPE::from_tokenizer_json(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(bpe, Err(TokenizerError::MisplacedBytePiece(0))));
+}
@@ -2,7 +2,7 @@
 use common::utok;
 use memmap2::Mmap;
 use patricia_tree::PatriciaMap;
-use std::{fs::File, io::Result, path::Path};
+use std::{fs::File, io::Result, ops::Range, path::Path};
 
 /// 一个基于朴素词表的分词器。
 pub struct VocabTxt {
@@ -50,18 +50,33 @@ impl Tokenizer for VocabTxt {
         self.max_piece_len
     }
 
-    fn encode(&self, mut text: &str) -> Vec<utok> {
-        let mut tokens = Vec::<utok>::new();
+    #[inline]
+    fn encode(&self, text: &str) -> Vec<utok> {
+        self.encode_with_offsets(text)
+            .into_iter()
+            .map(|(tok, _)| tok)
+            .collect()
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Vec<(utok, Range<usize>)> {
+        let mut tokens = Vec::new();
 
-        while !text.is_empty() {
-            if let Some((pre, tok)) = self.trie.get_longest_common_prefix(text) {
-                tokens.push(*tok);
-                text = &text[pre.len()..];
+        let mut rest = text;
+        while !rest.is_empty() {
+            let start = text.len() - rest.len();
+            if let Some((pre, tok)) = self.trie.get_longest_common_prefix(rest) {
+                tokens.push((*tok, start..start + pre.len()));
+                rest = &rest[pre.len()..];
             } else {
-                let mut chars = text.chars();
+                let mut chars = rest.chars();
                 let char = chars.next().unwrap();
-                tokens.extend(char.to_string().bytes().map(|b| (b + 3) as utok));
-                text = chars.as_str();
+                tokens.extend(
+                    char.to_string()
+                        .bytes()
+                        .enumerate()
+                        .map(|(i, b)| ((b + 3) as utok, start + i..start + i + 1)),
+                );
+                rest = chars.as_str();
             }
         }
 
@@ -72,4 +87,56 @@ impl Tokenizer for VocabTxt {
     fn decode(&self, token: utok) -> &str {
         self.byte_pieces.decode(self.words[token as usize].as_str())
     }
+
+    #[inline]
+    fn bos_token(&self) -> Option<utok> {
+        self.trie.get("<s>").copied()
+    }
+
+    #[inline]
+    fn eos_token(&self) -> Option<utok> {
+        self.trie.get("</s>").copied()
+    }
+
+    fn vocab_iter(&self) -> Box<dyn Iterator<Item = (utok, &str)> + '_> {
+        Box::new(
+            self.words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| (i as utok, self.byte_pieces.decode(w))),
+        )
+    }
+}
+
+#[test]
+fn test_vocab_iter() {
+    let path = std::env::temp_dir().join("transformer-rs-vocab-iter.txt");
+    std::fs::write(&path, "\"<unk>\"\n\"<0x41>\"\n\"hello\"\n\"你好\"\n").unwrap();
+    let vocab = VocabTxt::from_txt_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let vocab_iter = vocab.vocab_iter().collect::<Vec<_>>();
+    assert_eq!(
+        vocab_iter,
+        [(0, "<unk>"), (1, "A"), (2, "hello"), (3, "你好")]
+    );
+    for (tok, piece) in vocab_iter {
+        assert_eq!(vocab.decode(tok), piece);
+    }
+}
+
+#[test]
+fn test_encode_with_options() {
+    let path = std::env::temp_dir().join("transformer-rs-encode-with-options.txt");
+    std::fs::write(&path, "\"<unk>\"\n\"<s>\"\n\"</s>\"\n\"hello\"\n").unwrap();
+    let vocab = VocabTxt::from_txt_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let text = "hello";
+    let tokens = vocab.encode(text);
+    assert_eq!(tokens, [3]);
+    assert_eq!(vocab.encode_with_options(text, false, false), tokens);
+    let with_special = vocab.encode_with_options(text, true, true);
+    assert_eq!(with_special.len(), tokens.len() + 2);
+    assert_eq!(with_special, [1, 3, 2]);
 }
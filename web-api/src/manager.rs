@@ -82,7 +82,13 @@ where
             session.extend(messages.iter().map(|s| s.content.as_str()));
             if session.dialog_pos() % 2 == 1 {
                 info!("{session_id:?} inference started");
-                let mut busy = session.chat();
+                let mut busy = match session.chat() {
+                    Ok(busy) => busy,
+                    Err(e) => {
+                        warn!("{session_id:?} inference rejected with error \"{e}\"");
+                        return;
+                    }
+                };
                 while let Some(s) = busy.decode().await {
                     if let Err(e) = sender.send(s) {
                         warn!("Failed to send piece to {session_id:?} with error \"{e}\"");
@@ -99,14 +105,7 @@ where
             (Some(session_id_str), 0) => {
                 let session_id = SessionId::Permanent(session_id_str);
                 let mut session = self
-                    .pending
-                    .lock()
-                    .unwrap()
-                    .get_or_insert_mut(session_id.clone(), || {
-                        info!("{:?} created", &session_id);
-                        Some(self.service.launch())
-                    })
-                    .take()
+                    .take_or_launch(&session_id)?
                     .ok_or(Error::SessionBusy)?;
 
                 let (sender, receiver) = mpsc::unbounded_channel();
@@ -174,14 +173,7 @@ where
             (None, 0) => {
                 let session_id = SessionId::Temporary(AnonymousSessionId::new());
                 let mut session = self
-                    .pending
-                    .lock()
-                    .unwrap()
-                    .get_or_insert_mut(session_id.clone(), || {
-                        info!("{:?} created", &session_id);
-                        Some(self.service.launch())
-                    })
-                    .take()
+                    .take_or_launch(&session_id)?
                     .ok_or(Error::SessionNotFound)?;
                 let (sender, receiver) = mpsc::unbounded_channel();
                 let self_ = self.clone();
@@ -209,6 +201,17 @@ where
         }
     }
 
+    /// 取出会话，会话不存在时从服务启动一个新会话。
+    fn take_or_launch(&self, session_id: &SessionId) -> Result<Option<Session<M>>, Error> {
+        let mut pending = self.pending.lock().unwrap();
+        if !pending.contains(session_id) {
+            let session = self.service.launch().map_err(|_| Error::ShuttingDown)?;
+            info!("{session_id:?} created");
+            pending.put(session_id.clone(), Some(session));
+        }
+        Ok(pending.get_mut(session_id).unwrap().take())
+    }
+
     #[inline]
     fn restore(&self, session_id: &SessionId, session: Session<M>) {
         if let Some(option) = self.pending.lock().unwrap().get_mut(session_id) {
@@ -53,6 +53,7 @@ pub(crate) enum Error {
     SessionNotFound,
     WrongJson(serde_json::Error),
     InvalidDialogPos(usize),
+    ShuttingDown,
 }
 
 #[derive(serde::Serialize)]
@@ -71,6 +72,7 @@ impl Error {
             Self::SessionDuplicate => StatusCode::CONFLICT,
             Self::WrongJson(_) => StatusCode::BAD_REQUEST,
             Self::InvalidDialogPos(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 
@@ -96,6 +98,7 @@ impl Error {
             Self::SessionBusy => json(error!(0, "Session is busy")),
             Self::SessionDuplicate => json(error!(0, "Session ID already exists")),
             Self::WrongJson(e) => json(error!(0, e.to_string())),
+            Self::ShuttingDown => json(error!(0, "Service is shutting down")),
             &Self::InvalidDialogPos(current_dialog_pos) => {
                 #[derive(serde::Serialize)]
                 struct ErrorBodyExtra {
@@ -84,7 +84,8 @@ impl<M: CausalLM> Chatting<M> {
                     );
                     self.current = id;
                 } else {
-                    self.sessions.insert(self.current, self.service.launch());
+                    self.sessions
+                        .insert(self.current, self.service.launch().unwrap());
                     println!("Create new session {}.", self.current);
                     while self.sessions.contains_key(&self.next_id) {
                         self.next_id += 1;
@@ -145,7 +146,8 @@ impl<M: CausalLM> Chatting<M> {
             ["/create"] => {
                 self.current = self.next_id;
                 self.next_id += 1;
-                self.sessions.insert(self.current, self.service.launch());
+                self.sessions
+                    .insert(self.current, self.service.launch().unwrap());
                 println!("Create new session {}.", self.current);
             }
             ["/fork"] => {
@@ -228,7 +230,7 @@ impl<M: CausalLM> Chatting<M> {
         print_now!("{}", "AI: ".green());
         let session = self.session_mut();
         session.extend([text]);
-        let mut busy = session.chat();
+        let mut busy = session.chat().unwrap();
         while let Some(s) = busy.decode().await {
             match &*s {
                 "\\n" => println!(),
@@ -39,7 +39,9 @@ impl Task for GenerateArgs {
 
         let max_steps = self.max_steps.unwrap_or(usize::MAX);
         let mut steps = 0;
-        let mut generator = service.generate(&*prompt, Some(self.inference.sample_args()));
+        let mut generator = service
+            .generate(&*prompt, Some(self.inference.sample_args()))
+            .unwrap();
 
         let time = Instant::now();
         while let Some(s) = generator.decode().await {
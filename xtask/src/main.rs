@@ -69,6 +69,15 @@ struct InferenceArgs {
     /// Random sample top-p.
     #[clap(long)]
     top_p: Option<f32>,
+    /// Random sample min-p, relative to the probability of the most likely token.
+    #[clap(long)]
+    min_p: Option<f32>,
+    /// Repetition penalty, 1 for no penalty.
+    #[clap(long)]
+    repetition_penalty: Option<f32>,
+    /// Random seed for reproducible sampling.
+    #[clap(long)]
+    seed: Option<u64>,
 
     #[cfg(detected_cuda)]
     /// Use Nvidia GPU, specify device IDs separated by comma, e.g. `0` or `0,1`.
@@ -163,11 +172,23 @@ impl InferenceArgs {
 
     #[inline]
     fn sample_args(&self) -> SampleArgs {
-        SampleArgs {
+        let args = SampleArgs {
             temperature: self.temperature.unwrap_or(0.),
             top_k: self.top_k.unwrap_or(usize::MAX),
             top_p: self.top_p.unwrap_or(1.),
+            min_p: self.min_p.unwrap_or(0.),
+            min_tokens_to_keep: 1,
+            repetition_penalty: self.repetition_penalty.unwrap_or(1.),
+            seed: self.seed,
+            logit_bias: Default::default(),
+            strategy: Default::default(),
+            beam_width: 1,
+            beam_max_tokens: 256,
+        };
+        if let Err(e) = args.validate() {
+            panic!("{e}");
         }
+        args
     }
 }
 